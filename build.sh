@@ -19,12 +19,10 @@ cargo build -r
 echo "Copying bundled files to ./build/<target>/"
 
 cp $HOME/.cargo/target/release/potato_auth.exe ./build/windows/
-cp -r ./static/ ./build/windows/
 cp -r './run scripts/' ./build/windows/
 cp ./potato_auth.nginx.conf ./build/windows/
 
 cp $HOME/.cargo/target/release/potato_auth ./build/linux/
-cp -r ./static/ ./build/linux/
 cp -r './run scripts/' ./build/linux/
 cp ./potato_auth.nginx.conf ./build/linux/
 
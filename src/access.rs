@@ -0,0 +1,711 @@
+/*
+Rules that limit where and when a path may be accessed. Network rules are
+checked by /authrequest before the session, schedules after the user's own
+path patterns.
+*/
+
+use std::{collections::HashMap, net::IpAddr, sync::RwLock};
+
+use actix_web::HttpRequest;
+use chrono::{DateTime, Datelike, Duration, Utc};
+use ipnet::IpNet;
+
+use crate::{
+    config::{NetworkConfig, PublicRule, Schedule, ScheduleRule, UserConfig},
+    i18n::Message,
+    shared_data::Sharable,
+    userdb::User,
+    Error, Result,
+};
+
+/// Connection data of requests that came over a unix socket whose clients are
+/// trusted to forward addresses
+pub struct ProxySocket;
+
+/// Outcome of checking a client address against the network rules
+#[derive(Debug, PartialEq)]
+pub enum NetworkAccess {
+    Denied,
+    /// Allowed without logging in
+    Trusted,
+    RequireLogin,
+}
+
+/// Source of the current time so schedules can be tested
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        return Utc::now();
+    }
+}
+
+impl Schedule {
+    /// Checks if any window of the schedule contains time
+    pub fn allows(&self, time: DateTime<Utc>) -> bool {
+        let local = time.with_timezone(&self.timezone);
+        let day = local.weekday();
+        let yesterday = (local - Duration::days(1)).weekday();
+        let t = local.time();
+
+        for window in &self.windows {
+            let open = if window.start <= window.end {
+                window.days.contains(&day) && window.start <= t && t < window.end
+            } else {
+                // Window crosses midnight and belongs to the day it starts on
+                (window.days.contains(&day) && t >= window.start)
+                    || (window.days.contains(&yesterday) && t < window.end)
+            };
+            if open {
+                return true;
+            }
+        }
+        return false;
+    }
+}
+
+struct PathSchedule {
+    pattern: glob::Pattern,
+    schedule: String,
+}
+
+/// Parsed address lists. Rules without a pattern apply to every path.
+struct NetworkRule {
+    pattern: Option<glob::Pattern>,
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+    trusted: Vec<IpNet>,
+}
+
+impl NetworkRule {
+    fn applies_to(&self, path: &str) -> bool {
+        return match &self.pattern {
+            Some(p) => p.matches(path),
+            None => true,
+        };
+    }
+}
+
+fn contains(nets: &Vec<IpNet>, addr: Option<IpAddr>) -> bool {
+    return match addr {
+        Some(a) => nets.iter().any(|n| n.contains(&a)),
+        None => false,
+    };
+}
+
+/// Parses CIDR blocks and single addresses
+fn parse_nets(list: &Vec<String>) -> Result<Vec<IpNet>> {
+    let mut nets = Vec::new();
+    for s in list {
+        match s.parse::<IpNet>() {
+            Ok(n) => nets.push(n),
+            Err(_) => match s.parse::<IpAddr>() {
+                Ok(a) => nets.push(IpNet::from(a)),
+                Err(_) => return Err(Error::invalid(Message::new("rule.invalid_network").arg("address", s))),
+            },
+        }
+    }
+    return Ok(nets);
+}
+
+struct CompiledPublicRule {
+    rule: PublicRule,
+    host: glob::Pattern,
+    path: glob::Pattern,
+}
+
+impl CompiledPublicRule {
+    fn new(rule: PublicRule) -> Result<Self> {
+        let compile = |field: &'static str, s: &String| match glob::Pattern::new(s) {
+            Ok(p) => Ok(p),
+            Err(e) => Err(Error::invalid(
+                Message::new("glob.invalid")
+                    .field(field)
+                    .arg("pattern", s)
+                    .arg("error", e),
+            )),
+        };
+        let host = match compile("host", &rule.host) {
+            Ok(h) => h,
+            Err(e) => return Err(e),
+        };
+        let path = match compile("path", &rule.path) {
+            Ok(p) => p,
+            Err(e) => return Err(e),
+        };
+        return Ok(CompiledPublicRule { rule, host, path });
+    }
+
+    fn matches(&self, host: &str, method: &str, path: &str) -> bool {
+        return self.host.matches(host)
+            && self.path.matches(path)
+            && (self.rule.methods.is_empty() || self.rule.methods.iter().any(|m| m.eq_ignore_ascii_case(method)));
+    }
+}
+
+pub struct AccessControl {
+    schedules: HashMap<String, Schedule>,
+    path_schedules: Vec<PathSchedule>,
+    trusted_proxies: Vec<IpNet>,
+    network_rules: Vec<NetworkRule>,
+    public_rules: Vec<CompiledPublicRule>,
+    /// Config file that public rule changes are saved to
+    cfg_path: Option<String>,
+    clock: Box<dyn Clock>,
+}
+
+impl AccessControl {
+    pub fn new(cfg: &UserConfig) -> Result<Self> {
+        return Self::with_clock(cfg, Box::new(SystemClock));
+    }
+
+    pub fn with_clock(cfg: &UserConfig, clock: Box<dyn Clock>) -> Result<Self> {
+        let schedules = cfg.schedules.clone().unwrap_or_default();
+
+        let mut path_schedules = Vec::new();
+        for rule in cfg.path_schedules.iter().flatten() {
+            match Self::parse_rule(rule, &schedules) {
+                Ok(p) => path_schedules.push(p),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let network = cfg.network.clone().unwrap_or_default();
+        let trusted_proxies = match parse_nets(&network.trusted_proxies) {
+            Ok(n) => n,
+            Err(e) => return Err(e),
+        };
+        let network_rules = match Self::parse_network_rules(&network) {
+            Ok(r) => r,
+            Err(e) => return Err(e),
+        };
+
+        let mut public_rules = Vec::new();
+        for rule in cfg.public_rules.iter().flatten() {
+            match CompiledPublicRule::new(rule.to_owned()) {
+                Ok(r) => public_rules.push(r),
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok(AccessControl {
+            schedules,
+            path_schedules,
+            trusted_proxies,
+            network_rules,
+            public_rules,
+            cfg_path: cfg.cfg_path.to_owned(),
+            clock,
+        });
+    }
+
+    /// Checks if a request may be approved without a session
+    pub fn is_public(&self, host: &str, method: &str, path: &str) -> bool {
+        return self.public_rules.iter().any(|r| r.matches(host, method, path));
+    }
+
+    pub fn public_rules(&self) -> Vec<PublicRule> {
+        return self.public_rules.iter().map(|r| r.rule.to_owned()).collect();
+    }
+
+    /// Saves the rule to the config file, then starts using it
+    pub fn add_public_rule(&mut self, rule: PublicRule) -> Result<()> {
+        let compiled = match CompiledPublicRule::new(rule.clone()) {
+            Ok(r) => r,
+            Err(e) => return Err(e),
+        };
+        let mut rules = self.public_rules();
+        rules.push(rule);
+        match self.save_public_rules(rules) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        self.public_rules.push(compiled);
+        return Ok(());
+    }
+
+    /// Removes the rule from the config file, then stops using it
+    pub fn remove_public_rule(&mut self, index: usize) -> Result<PublicRule> {
+        if index >= self.public_rules.len() {
+            return Err(Error::NotFound(
+                Message::new("rule.not_found").field("index").arg("index", index),
+            ));
+        }
+        let mut rules = self.public_rules();
+        rules.remove(index);
+        match self.save_public_rules(rules) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        return Ok(self.public_rules.remove(index).rule);
+    }
+
+    fn save_public_rules(&self, rules: Vec<PublicRule>) -> Result<()> {
+        let path = match &self.cfg_path {
+            Some(p) => p,
+            None => return Err(Error::Conflict(Message::new("rule.no_config"))),
+        };
+        return UserConfig::edit_file(path, |c| c.public_rules = Some(rules));
+    }
+
+    /// Replaces every rule with those of other, which was built from a
+    /// reloaded config. The clock is kept.
+    pub fn replace_rules(&mut self, mut other: AccessControl) {
+        std::mem::swap(&mut self.clock, &mut other.clock);
+        *self = other;
+    }
+
+    fn parse_network_rules(cfg: &NetworkConfig) -> Result<Vec<NetworkRule>> {
+        let mut rules = Vec::new();
+
+        let global = [(None, &cfg.allow, &cfg.deny, &cfg.trusted_networks)];
+        let per_path = cfg
+            .path_rules
+            .iter()
+            .map(|r| (Some(&r.path), &r.allow, &r.deny, &r.trusted_networks));
+
+        for (path, allow, deny, trusted) in global.into_iter().chain(per_path) {
+            let pattern = match path {
+                Some(p) => match glob::Pattern::new(p) {
+                    Ok(pattern) => Some(pattern),
+                    Err(e) => {
+                        return Err(Error::invalid(
+                            Message::new("glob.invalid")
+                                .field("path")
+                                .arg("pattern", p)
+                                .arg("error", e),
+                        ))
+                    }
+                },
+                None => None,
+            };
+
+            let (allow, deny, trusted) = match (parse_nets(allow), parse_nets(deny), parse_nets(trusted)) {
+                (Ok(a), Ok(d), Ok(t)) => (a, d, t),
+                (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return Err(e),
+            };
+
+            if allow.is_empty() && deny.is_empty() && trusted.is_empty() {
+                continue;
+            }
+            rules.push(NetworkRule {
+                pattern,
+                allow,
+                deny,
+                trusted,
+            });
+        }
+        return Ok(rules);
+    }
+
+    /// Finds the address of the client that made req. Forwarding headers are
+    /// only used when the request comes from a trusted proxy, and the
+    /// X-Forwarded-For chain is followed back until the first address that
+    /// is not a trusted proxy. Requests over a unix socket have no address,
+    /// and are only trusted on sockets marked as `trusted_proxy`.
+    pub fn client_addr(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr().map(|a| a.ip());
+        let trusted = match peer {
+            Some(_) => contains(&self.trusted_proxies, peer),
+            None => req.conn_data::<ProxySocket>().is_some(),
+        };
+        if !trusted {
+            return peer;
+        }
+
+        let header = |name: &str| req.headers().get(name).and_then(|h| h.to_str().ok());
+
+        match header("X-Forwarded-For") {
+            Some(chain) => {
+                let mut client = peer;
+                for hop in chain.rsplit(',') {
+                    match hop.trim().parse::<IpAddr>() {
+                        Ok(addr) => client = Some(addr),
+                        Err(_) => break,
+                    }
+                    if !contains(&self.trusted_proxies, client) {
+                        break;
+                    }
+                }
+                return client;
+            }
+            None => {}
+        }
+
+        return match header("X-Real-IP").and_then(|h| h.trim().parse::<IpAddr>().ok()) {
+            Some(addr) => Some(addr),
+            None => peer,
+        };
+    }
+
+    /// Checks addr against the global lists and those of every path rule
+    /// that matches path. A deny entry always wins, and a non-empty allow
+    /// list must contain addr.
+    pub fn network_access(&self, addr: Option<IpAddr>, path: &str) -> NetworkAccess {
+        let mut trusted = false;
+        for rule in self.network_rules.iter().filter(|r| r.applies_to(path)) {
+            if contains(&rule.deny, addr) {
+                return NetworkAccess::Denied;
+            }
+            if !rule.allow.is_empty() && !contains(&rule.allow, addr) {
+                return NetworkAccess::Denied;
+            }
+            trusted |= contains(&rule.trusted, addr);
+        }
+
+        if trusted {
+            return NetworkAccess::Trusted;
+        }
+        return NetworkAccess::RequireLogin;
+    }
+
+    fn parse_rule(rule: &ScheduleRule, schedules: &HashMap<String, Schedule>) -> Result<PathSchedule> {
+        if !schedules.contains_key(&rule.schedule) {
+            return crate::err!("Path rule `{}` uses unknown schedule `{}`", rule.path, rule.schedule);
+        }
+        return match glob::Pattern::new(&rule.path) {
+            Ok(pattern) => Ok(PathSchedule {
+                pattern,
+                schedule: rule.schedule.to_owned(),
+            }),
+            Err(e) => Err(Error::invalid(
+                Message::new("glob.invalid")
+                    .field("path")
+                    .arg("pattern", &rule.path)
+                    .arg("error", e),
+            )),
+        };
+    }
+
+    pub fn has_schedule(&self, name: &str) -> bool {
+        return self.schedules.contains_key(name);
+    }
+
+    /// Checks the user's schedule and every schedule with a path rule that
+    /// matches path. Unknown schedules never allow access.
+    pub fn allowed_now(&self, user: &User, path: &str) -> bool {
+        let now = self.clock.now();
+
+        let mut required: Vec<&String> = Vec::new();
+        match user.get_schedule() {
+            Some(s) => required.push(s),
+            None => {}
+        }
+        for rule in &self.path_schedules {
+            if rule.pattern.matches(path) {
+                required.push(&rule.schedule);
+            }
+        }
+
+        for name in required {
+            match self.schedules.get(name) {
+                Some(s) if s.allows(now) => {}
+                Some(_) => return false,
+                None => {
+                    tracing::warn!("{} has unknown schedule `{}`", user.get_name(), name);
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+}
+
+impl Sharable for AccessControl {
+    type Shared = RwLock<AccessControl>;
+
+    fn to_sharable(self) -> actix_web::web::Data<Self::Shared> {
+        return actix_web::web::Data::new(RwLock::new(self));
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use actix_web::test::TestRequest;
+    use chrono::{NaiveTime, TimeZone, Weekday};
+
+    use super::*;
+    use crate::{
+        config::{AccessWindow, NetworkRule},
+        userdb::AcctType,
+    };
+
+    pub struct FixedClock(pub DateTime<Utc>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> DateTime<Utc> {
+            return self.0;
+        }
+    }
+
+    /// Weekdays 15:00-20:00 and a Friday night window until 01:00, in New York
+    fn kids_schedule() -> Schedule {
+        let t = |s: &str| NaiveTime::parse_from_str(s, "%H:%M").unwrap();
+        return Schedule {
+            timezone: chrono_tz::America::New_York,
+            windows: vec![
+                AccessWindow {
+                    days: vec![Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri],
+                    start: t("15:00"),
+                    end: t("20:00"),
+                },
+                AccessWindow {
+                    days: vec![Weekday::Fri],
+                    start: t("22:00"),
+                    end: t("01:00"),
+                },
+            ],
+        };
+    }
+
+    #[test]
+    fn schedule_windows() {
+        let s = kids_schedule();
+        // 2024-06-07 is a Friday; New York is UTC-4 in June
+        let at = |d: u32, h: u32, m: u32| Utc.with_ymd_and_hms(2024, 6, d, h, m, 0).unwrap();
+
+        assert!(!s.allows(at(7, 18, 59))); // Fri 14:59
+        assert!(s.allows(at(7, 19, 0))); // Fri 15:00
+        assert!(!s.allows(at(8, 0, 0))); // Fri 20:00
+        assert!(s.allows(at(8, 3, 30))); // Fri 23:30
+        assert!(s.allows(at(8, 4, 59))); // Sat 00:59
+        assert!(!s.allows(at(8, 5, 0))); // Sat 01:00
+        assert!(!s.allows(at(8, 20, 0))); // Sat 16:00
+    }
+
+    #[test]
+    fn allowed_now() {
+        let mut cfg = UserConfig::default();
+        cfg.schedules = Some(HashMap::from([("kids".to_string(), kids_schedule())]));
+        cfg.path_schedules = Some(vec![ScheduleRule {
+            path: "/games/**".to_string(),
+            schedule: "kids".to_string(),
+        }]);
+
+        let mut user = User::new(
+            &"kid".to_string(),
+            &"!".to_string(),
+            &vec!["*".to_string()],
+            AcctType::User,
+        )
+        .unwrap();
+
+        // Saturday 16:00 in New York
+        let closed = AccessControl::with_clock(
+            &cfg,
+            Box::new(FixedClock(Utc.with_ymd_and_hms(2024, 6, 8, 20, 0, 0).unwrap())),
+        )
+        .unwrap();
+        // Monday 16:00 in New York
+        let open = AccessControl::with_clock(
+            &cfg,
+            Box::new(FixedClock(Utc.with_ymd_and_hms(2024, 6, 10, 20, 0, 0).unwrap())),
+        )
+        .unwrap();
+
+        assert!(closed.allowed_now(&user, "/movies"));
+        assert!(!closed.allowed_now(&user, "/games/chess"));
+        assert!(open.allowed_now(&user, "/games/chess"));
+
+        user.set_schedule(Some("kids".to_string()));
+        assert!(!closed.allowed_now(&user, "/movies"));
+        assert!(open.allowed_now(&user, "/movies"));
+
+        user.set_schedule(Some("missing".to_string()));
+        assert!(!open.allowed_now(&user, "/movies"));
+
+        cfg.path_schedules = Some(vec![ScheduleRule {
+            path: "/games/**".to_string(),
+            schedule: "missing".to_string(),
+        }]);
+        assert!(AccessControl::new(&cfg).is_err());
+
+        cfg.path_schedules = Some(vec![ScheduleRule {
+            path: "/games/[".to_string(),
+            schedule: "kids".to_string(),
+        }]);
+        assert!(matches!(AccessControl::new(&cfg), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn client_addr() {
+        let mut cfg = UserConfig::default();
+        cfg.network = Some(NetworkConfig {
+            trusted_proxies: vec!["127.0.0.1".to_string(), "10.1.0.0/16".to_string()],
+            ..Default::default()
+        });
+        let access = AccessControl::new(&cfg).unwrap();
+        let req = |peer: &str, headers: &[(&str, &str)]| {
+            let mut r = TestRequest::default().peer_addr(peer.parse().unwrap());
+            for h in headers {
+                r = r.insert_header(*h);
+            }
+            return r.to_http_request();
+        };
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        // Headers from untrusted peers are ignored
+        assert_eq!(
+            access.client_addr(&req("192.0.2.1:1000", &[("X-Real-IP", "10.0.0.1")])),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            access.client_addr(&req("127.0.0.1:1000", &[("X-Real-IP", "192.0.2.7")])),
+            ip("192.0.2.7")
+        );
+        assert_eq!(access.client_addr(&req("127.0.0.1:1000", &[])), ip("127.0.0.1"));
+
+        // Spoofed entries before the last untrusted hop are not used
+        assert_eq!(
+            access.client_addr(&req(
+                "127.0.0.1:1000",
+                &[
+                    ("X-Forwarded-For", "10.0.0.1, 192.0.2.7, 10.1.2.3"),
+                    ("X-Real-IP", "10.1.2.3")
+                ]
+            )),
+            ip("192.0.2.7")
+        );
+        assert_eq!(
+            access.client_addr(&req("127.0.0.1:1000", &[("X-Forwarded-For", "garbage, 10.1.2.3")])),
+            ip("10.1.2.3")
+        );
+
+        // Requests over a unix socket have no peer address, and their headers
+        // are only used on proxy sockets (see app::tests::server_multiple_listeners)
+        let unix = TestRequest::default().insert_header(("X-Real-IP", "192.0.2.7"));
+        assert_eq!(access.client_addr(&unix.to_http_request()), None);
+        assert_eq!(access.client_addr(&TestRequest::default().to_http_request()), None);
+    }
+
+    #[test]
+    fn network_access() {
+        let mut cfg = UserConfig::default();
+        cfg.network = Some(NetworkConfig {
+            deny: vec!["203.0.113.0/24".to_string()],
+            trusted_networks: vec!["192.168.1.0/24".to_string()],
+            path_rules: vec![
+                NetworkRule {
+                    path: "/lan/**".to_string(),
+                    allow: vec!["192.168.0.0/16".to_string(), "fd00::/8".to_string()],
+                    ..Default::default()
+                },
+                NetworkRule {
+                    path: "/private/**".to_string(),
+                    deny: vec!["192.168.1.50".to_string()],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        });
+        let access = AccessControl::new(&cfg).unwrap();
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        assert_eq!(
+            access.network_access(ip("198.51.100.1"), "/movies"),
+            NetworkAccess::RequireLogin
+        );
+        assert_eq!(
+            access.network_access(ip("203.0.113.9"), "/movies"),
+            NetworkAccess::Denied
+        );
+        assert_eq!(
+            access.network_access(ip("192.168.1.2"), "/movies"),
+            NetworkAccess::Trusted
+        );
+
+        assert_eq!(
+            access.network_access(ip("198.51.100.1"), "/lan/nas"),
+            NetworkAccess::Denied
+        );
+        assert_eq!(
+            access.network_access(ip("192.168.5.5"), "/lan/nas"),
+            NetworkAccess::RequireLogin
+        );
+        assert_eq!(
+            access.network_access(ip("fd00::1"), "/lan/nas"),
+            NetworkAccess::RequireLogin
+        );
+        assert_eq!(access.network_access(None, "/lan/nas"), NetworkAccess::Denied);
+
+        assert_eq!(
+            access.network_access(ip("192.168.1.50"), "/private/a"),
+            NetworkAccess::Denied
+        );
+        assert_eq!(
+            access.network_access(ip("192.168.1.51"), "/private/a"),
+            NetworkAccess::Trusted
+        );
+
+        cfg.network.as_mut().unwrap().deny = vec!["not an address".to_string()];
+        assert!(AccessControl::new(&cfg).is_err());
+
+        cfg.network.as_mut().unwrap().deny = Vec::new();
+        cfg.network.as_mut().unwrap().path_rules[0].path = "/lan/[".to_string();
+        assert!(matches!(AccessControl::new(&cfg), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn public_rules() {
+        let mut cfg = UserConfig::default();
+        cfg.cfg_path = Some(crate::test_utils::make_tmp_file());
+        std::fs::remove_file(cfg.cfg_path.as_ref().unwrap()).unwrap();
+        cfg.public_rules = Some(vec![
+            PublicRule {
+                path: "/.well-known/**".to_string(),
+                ..Default::default()
+            },
+            PublicRule {
+                host: "status.example.com".to_string(),
+                path: "/*".to_string(),
+                methods: vec!["GET".to_string(), "HEAD".to_string()],
+            },
+        ]);
+        let mut access = AccessControl::new(&cfg).unwrap();
+
+        assert!(access.is_public("example.com", "POST", "/.well-known/acme-challenge/abc"));
+        assert!(access.is_public("status.example.com", "get", "/index.html"));
+        assert!(!access.is_public("status.example.com", "POST", "/index.html"));
+        assert!(!access.is_public("example.com", "GET", "/index.html"));
+
+        assert!(access
+            .add_public_rule(PublicRule {
+                path: "/favicon.ico".to_string(),
+                ..Default::default()
+            })
+            .is_ok());
+        assert!(access.is_public("example.com", "GET", "/favicon.ico"));
+        assert_eq!(access.remove_public_rule(0).unwrap().path, "/.well-known/**");
+        assert!(access.remove_public_rule(5).is_err());
+
+        let saved = UserConfig::new(&vec!["_".to_string(), "-c".to_string(), cfg.cfg_path.unwrap()]).unwrap();
+        assert_eq!(saved.public_rules.unwrap(), access.public_rules());
+    }
+
+    #[test]
+    fn public_rules_unsaved() {
+        // Without a config file nothing can be saved, so nothing may change
+        let mut cfg = UserConfig::default();
+        cfg.cfg_path = None;
+        cfg.public_rules = Some(vec![PublicRule {
+            path: "/.well-known/**".to_string(),
+            ..Default::default()
+        }]);
+        let mut access = AccessControl::new(&cfg).unwrap();
+
+        let favicon = PublicRule {
+            path: "/favicon.ico".to_string(),
+            ..Default::default()
+        };
+        assert!(access.add_public_rule(favicon).is_err());
+        assert!(!access.is_public("example.com", "GET", "/favicon.ico"));
+
+        assert!(access.remove_public_rule(0).is_err());
+        assert!(access.is_public("example.com", "GET", "/.well-known/acme-challenge/abc"));
+        assert_eq!(access.public_rules(), cfg.public_rules.unwrap());
+    }
+}
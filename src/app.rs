@@ -1,17 +1,123 @@
+use crate::access::{AccessControl, ProxySocket};
+use crate::assets::Assets;
 use crate::config::UserConfig;
 
+use crate::ldap::LdapAuthenticator;
+use crate::oidc::OidcClient;
+use crate::reload::Reloader;
 use crate::sessions::SessionStore;
 use crate::shared_data::Sharable;
-use crate::{routes, userdb};
+use crate::signals::{Signal, SignalListener};
+use crate::listen::{self, Listener};
+use crate::tls::TlsAcceptor;
+use crate::pages::PageConfig;
+use crate::routes::{api::LocalAdmin, recover::RecoveryToken};
+use crate::{routes, systemd, userdb};
+
+use std::sync::Arc;
 
 use actix_web::cookie::time::Duration;
 use actix_web::dev::{Server, ServerHandle};
 
-use actix_web::{web, App, HttpServer};
+use actix_http::{body::MessageBody, error::DispatchError, Extensions, HttpService, Protocol, Request, Response};
+use actix_service::{fn_service, map_config, IntoServiceFactory, Service, ServiceFactory, ServiceFactoryExt};
+use actix_web::{dev::AppConfig, web, App, HttpServer};
 
 use serde::Deserialize;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio::sync::Notify;
+
+/// Starts serving the app on every listener. TCP listeners use HTTPS when tls
+/// is given. HttpServer can only do TLS through actix's own acceptors, so the
+/// server is put together from actix-server and actix-http in that case.
+fn build_server<F, I, S, B>(factory: F, listeners: Vec<Listener>, tls: Option<Arc<TlsAcceptor>>) -> std::io::Result<Server>
+where
+    F: Fn() -> I + Send + Clone + 'static,
+    I: IntoServiceFactory<S, Request>,
+    S: ServiceFactory<Request, Config = AppConfig> + 'static,
+    S::Error: Into<actix_web::Error> + 'static,
+    S::InitError: std::fmt::Debug,
+    S::Response: Into<Response<B>> + 'static,
+    <S::Service as Service<Request>>::Future: 'static,
+    S::Service: 'static,
+    B: MessageBody + 'static,
+{
+    let tls = match tls {
+        Some(t) => t,
+        None => {
+            let mut builder = HttpServer::new(factory).disable_signals();
+            // Each listener keeps the connect hook that was set when it was added
+            for l in listeners {
+                builder = match l {
+                    Listener::Tcp(t) => builder.on_connect(|_, _| {}).listen(t)?,
+                    #[cfg(unix)]
+                    Listener::Unix(u, proxy) => builder
+                        .on_connect(move |_, ext| mark_proxy_connection(proxy, ext))
+                        .listen_uds(u)?,
+                };
+            }
+            return Ok(builder.run());
+        }
+    };
+
+    let mut builder = actix_server::Server::build().disable_signals();
+    for l in listeners {
+        let name = l.to_string();
+        let factory = factory.clone();
+        builder = match l {
+            Listener::Tcp(t) => {
+                let tls = tls.clone();
+                builder.listen(name, t, move || {
+                    let app = factory().into_factory().map_err(|e| e.into().error_response());
+                    let (accept_tls, connect_tls) = (tls.clone(), tls.clone());
+                    fn_service(move |io: TcpStream| {
+                        let tls = accept_tls.clone();
+                        async move { tls.accept(io).await.map_err(DispatchError::Io) }
+                    })
+                    .and_then(
+                        HttpService::build()
+                            .on_connect_ext(move |io: &_, ext: &mut _| connect_tls.on_connect(io, ext))
+                            .finish(map_config(app, |_| AppConfig::default())),
+                    )
+                })?
+            }
+            #[cfg(unix)]
+            Listener::Unix(u, proxy) => builder.listen_uds(name, u, move || {
+                let app = factory().into_factory().map_err(|e| e.into().error_response());
+                fn_service(|io: UnixStream| async { Ok((io, Protocol::Http1, None)) }).and_then(
+                    HttpService::build()
+                        .on_connect_ext(move |_: &_, ext: &mut _| mark_proxy_connection(proxy, ext))
+                        .finish(map_config(app, |_| AppConfig::default())),
+                )
+            })?,
+        };
+    }
+    return Ok(builder.run());
+}
+
+#[cfg(unix)]
+fn mark_proxy_connection(proxy: bool, ext: &mut Extensions) {
+    if proxy {
+        ext.insert(ProxySocket);
+    }
+}
+
+/// Which routes a set of listeners serves
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Surface {
+    All,
+    /// Logins and auth requests, for when the admin page is served elsewhere
+    /// or disabled
+    Public,
+    /// Logins and the admin page, without auth requests
+    Admin,
+    /// Only the API, trusted without a login, for admin.socket
+    Local,
+}
 
 pub async fn run_server(mut cfg: UserConfig) {
     loop {
@@ -23,10 +129,14 @@ pub async fn run_server(mut cfg: UserConfig) {
                 match UserConfig::new(&std::env::args().map(|x| x.to_string()).collect()) {
                     Ok(c) => {
                         cfg = c;
+                        // --recover is still in args, but recovery only
+                        // happens once per run
+                        cfg.recover = false;
                     }
                     Err(e) => {
                         tracing::error!("Unable to reload config: {e}");
                         tracing::warn!("Server will restart with previous valid config");
+                        cfg.recover = false;
                     }
                 }
                 continue;
@@ -47,7 +157,7 @@ async fn start_server(cfg: UserConfig) -> ExitCommand {
     let session_timeout = Duration::seconds(*cfg.session_timeout.as_ref().unwrap());
     let session_store = SessionStore::new(session_timeout).to_sharable();
 
-    let user_db = match userdb::UserDB::new(cfg.user_db.as_ref().unwrap()) {
+    let user_db = match userdb::UserDB::from_config(&cfg) {
         Ok(u) => u.to_sharable(),
         Err(e) => {
             tracing::error!("{}", e);
@@ -55,65 +165,289 @@ async fn start_server(cfg: UserConfig) -> ExitCommand {
         }
     };
 
+    let access_control = match AccessControl::new(&cfg) {
+        Ok(a) => a.to_sharable(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ExitCommand::Quit;
+        }
+    };
+
+    let controller = signaller.clone();
+    let shutdown_db = user_db.clone();
+    let (live_db, live_sessions, live_access) = (user_db.clone(), session_store.clone(), access_control.clone());
+
     // Spin up server in setup or normal operating mode
-    let addr = (cfg.address.as_ref().unwrap().as_str(), *cfg.port.as_ref().unwrap());
+    let listeners = match listen::bind(&cfg) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!("{}", e);
+            return ExitCommand::Quit;
+        }
+    };
+    let tls = match cfg.tls.clone().map(TlsAcceptor::new) {
+        Some(Ok(t)) => Some(Arc::new(t)),
+        Some(Err(e)) => {
+            tracing::error!("Unable to set up TLS: {}", e);
+            return ExitCommand::Quit;
+        }
+        None => None,
+    };
+    let assets = Assets::new(cfg.asset_dir.clone()).to_sharable();
+    let page_config = PageConfig::new(&cfg).to_sharable();
+    let route_prefix = cfg.route_prefix();
+    let status = if user_db.read().unwrap().count() == 0 {
+        "Waiting for setup"
+    } else if cfg.recover {
+        "Waiting for recovery"
+    } else {
+        "Running"
+    };
     let srv: Server;
+    // Admin page and admin.socket servers
+    let mut extra_srvs: Vec<Server> = Vec::new();
+    let mut reloader = None;
+    let mut reload = None;
     if user_db.read().unwrap().count() == 0 {
         tracing::info!(
-            "Staring server in limited setup mode. Go to {}:{}/setup to create an administrator account",
-            addr.0,
-            addr.1
+            "Staring server in limited setup mode. Go to {}{}/setup to create an administrator account",
+            listeners[0],
+            route_prefix
         );
-        srv = HttpServer::new(move || {
+        let factory = move || {
             App::new()
                 .app_data(signaller.clone())
                 .app_data(user_db.clone())
-                .route("/static/{a}", web::get().to(routes::static_dir::get))
-                .route("/setup", web::get().to(routes::setup::get))
-                .route("/setup", web::post().to(routes::setup::post))
-        })
-        .bind(addr)
-        .unwrap()
-        .run();
-    } else {
-        srv = HttpServer::new(move || {
+                .app_data(page_config.clone())
+                .app_data(assets.clone())
+                .service(
+                    web::scope(&route_prefix)
+                        .route("/static/{a}", web::get().to(routes::static_dir::get))
+                        .route("/setup", web::get().to(routes::setup::get))
+                        .route("/setup", web::post().to(routes::setup::post)),
+                )
+        };
+        srv = match build_server(factory, listeners, tls) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("Unable to start server: {}", e);
+                return ExitCommand::Quit;
+            }
+        };
+    } else if cfg.recover {
+        let token = RecoveryToken::new();
+        tracing::warn!(
+            "Starting server in recovery mode. Go to {}{}/recover and enter the token {} to reset an admin password or add an admin",
+            listeners[0],
+            route_prefix,
+            token.get().unwrap_or_default()
+        );
+        let token = token.to_sharable();
+        let factory = move || {
             App::new()
+                .app_data(signaller.clone())
+                .app_data(user_db.clone())
+                .app_data(page_config.clone())
+                .app_data(assets.clone())
+                .app_data(token.clone())
+                .service(
+                    web::scope(&route_prefix)
+                        .route("/static/{a}", web::get().to(routes::static_dir::get))
+                        .route("/recover", web::get().to(routes::recover::get))
+                        .route("/recover", web::post().to(routes::recover::post)),
+                )
+        };
+        srv = match build_server(factory, listeners, tls) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("Unable to start server: {}", e);
+                return ExitCommand::Quit;
+            }
+        };
+    } else {
+        let oidc_client = cfg.oidc.clone().map(|c| OidcClient::new(c).to_sharable());
+        let ldap = cfg.ldap.clone().map(|c| LdapAuthenticator::new(c).to_sharable());
+        let notify = Arc::new(Notify::new());
+        reloader = Some(tokio::spawn(
+            Reloader::new(cfg.clone(), user_db.clone(), session_store.clone(), access_control.clone())
+                .watch(notify.clone()),
+        ));
+        reload = Some(notify);
+        let admin = cfg.admin.clone().unwrap_or_default();
+        let admin_listeners = if admin.enabled {
+            match listen::bind_all(&admin.listen) {
+                Ok(l) => l,
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    return ExitCommand::Quit;
+                }
+            }
+        } else {
+            Vec::new()
+        };
+        let local_listener = match admin.socket.as_deref() {
+            Some(path) => match listen::bind_admin_socket(path) {
+                Ok(l) => Some(l),
+                Err(e) => {
+                    tracing::error!("{}", e);
+                    return ExitCommand::Quit;
+                }
+            },
+            None => None,
+        };
+        let main_surface = if !admin.enabled || !admin_listeners.is_empty() {
+            Surface::Public
+        } else {
+            Surface::All
+        };
+
+        let make_app = move |surface: Surface| {
+            let mut app = App::new()
                 .app_data(signaller.clone())
                 .app_data(user_db.clone())
                 .app_data(session_store.clone())
+                .app_data(access_control.clone())
+                .app_data(page_config.clone())
+                .app_data(assets.clone());
+            if let Some(ldap) = &ldap {
+                app = app.app_data(ldap.clone());
+            }
+            if surface == Surface::Local {
+                return app
+                    .app_data(LocalAdmin::new().to_sharable())
+                    .service(web::scope(&route_prefix).service(routes::api::scope()));
+            }
+            let mut scope = web::scope(&route_prefix)
                 .route("/static/{file}", web::get().to(routes::static_dir::get))
                 .route("/login", web::get().to(routes::login::get))
                 .route("/login", web::post().to(routes::login::post))
                 .route("/logout", web::get().to(routes::logout::get))
-                .route("/admin", web::get().to(routes::admin::get))
-                .route("/authrequest", web::get().to(routes::auth_request::get))
-                .route("/rpc/{command}", web::post().to(routes::rpc::post))
-                .route("/rpc/{command}", web::get().to(routes::rpc::get))
-        })
-        .bind(addr)
-        .unwrap()
-        .run();
+                .route("/changepassword", web::get().to(routes::change_password::get))
+                .route("/changepassword", web::post().to(routes::change_password::post));
+            if surface != Surface::Admin {
+                if let Some(client) = &oidc_client {
+                    app = app.app_data(client.clone());
+                    scope = scope
+                        .route("/login/oidc", web::get().to(routes::oidc::get))
+                        .route("/login/oidc/callback", web::get().to(routes::oidc::callback));
+                }
+                scope = scope.route("/authrequest", web::get().to(routes::auth_request::get));
+            }
+            if surface != Surface::Public {
+                scope = scope
+                    .route("/admin", web::get().to(routes::admin::get))
+                    .route("/rpc/{command}", web::post().to(routes::rpc::post))
+                    .route("/rpc/{command}", web::get().to(routes::rpc::get))
+                    .service(routes::api::scope());
+            }
+            app.service(scope)
+        };
+
+        let main_app = make_app.clone();
+        srv = match build_server(move || main_app(main_surface), listeners, tls.clone()) {
+            Ok(s) => s,
+            Err(e) => {
+                tracing::error!("Unable to start server: {}", e);
+                return ExitCommand::Quit;
+            }
+        };
+        if let Some(l) = local_listener {
+            tracing::info!("Serving the admin API on {}", l);
+            let local_app = make_app.clone();
+            match build_server(move || local_app(Surface::Local), vec![l], None) {
+                Ok(s) => extra_srvs.push(s),
+                Err(e) => {
+                    tracing::error!("Unable to start admin socket server: {}", e);
+                    return ExitCommand::Quit;
+                }
+            }
+        }
+        if !admin_listeners.is_empty() {
+            tracing::info!("Serving the admin page on {}", admin_listeners[0]);
+            match build_server(move || make_app(Surface::Admin), admin_listeners, tls) {
+                Ok(s) => extra_srvs.push(s),
+                Err(e) => {
+                    tracing::error!("Unable to start admin server: {}", e);
+                    return ExitCommand::Quit;
+                }
+            }
+        } else if !admin.enabled {
+            tracing::info!("The admin page is disabled");
+        }
     }
 
-    let handle = srv.handle();
-    let task = tokio::spawn(srv);
+    let mut handles = vec![srv.handle()];
+    let mut tasks = vec![tokio::spawn(srv)];
+    for s in extra_srvs {
+        handles.push(s.handle());
+        tasks.push(tokio::spawn(s));
+    }
+    let signal_task = tokio::spawn(handle_signals(controller, reload));
+    let watchdog = systemd::start_watchdog(move || {
+        // One at a time, so the check can't deadlock with a request
+        drop(live_db.read().unwrap());
+        drop(live_sessions.read().unwrap());
+        drop(live_access.read().unwrap());
+    });
+    systemd::ready(status);
+
+    let exit_sig = wait_for_exit_signal(signal_recv, handles).await;
+    match exit_sig {
+        ExitCommand::Restart => systemd::reloading(),
+        ExitCommand::Quit => systemd::stopping(),
+    }
+    for task in tasks {
+        _ = task.await;
+    }
+    signal_task.abort();
+    if let Some(w) = watchdog {
+        w.abort();
+    }
+    if let Some(r) = reloader {
+        r.abort();
+    }
+
+    match shutdown_db.write().unwrap().save_unsaved() {
+        Ok(_) => {}
+        Err(e) => tracing::error!("Unable to save user db: {}", e),
+    }
 
-    let exit_sig = wait_for_exit_signal(signal_recv, handle).await;
-    _ = tokio::join!(task);
     tracing::error!("Returning {:#?}", exit_sig);
     return exit_sig;
 }
 
-async fn wait_for_exit_signal(mut rx: Receiver<(ExitCommand, bool)>, handle: ServerHandle) -> ExitCommand {
+/// Stops the server on SIGTERM or SIGINT and reloads on SIGHUP. Setup and
+/// recovery mode have nothing to reload, so SIGHUP restarts the server instead.
+async fn handle_signals(controller: web::Data<ServerController>, reload: Option<Arc<Notify>>) {
+    let mut signals = SignalListener::new();
+    loop {
+        match signals.recv().await {
+            Signal::Stop => {
+                controller.send_exit(ExitCommand::Quit, false).await;
+                return;
+            }
+            Signal::Reload => match &reload {
+                Some(r) => r.notify_one(),
+                None => {
+                    controller.send_exit(ExitCommand::Restart, false).await;
+                    return;
+                }
+            },
+        }
+    }
+}
+
+async fn wait_for_exit_signal(mut rx: Receiver<(ExitCommand, bool)>, handles: Vec<ServerHandle>) -> ExitCommand {
     match rx.recv().await {
         Some((command, force)) => {
             if force {
                 tracing::info!("Forcefully stopping server");
-                handle.stop(false).await;
             } else {
                 tracing::info!("Requesting server shutdown");
-                handle.stop(true).await;
             };
+            for handle in handles {
+                handle.stop(!force).await;
+            }
             return command;
         }
         None => {
@@ -161,6 +495,7 @@ pub mod tests {
 
     use super::*;
     use crate::routes::tests::make_test_userdb;
+    use crate::config::{AdminConfig, ListenConfig, NetworkConfig};
     use crate::{config::UserConfig, test_utils::make_tmp_file, Error};
 
     pub fn start_test_server(cfg: UserConfig) -> JoinHandle<ExitCommand> {
@@ -212,4 +547,225 @@ pub mod tests {
 
         return Ok(());
     }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn server_multiple_listeners() -> crate::Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        const PORT: u16 = 8650;
+
+        let socket = make_tmp_file();
+        std::fs::remove_file(&socket).unwrap();
+        let proxy_socket = make_tmp_file();
+        std::fs::remove_file(&proxy_socket).unwrap();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.listen = Some(vec![
+            ListenConfig {
+                address: format!("127.0.0.1:{}", PORT),
+                ..Default::default()
+            },
+            ListenConfig {
+                address: format!("unix:{}", socket),
+                mode: Some("600".to_string()),
+                ..Default::default()
+            },
+            ListenConfig {
+                address: format!("unix:{}", proxy_socket),
+                mode: Some("600".to_string()),
+                trusted_proxy: true,
+            },
+        ]);
+        cfg.network = Some(NetworkConfig {
+            deny: vec!["203.0.113.0/24".to_string()],
+            ..Default::default()
+        });
+        start_test_server(cfg);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        match reqwest::get(format!("http://localhost:{}/authrequest", PORT)).await {
+            Ok(r) => assert_eq!(r.status(), StatusCode::UNAUTHORIZED),
+            Err(e) => return Err(Error::convert(e)),
+        };
+
+        // nginx's address headers are only trusted on the proxy socket
+        let unix_get = |socket: &String, real_ip: &'static str| {
+            let socket = socket.to_owned();
+            async move {
+                let mut stream = tokio::net::UnixStream::connect(socket).await.unwrap();
+                let req = format!(
+                    "GET /authrequest HTTP/1.1\r\nHost: localhost\r\nX-Real-IP: {}\r\nConnection: close\r\n\r\n",
+                    real_ip
+                );
+                stream.write_all(req.as_bytes()).await.unwrap();
+                let mut resp = String::new();
+                stream.read_to_string(&mut resp).await.unwrap();
+                return resp;
+            }
+        };
+        assert!(unix_get(&proxy_socket, "192.0.2.1").await.starts_with("HTTP/1.1 401"));
+        assert!(unix_get(&proxy_socket, "203.0.113.5").await.starts_with("HTTP/1.1 403"));
+        assert!(unix_get(&socket, "203.0.113.5").await.starts_with("HTTP/1.1 401"));
+
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn server_tls() -> crate::Result<()> {
+        use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        use crate::tls::tests::*;
+
+        const PORT: u16 = 8649;
+
+        let mut cfg = UserConfig::default();
+        cfg.port = Some(PORT);
+        cfg.user_db = Some(make_test_userdb());
+        cfg.tls = Some(make_test_tls_config(true));
+        start_test_server(cfg);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        let client_config = |client_cert: bool| {
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(CertificateDer::from_pem_slice(CA_CERT.as_bytes()).unwrap()).unwrap();
+            let builder =
+                rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                    .with_safe_default_protocol_versions()
+                    .unwrap()
+                    .with_root_certificates(roots);
+            if !client_cert {
+                return Arc::new(builder.with_no_client_auth());
+            }
+            let cert = CertificateDer::from_pem_slice(CLIENT_CERT.as_bytes()).unwrap();
+            let key = PrivateKeyDer::from_pem_slice(CLIENT_KEY.as_bytes()).unwrap();
+            return Arc::new(builder.with_client_auth_cert(vec![cert], key).unwrap());
+        };
+        let https_get = |client_cert: bool, path: &'static str| {
+            let connector = tokio_rustls::TlsConnector::from(client_config(client_cert));
+            async move {
+                let stream = tokio::net::TcpStream::connect(("localhost", PORT)).await.unwrap();
+                let server_name = ServerName::try_from("localhost").unwrap();
+                let mut stream = connector.connect(server_name, stream).await.unwrap();
+                let req = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+                stream.write_all(req.as_bytes()).await.unwrap();
+                let mut resp = Vec::new();
+                _ = stream.read_to_end(&mut resp).await;
+                return String::from_utf8_lossy(&resp).to_string();
+            }
+        };
+
+        assert!(https_get(false, "/login").await.starts_with("HTTP/1.1 200"));
+        // Only clients with a certificate from the client CA may ask
+        assert!(https_get(false, "/authrequest").await.starts_with("HTTP/1.1 403"));
+        assert!(https_get(true, "/authrequest").await.starts_with("HTTP/1.1 401"));
+
+        assert!(reqwest::get(format!("http://localhost:{}/login", PORT)).await.is_err());
+
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn server_admin_listener() -> crate::Result<()> {
+        const PORT: u16 = 8648;
+        const ADMIN_PORT: u16 = 8647;
+        const NO_ADMIN_PORT: u16 = 8646;
+
+        let status = |port: u16, path: &'static str| async move {
+            return match reqwest::get(format!("http://localhost:{}{}", port, path)).await {
+                Ok(r) => r.status(),
+                Err(e) => panic!("{}", e),
+            };
+        };
+
+        let mut cfg = UserConfig::default();
+        cfg.port = Some(PORT);
+        cfg.user_db = Some(make_test_userdb());
+        cfg.admin = Some(AdminConfig {
+            listen: vec![ListenConfig {
+                address: format!("127.0.0.1:{}", ADMIN_PORT),
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        start_test_server(cfg.clone());
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        assert_eq!(status(PORT, "/admin").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(PORT, "/rpc/listusers").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(PORT, "/authrequest").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(ADMIN_PORT, "/admin").await, StatusCode::OK);
+        assert_eq!(status(ADMIN_PORT, "/login").await, StatusCode::OK);
+        assert_eq!(status(ADMIN_PORT, "/authrequest").await, StatusCode::NOT_FOUND);
+
+        // Disabled entirely
+        cfg.port = Some(NO_ADMIN_PORT);
+        cfg.admin = Some(AdminConfig {
+            enabled: false,
+            ..Default::default()
+        });
+        start_test_server(cfg);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        assert_eq!(status(NO_ADMIN_PORT, "/admin").await, StatusCode::NOT_FOUND);
+        assert_eq!(status(NO_ADMIN_PORT, "/authrequest").await, StatusCode::UNAUTHORIZED);
+
+        return Ok(());
+    }
+
+    #[tokio::test]
+    async fn server_base_path() -> crate::Result<()> {
+        const PORT: u16 = 8645;
+        const ROOT_PORT: u16 = 8644;
+        const STRIPPED_PORT: u16 = 8630;
+
+        // nginx passes the full path through
+        let mut cfg = UserConfig::default();
+        cfg.port = Some(PORT);
+        cfg.user_db = Some(make_test_userdb());
+        cfg.base_path = Some("/auth/".to_string());
+        cfg.serve_under_base_path = Some(true);
+        start_test_server(cfg.clone());
+
+        // Served on its own subdomain
+        cfg.port = Some(ROOT_PORT);
+        cfg.base_path = Some(String::new());
+        start_test_server(cfg);
+
+        // The default, where nginx strips /potato_auth
+        let mut cfg = UserConfig::default();
+        cfg.port = Some(STRIPPED_PORT);
+        cfg.user_db = Some(make_test_userdb());
+        start_test_server(cfg);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        let get = |url: String| async move {
+            return match reqwest::get(url).await {
+                Ok(r) => (r.status(), r.text().await.unwrap()),
+                Err(e) => panic!("{}", e),
+            };
+        };
+
+        let (status, page) = get(format!("http://localhost:{}/auth/login", PORT)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(page.contains("\"/auth/static/styles.css\""));
+        assert!(!page.contains("{{base_path}}"));
+        assert_eq!(get(format!("http://localhost:{}/auth/static/styles.css", PORT)).await.0, StatusCode::OK);
+        assert_eq!(get(format!("http://localhost:{}/login", PORT)).await.0, StatusCode::NOT_FOUND);
+
+        let (status, page) = get(format!("http://localhost:{}/login", ROOT_PORT)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(page.contains("\"/static/styles.css\""));
+
+        let (status, page) = get(format!("http://localhost:{}/login", STRIPPED_PORT)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(page.contains("\"/potato_auth/static/styles.css\""));
+        assert_eq!(
+            get(format!("http://localhost:{}/potato_auth/login", STRIPPED_PORT)).await.0,
+            StatusCode::NOT_FOUND
+        );
+
+        return Ok(());
+    }
 }
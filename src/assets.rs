@@ -0,0 +1,173 @@
+/*
+Pages, scripts, styles and images served by PotatoAuth. They are built into
+the binary, and a file with the same name in `asset_dir` is served in place of
+the built in one so the pages can be customized without rebuilding.
+*/
+
+use std::{borrow::Cow, path::PathBuf};
+
+use actix_web::{
+    http::header::{CACHE_CONTROL, ETAG, IF_NONE_MATCH},
+    web::{self, Bytes},
+    HttpRequest, HttpResponse,
+};
+use sha1::{Digest, Sha1};
+
+use crate::shared_data::Sharable;
+
+/// Cache-Control of the pages, which are filled in as they are served
+pub const PAGE_CACHE: &str = "no-cache";
+/// Cache-Control of everything else
+pub const STATIC_CACHE: &str = "public, max-age=3600";
+
+macro_rules! embed {
+    ($($name:literal),* $(,)?) => {
+        &[$(($name, include_bytes!(concat!("../static/", $name)) as &[u8])),*]
+    };
+}
+
+/// Every file in static/
+const EMBEDDED: &[(&str, &[u8])] = embed!(
+    "admin.html",
+    "alerts.js",
+    "change_password.html",
+    "i18n.js",
+    "login.html",
+    "logo.png",
+    "logo_white.png",
+    "logout.html",
+    "recover.html",
+    "reloader.js",
+    "setup.html",
+    "styles.css",
+);
+
+pub struct Assets {
+    /// Directory checked for replacements before the built in files
+    override_dir: Option<PathBuf>,
+}
+
+impl Assets {
+    pub fn new(override_dir: Option<String>) -> Self {
+        return Assets {
+            override_dir: override_dir.map(PathBuf::from),
+        };
+    }
+
+    /// Contents of the file called name, or None if there is no such file
+    pub async fn get(&self, name: &str) -> Option<Cow<'static, [u8]>> {
+        // Only plain file names, so nothing outside the directory is served
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return None;
+        }
+
+        if let Some(dir) = &self.override_dir {
+            let filepath = dir.join(name);
+            match tokio::fs::read(&filepath).await {
+                Ok(contents) => return Some(Cow::Owned(contents)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Unable to read {}: {}", filepath.display(), e),
+            }
+        }
+        return EMBEDDED
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, c)| Cow::Borrowed(*c));
+    }
+}
+
+impl Sharable for Assets {
+    type Shared = Assets;
+
+    fn to_sharable(self) -> web::Data<Self::Shared> {
+        return web::Data::new(self);
+    }
+}
+
+pub fn content_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    return match extension.as_deref() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("webp") => "image/webp",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    };
+}
+
+/// Responds with the contents of the file called name, or with 304 Not
+/// Modified if the client's copy has the same ETag
+pub fn respond(req: &HttpRequest, name: &str, contents: Cow<'static, [u8]>, cache_control: &str) -> HttpResponse {
+    let digest: String = Sha1::digest(&contents).iter().map(|b| format!("{:02x}", b)).collect();
+    let etag = format!("\"{}\"", digest);
+    let cached = req
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| h.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+
+    let mut resp = if cached {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    resp.insert_header((ETAG, etag))
+        .insert_header((CACHE_CONTROL, cache_control));
+    if cached {
+        return resp.finish();
+    }
+
+    let body = match contents {
+        Cow::Borrowed(c) => Bytes::from_static(c),
+        Cow::Owned(c) => Bytes::from(c),
+    };
+    return resp.content_type(content_type(name)).body(body);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::make_tmp_file;
+
+    #[tokio::test]
+    async fn override_dir() {
+        let builtin = Assets::new(None);
+        assert_eq!(
+            builtin.get("styles.css").await.unwrap().as_ref(),
+            include_bytes!("../static/styles.css")
+        );
+        assert!(builtin.get("missing.css").await.is_none());
+
+        let dir = make_tmp_file();
+        std::fs::remove_file(&dir).unwrap();
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(format!("{}/styles.css", dir), "body {}").unwrap();
+        std::fs::write(format!("{}/extra.svg", dir), "<svg/>").unwrap();
+
+        let custom = Assets::new(Some(dir.clone()));
+        assert_eq!(custom.get("styles.css").await.unwrap().as_ref(), b"body {}");
+        assert_eq!(custom.get("extra.svg").await.unwrap().as_ref(), b"<svg/>");
+        // Files that are not overridden are still served
+        assert!(custom.get("logo.png").await.is_some());
+
+        for name in ["", "../styles.css", ".hidden", "a/b.css", "a\\b.css"] {
+            assert!(custom.get(name).await.is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(content_type("login.html"), "text/html; charset=utf-8");
+        assert_eq!(content_type("alerts.js"), "text/javascript; charset=utf-8");
+        assert_eq!(content_type("logo.PNG"), "image/png");
+        assert_eq!(content_type("README"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,693 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use clap::Subcommand;
+use serde_json::Value;
+
+use crate::{
+    access::AccessControl,
+    config::UserConfig,
+    file_utils::make_dirs_and_write,
+    i18n::Message,
+    routes::api::PREFIX,
+    sessions::SessionInfo,
+    tls::TlsAcceptor,
+    transfer::{self, TransferFormat},
+    userdb::{AcctType, ImportReport, UserDB},
+    Error, Result,
+};
+
+/// Commands that operate on the configured files instead of starting the
+/// server
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Command {
+    /// Import users from an Apache htpasswd file
+    ImportHtpasswd {
+        /// htpasswd file to read
+        file: String,
+
+        /// Comma-separated glob patterns given to every imported user
+        #[clap(long, value_delimiter = ',')]
+        paths: Vec<String>,
+
+        /// Import users as Admin accounts
+        #[clap(long)]
+        admin: bool,
+
+        /// Report what would be imported without changing the user db
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Write all users, including password hashes, as JSON or CSV
+    ExportUsers {
+        #[clap(long, value_enum, default_value_t = TransferFormat::Json)]
+        format: TransferFormat,
+
+        /// File to write to instead of stdout
+        #[clap(long)]
+        output: Option<String>,
+    },
+
+    /// Import users from a JSON or CSV export
+    ImportUsers {
+        file: String,
+
+        #[clap(long, value_enum, default_value_t = TransferFormat::Json)]
+        format: TransferFormat,
+
+        /// Report conflicts without changing the user db
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Manage accounts in the user db. A running server picks up changes
+    /// within a few seconds.
+    User {
+        #[clap(subcommand)]
+        command: UserCommand,
+    },
+
+    /// Manage the sessions of a running server through admin.socket
+    Session {
+        #[clap(subcommand)]
+        command: SessionCommand,
+    },
+
+    /// Validate or print the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum UserCommand {
+    /// Add an account. The password is read from stdin unless --password is
+    /// given.
+    Add {
+        name: String,
+
+        /// Comma-separated glob patterns the user may access
+        #[clap(long, value_delimiter = ',')]
+        paths: Vec<String>,
+
+        /// Make an Admin account
+        #[clap(long)]
+        admin: bool,
+
+        #[clap(long)]
+        password: Option<String>,
+    },
+
+    /// List accounts
+    List,
+
+    /// Remove an account, which also ends its sessions
+    Remove { name: String },
+
+    /// Set an account's password. The password is read from stdin unless
+    /// --password is given.
+    Passwd {
+        name: String,
+
+        #[clap(long)]
+        password: Option<String>,
+
+        /// Make the user choose a new password when they next log in
+        #[clap(long)]
+        temporary: bool,
+    },
+
+    /// Replace the glob patterns an account may access
+    SetPaths {
+        name: String,
+
+        /// Comma-separated glob patterns
+        #[clap(value_delimiter = ',')]
+        paths: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum SessionCommand {
+    /// List logged in sessions
+    List,
+
+    /// End a session, logging its user out
+    Revoke {
+        /// Id shown by `session list`
+        id: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum ConfigCommand {
+    /// Check that the config, user db and TLS files can be loaded
+    Check,
+
+    /// Print the config with command line arguments and defaults applied
+    Show,
+}
+
+pub fn run(command: Command, cfg: &UserConfig) -> Result<()> {
+    // Neither of these changes the user db
+    match command {
+        Command::Session { command } => return run_session(command, cfg),
+        Command::Config { command } => return run_config(command, cfg),
+        _ => {}
+    }
+
+    // Ask for the password first, so the server isn't kept waiting on the
+    // lock while someone types
+    let command = match command {
+        Command::User { mut command } => match command.read_password() {
+            Ok(_) => Command::User { command },
+            Err(e) => return Err(e),
+        },
+        c => c,
+    };
+
+    let mut user_db = match UserDB::from_config_locked(cfg) {
+        Ok(u) => u,
+        Err(e) => return Err(e),
+    };
+
+    match command {
+        Command::ImportHtpasswd {
+            file,
+            paths,
+            admin,
+            dry_run,
+        } => {
+            let acct_type = if admin { AcctType::Admin } else { AcctType::User };
+            let report = match read_file(&file).and_then(|c| user_db.import_htpasswd(&c, &paths, acct_type, dry_run)) {
+                Ok(r) => r,
+                Err(e) => return Err(e),
+            };
+            print_report(&report);
+        }
+        Command::ExportUsers { format, output } => {
+            let data = match transfer::serialize(&user_db.export(), format) {
+                Ok(d) => d,
+                Err(e) => return Err(e),
+            };
+            match output {
+                Some(path) => return make_dirs_and_write(path, data),
+                None => println!("{}", data),
+            }
+        }
+        Command::ImportUsers { file, format, dry_run } => {
+            let report = match read_file(&file)
+                .and_then(|c| transfer::deserialize(&c, format))
+                .and_then(|u| user_db.import_users(u, dry_run))
+            {
+                Ok(r) => r,
+                Err(e) => return Err(e),
+            };
+            print_report(&report);
+        }
+        Command::User { command } => return run_user(command, &mut user_db),
+        Command::Session { .. } | Command::Config { .. } => {}
+    }
+
+    return Ok(());
+}
+
+impl UserCommand {
+    /// Fills in the password of commands that need one and weren't given it
+    fn read_password(&mut self) -> Result<()> {
+        let password = match self {
+            UserCommand::Add { password, .. } | UserCommand::Passwd { password, .. } => password,
+            _ => return Ok(()),
+        };
+        return match read_password(password.take()) {
+            Ok(p) => {
+                *password = Some(p);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        };
+    }
+}
+
+fn run_user(command: UserCommand, user_db: &mut UserDB) -> Result<()> {
+    let message = match command {
+        UserCommand::Add {
+            name,
+            paths,
+            admin,
+            password,
+        } => {
+            let acct_type = if admin { AcctType::Admin } else { AcctType::User };
+            match read_password(password).and_then(|p| user_db.add_user(&name, &p, &paths, acct_type)) {
+                Ok(_) => Message::new("user.added").arg("name", name),
+                Err(e) => return Err(e),
+            }
+        }
+        UserCommand::List => {
+            let now = chrono::Utc::now().timestamp();
+            let mut users = user_db.list_safe();
+            users.sort_by(|a, b| a.name.cmp(&b.name));
+            for u in users {
+                let status = if u.disabled {
+                    "disabled"
+                } else if u.expires_at.is_some_and(|e| e <= now) {
+                    "expired"
+                } else {
+                    "active"
+                };
+                println!("{}\t{:?}\t{}\t{}", u.name, u.acct_type, status, u.paths.join(","));
+            }
+            return Ok(());
+        }
+        UserCommand::Remove { name } => {
+            if user_db.get(&name).is_none() {
+                return Err(Error::NotFound(Message::new("user.not_found").arg("name", name)));
+            }
+            match user_db.remove(&name) {
+                Ok(_) => Message::new("user.removed").arg("name", name),
+                Err(e) => return Err(e),
+            }
+        }
+        UserCommand::Passwd {
+            name,
+            password,
+            temporary,
+        } => {
+            let user = match read_password(password).and_then(|p| user_db.update_user(&name, Some(p), None, None)) {
+                Ok(u) => u,
+                Err(e) => return Err(e),
+            };
+            user.write().unwrap().set_must_change_password(temporary);
+            match user_db.write_to_file() {
+                Ok(_) => Message::new("user.updated").arg("name", name),
+                Err(e) => return Err(e),
+            }
+        }
+        UserCommand::SetPaths { name, paths } => match user_db.update_user(&name, None, Some(paths), None) {
+            Ok(_) => match user_db.write_to_file() {
+                Ok(_) => Message::new("user.updated").arg("name", name),
+                Err(e) => return Err(e),
+            },
+            Err(e) => return Err(e),
+        },
+    };
+
+    println!("{}", message);
+    return Ok(());
+}
+
+fn run_session(command: SessionCommand, cfg: &UserConfig) -> Result<()> {
+    match command {
+        SessionCommand::List => {
+            let sessions: Vec<SessionInfo> = match call_server(cfg, "GET", "/sessions")
+                .and_then(|r| serde_json::from_value(r).map_err(Error::convert))
+            {
+                Ok(s) => s,
+                Err(e) => return Err(e),
+            };
+            for s in sessions {
+                let expires = match chrono::DateTime::from_timestamp(s.expires_at, 0) {
+                    Some(t) => t.to_rfc3339(),
+                    None => s.expires_at.to_string(),
+                };
+                let restricted = if s.restricted { "\trestricted" } else { "" };
+                println!("{}\t{}\t{}{}", s.id, s.user, expires, restricted);
+            }
+        }
+        SessionCommand::Revoke { id } => {
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(Error::NotFound(Message::new("session.not_found").arg("id", id)));
+            }
+            match call_server(cfg, "DELETE", &format!("/sessions/{}", id)) {
+                Ok(r) => println!("{}", r.as_str().unwrap_or_default()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    return Ok(());
+}
+
+fn run_config(command: ConfigCommand, cfg: &UserConfig) -> Result<()> {
+    match command {
+        ConfigCommand::Check => {
+            let mut problems: Vec<String> = Vec::new();
+            if let Err(e) = UserDB::from_config_strict(cfg) {
+                problems.push(format!("User db: {}", e));
+            }
+            if let Err(e) = AccessControl::new(cfg) {
+                problems.push(format!("Access rules: {}", e));
+            }
+            if let Some(Err(e)) = cfg.tls.clone().map(TlsAcceptor::new) {
+                problems.push(format!("TLS: {}", e));
+            }
+
+            if !problems.is_empty() {
+                return Err(Error::new(problems.join("\n")));
+            }
+            println!("{} is valid", cfg.cfg_path.as_deref().unwrap_or("Config"));
+        }
+        ConfigCommand::Show => match serde_json::to_string_pretty(cfg) {
+            Ok(s) => println!("{}", s),
+            Err(e) => return Err(Error::convert(e)),
+        },
+    }
+    return Ok(());
+}
+
+/// Returns password, or reads one from the first line of stdin
+fn read_password(password: Option<String>) -> Result<String> {
+    if let Some(p) = password {
+        return Ok(p);
+    }
+
+    let stdin = std::io::stdin();
+    let mut _no_echo = None;
+    if stdin.is_terminal() {
+        eprint!("Password: ");
+        _ = std::io::stderr().flush();
+        _no_echo = NoEcho::new();
+    }
+    let mut line = String::new();
+    return match stdin.lock().read_line(&mut line) {
+        Ok(_) if !line.trim_end_matches(['\r', '\n']).is_empty() => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+        Ok(_) => crate::err!("No password given"),
+        Err(e) => Err(Error::convert(e)),
+    };
+}
+
+/// Hides what is typed on the terminal until dropped
+#[cfg(unix)]
+struct NoEcho {
+    original: rustix::termios::Termios,
+}
+
+#[cfg(unix)]
+impl NoEcho {
+    fn new() -> Option<Self> {
+        use rustix::termios::{tcgetattr, tcsetattr, LocalModes, OptionalActions};
+
+        let stdin = std::io::stdin();
+        let original = match tcgetattr(&stdin) {
+            Ok(t) => t,
+            Err(_) => return None,
+        };
+        let mut hidden = original.clone();
+        hidden.local_modes.remove(LocalModes::ECHO);
+        return match tcsetattr(&stdin, OptionalActions::Now, &hidden) {
+            Ok(_) => Some(NoEcho { original }),
+            Err(_) => None,
+        };
+    }
+}
+
+#[cfg(unix)]
+impl Drop for NoEcho {
+    fn drop(&mut self) {
+        _ = rustix::termios::tcsetattr(std::io::stdin(), rustix::termios::OptionalActions::Now, &self.original);
+        // The newline that ended the password wasn't echoed either
+        eprintln!();
+    }
+}
+
+/// Windows terminals keep echoing
+#[cfg(not(unix))]
+struct NoEcho;
+
+#[cfg(not(unix))]
+impl NoEcho {
+    fn new() -> Option<Self> {
+        return None;
+    }
+}
+
+/// Sends a request to the API of the server running with cfg through
+/// admin.socket and returns the reply's response field
+#[cfg(unix)]
+fn call_server(cfg: &UserConfig, method: &str, path: &str) -> Result<Value> {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    let socket = match cfg.admin.as_ref().and_then(|a| a.socket.clone()) {
+        Some(s) => s,
+        None => return crate::err!("Set admin.socket in the config to manage a running server"),
+    };
+    let mut stream = match UnixStream::connect(&socket) {
+        Ok(s) => s,
+        Err(e) => return crate::err!("Unable to connect to {}, is the server running? ({})", socket, e),
+    };
+
+    // Connection: close so the reply ends when the server closes the socket
+    let request = format!(
+        "{} {}{}{} HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        method,
+        cfg.route_prefix(),
+        PREFIX,
+        path
+    );
+    let mut reply = String::new();
+    match stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.read_to_string(&mut reply))
+    {
+        Ok(_) => {}
+        Err(e) => return Err(Error::convert(e)),
+    }
+
+    let body: Value = match reply.split_once("\r\n\r\n").map(|(_, b)| serde_json::from_str(b)) {
+        Some(Ok(b)) => b,
+        _ => return crate::err!("Unexpected reply from {}", socket),
+    };
+    if body["ok"] != true {
+        return Err(Error::new(body["response"].as_str().unwrap_or_default()));
+    }
+    return Ok(body["response"].clone());
+}
+
+#[cfg(not(unix))]
+fn call_server(_cfg: &UserConfig, _method: &str, _path: &str) -> Result<Value> {
+    return crate::err!("admin.socket is only supported on unix");
+}
+
+fn read_file(path: &String) -> Result<String> {
+    return match std::fs::read_to_string(path) {
+        Ok(c) => Ok(c),
+        Err(e) => Err(Error::new(format!("Unable to read {}: {}", path, e))),
+    };
+}
+
+fn print_report(report: &ImportReport) {
+    let verb = if report.dry_run { "Would add" } else { "Added" };
+    for name in &report.added {
+        println!("{} {}", verb, name);
+    }
+    for reason in &report.skipped {
+        println!("Skipped {}", reason);
+    }
+
+    let verb = if report.dry_run { "Would import" } else { "Imported" };
+    println!(
+        "{} {} of {} entries",
+        verb,
+        report.added.len(),
+        report.added.len() + report.skipped.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::make_tmp_file;
+
+    #[test]
+    fn import_htpasswd_command() {
+        let htpasswd = make_tmp_file();
+        std::fs::write(&htpasswd, "sha_user:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\n").unwrap();
+        let udb = make_tmp_file();
+        let cfg_file = make_tmp_file();
+        UserConfig::default().write_to_file(&cfg_file).unwrap();
+
+        let args: Vec<String> = [
+            "_",
+            "-c",
+            &cfg_file,
+            "-u",
+            &udb,
+            "import-htpasswd",
+            &htpasswd,
+            "--paths",
+            "/a/**,/b/**",
+            "--admin",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+        let cfg = UserConfig::new(&args).unwrap();
+        let command = cfg.command.clone().expect("No command parsed");
+
+        run(command, &cfg).expect("Import failed");
+
+        let user_db = UserDB::new(&udb).unwrap();
+        let user = user_db.get(&"sha_user".to_string()).unwrap().read().unwrap();
+        assert_eq!(user.get_type(), &AcctType::Admin);
+        assert!(user.path_allowed("/b/c"));
+    }
+
+    #[test]
+    fn export_import_commands() {
+        let src_udb = crate::routes::tests::make_test_userdb();
+        let dest_udb = make_tmp_file();
+        let export = make_tmp_file();
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(src_udb);
+        run(
+            Command::ExportUsers {
+                format: TransferFormat::Csv,
+                output: Some(export.to_owned()),
+            },
+            &cfg,
+        )
+        .unwrap();
+
+        cfg.user_db = Some(dest_udb.to_owned());
+        run(
+            Command::ImportUsers {
+                file: export.to_owned(),
+                format: TransferFormat::Csv,
+                dry_run: true,
+            },
+            &cfg,
+        )
+        .unwrap();
+        assert_eq!(UserDB::new(&dest_udb).unwrap().count(), 0);
+
+        run(
+            Command::ImportUsers {
+                file: export,
+                format: TransferFormat::Csv,
+                dry_run: false,
+            },
+            &cfg,
+        )
+        .unwrap();
+        assert_eq!(UserDB::new(&dest_udb).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn user_commands() {
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_tmp_file());
+        let user = |command: UserCommand| run(Command::User { command }, &cfg);
+        let name = "cli_user".to_string();
+
+        user(UserCommand::Add {
+            name: name.to_owned(),
+            paths: vec!["/a/**".to_string()],
+            admin: true,
+            password: Some("first_password".to_string()),
+        })
+        .unwrap();
+        assert!(user(UserCommand::Add {
+            name: name.to_owned(),
+            paths: Vec::new(),
+            admin: false,
+            password: Some("first_password".to_string()),
+        })
+        .is_err());
+
+        user(UserCommand::Passwd {
+            name: name.to_owned(),
+            password: Some("second_password".to_string()),
+            temporary: true,
+        })
+        .unwrap();
+        user(UserCommand::SetPaths {
+            name: name.to_owned(),
+            paths: vec!["/b/**".to_string()],
+        })
+        .unwrap();
+
+        let user_db = UserDB::from_config(&cfg).unwrap();
+        let u = user_db
+            .verify_credentials(&name, &"second_password".to_string())
+            .unwrap();
+        assert!(user_db.password_change_required(u));
+        assert!(u.read().unwrap().path_allowed("/b/c"));
+        assert!(!u.read().unwrap().path_allowed("/a/c"));
+
+        user(UserCommand::Remove { name: name.to_owned() }).unwrap();
+        assert!(user(UserCommand::Remove { name }).is_err());
+        assert_eq!(UserDB::from_config(&cfg).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn config_check() {
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(crate::routes::tests::make_test_userdb());
+        run(
+            Command::Config {
+                command: ConfigCommand::Check,
+            },
+            &cfg,
+        )
+        .unwrap();
+
+        cfg.user_db = Some(format!("{}.missing", make_tmp_file()));
+        assert!(run(
+            Command::Config {
+                command: ConfigCommand::Check
+            },
+            &cfg
+        )
+        .is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn session_commands() {
+        use crate::{
+            app::tests::start_test_server,
+            config::AdminConfig,
+            routes::tests::{login_client, make_client, make_test_userdb},
+        };
+
+        const PORT: u16 = 8639;
+        let socket = make_tmp_file();
+        std::fs::remove_file(&socket).unwrap();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.admin = Some(AdminConfig {
+            socket: Some(socket),
+            ..Default::default()
+        });
+        start_test_server(cfg.clone());
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        let client = make_client();
+        login_client(&client, PORT, AcctType::User).await;
+
+        // The commands block, so they must not run on the server's thread
+        let list_cfg = cfg.clone();
+        let sessions = tokio::task::spawn_blocking(move || call_server(&list_cfg, "GET", "/sessions"))
+            .await
+            .unwrap()
+            .unwrap();
+        let id = sessions[0]["id"].as_str().unwrap().to_string();
+
+        let revoke = move |id: String| {
+            let cfg = cfg.clone();
+            tokio::task::spawn_blocking(move || {
+                run(
+                    Command::Session {
+                        command: SessionCommand::Revoke { id },
+                    },
+                    &cfg,
+                )
+            })
+        };
+        revoke(id.to_owned()).await.unwrap().unwrap();
+        assert!(revoke(id).await.unwrap().is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::fs;
+use std::{collections::HashMap, fs, path::Path};
 
 use clap::Parser;
 //use clap::{Parser, Subcommand};
@@ -6,6 +6,7 @@ use merge::Merge;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    cli::Command,
     file_utils::{file_exists, make_dirs_and_write},
     Result,
 };
@@ -77,6 +78,45 @@ pub struct UserConfig {
     #[clap(short, long)]
     pub port: Option<u16>,
 
+    /// Addresses to listen on instead of address and port. Unix sockets are
+    /// given as unix:/path/to/socket
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub listen: Option<Vec<ListenConfig>>,
+
+    /// Certificate and key for serving HTTPS on the TCP listeners
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub tls: Option<TlsConfig>,
+
+    /// Where the admin page and RPC API are served
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub admin: Option<AdminConfig>,
+
+    /// URL path nginx serves PotatoAuth under, eg /potato_auth, used for the
+    /// links in pages and redirects. Empty when it has its own subdomain.
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(long)]
+    pub base_path: Option<String>,
+
+    /// Also serve the routes under base_path, for nginx configs that pass
+    /// the full path through instead of stripping base_path
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(long)]
+    pub serve_under_base_path: Option<bool>,
+
+    /// Directory of files served in place of the built in pages, scripts,
+    /// styles and images with the same name
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub asset_dir: Option<String>,
+
+    /// Name, logo and colors of the login, logout and setup pages
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub theme: Option<ThemeConfig>,
+
     /// Time in seconds for inactive sessions to expire
     #[merge(strategy = merge_strategy::overwrite_option)]
     #[clap(skip)]
@@ -103,11 +143,64 @@ pub struct UserConfig {
     #[clap(long)]
     pub console: bool,
 
+    /// Start in recovery mode to reset an admin password or add an admin
+    /// using a one-time token printed to the log
+    #[serde(skip)]
+    #[merge(strategy = merge_strategy::boolean_or)]
+    #[clap(long)]
+    pub recover: bool,
+
+    /// argon2 settings for new password hashes. Hashes made with a different
+    /// variant or lower costs are upgraded when their user logs in.
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub argon2: Option<Argon2Config>,
+
+    /// Rules for new passwords
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub password_policy: Option<PasswordPolicy>,
+
+    /// Named access schedules that can be assigned to users or paths
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub schedules: Option<HashMap<String, Schedule>>,
+
+    /// Paths that may only be accessed during a schedule
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub path_schedules: Option<Vec<ScheduleRule>>,
+
+    /// Address based access rules
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub network: Option<NetworkConfig>,
+
+    /// Requests that are approved without a session
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub public_rules: Option<Vec<PublicRule>>,
+
+    /// External OpenID Connect identity provider used for logins
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub oidc: Option<OidcConfig>,
+
+    /// LDAP directory checked when a login does not match a local account
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(skip)]
+    pub ldap: Option<LdapConfig>,
+
     /// custom config file location
     #[serde(skip)]
     #[merge(strategy = merge_strategy::overwrite_option)]
     #[clap(short, long)]
     pub cfg_path: Option<String>,
+
+    #[serde(skip)]
+    #[merge(strategy = merge_strategy::overwrite_option)]
+    #[clap(subcommand)]
+    pub command: Option<Command>,
 }
 
 impl Default for UserConfig {
@@ -115,17 +208,368 @@ impl Default for UserConfig {
         return UserConfig {
             address: Some("localhost".to_string()),
             port: Some(8675),
+            listen: None,
+            tls: None,
+            admin: None,
+            base_path: Some("/potato_auth".to_string()),
+            serve_under_base_path: Some(false),
+            asset_dir: None,
+            theme: None,
             session_timeout: Some(3600),
             user_db: default_path::users_file(),
             log_dir: default_path::log_dir(),
             log_archive_count: Some(5),
+            argon2: Some(Argon2Config::default()),
+            password_policy: Some(PasswordPolicy::default()),
+            schedules: None,
+            path_schedules: None,
+            network: None,
+            public_rules: None,
             cfg_path: Some(default_path::config_file()), // only used for passing --config via cmdline args
             console: false,
+            recover: false,
+            oidc: None,
+            ldap: None,
+            command: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Argon2Variant {
+    Argon2d,
+    Argon2i,
+    Argon2id,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Argon2Config {
+    pub variant: Argon2Variant,
+    /// Memory cost in KiB
+    pub mem_cost: u32,
+    /// Number of passes
+    pub time_cost: u32,
+    /// Degree of parallelism
+    pub lanes: u32,
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        return Argon2Config {
+            variant: Argon2Variant::Argon2id,
+            mem_cost: 19456,
+            time_cost: 2,
+            lanes: 1,
+        };
+    }
+}
+
+impl Argon2Config {
+    /// The settings in the form argon2 takes them
+    pub fn params(&self) -> argon2::Config<'static> {
+        return argon2::Config {
+            variant: match self.variant {
+                Argon2Variant::Argon2d => argon2::Variant::Argon2d,
+                Argon2Variant::Argon2i => argon2::Variant::Argon2i,
+                Argon2Variant::Argon2id => argon2::Variant::Argon2id,
+            },
+            mem_cost: self.mem_cost,
+            time_cost: self.time_cost,
+            lanes: self.lanes,
+            ..argon2::Config::default()
+        };
+    }
+
+    /// Checks the costs are within the limits argon2 accepts
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=0x00FF_FFFF).contains(&self.lanes) {
+            return crate::err!("Invalid argon2 config: lanes must be between 1 and {}", 0x00FF_FFFF);
+        }
+        if self.mem_cost < 8 * self.lanes {
+            return crate::err!("Invalid argon2 config: mem_cost must be at least 8 KiB per lane");
+        }
+        if self.time_cost < 1 {
+            return crate::err!("Invalid argon2 config: time_cost must be at least 1");
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Reject passwords that contain the user name
+    pub reject_username: bool,
+    /// Reject passwords found in the bundled list of common passwords
+    pub reject_common: bool,
+    /// Directory of Have I Been Pwned range files (eg `21BD1`) to check
+    /// passwords against
+    pub breached_passwords_dir: Option<String>,
+    /// Number of previous passwords a user may not reuse
+    pub history_size: usize,
+    /// Days after which users must choose a new password
+    pub max_age_days: Option<u32>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        return PasswordPolicy {
+            min_length: 8,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_username: false,
+            reject_common: false,
+            breached_passwords_dir: None,
+            history_size: 0,
+            max_age_days: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+/// Times during which access is allowed. Access is allowed if any window
+/// contains the current time.
+pub struct Schedule {
+    pub timezone: chrono_tz::Tz,
+    pub windows: Vec<AccessWindow>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        return Schedule {
+            timezone: chrono_tz::UTC,
+            windows: Vec::new(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AccessWindow {
+    /// Days on which the window starts (eg "Mon")
+    pub days: Vec<chrono::Weekday>,
+    /// Start time in the schedule's timezone (eg "15:00")
+    pub start: chrono::NaiveTime,
+    /// End time. May be earlier than start for windows that end after
+    /// midnight.
+    pub end: chrono::NaiveTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ScheduleRule {
+    /// Glob pattern of the paths this rule applies to
+    pub path: String,
+    /// Name of a schedule in `schedules`
+    pub schedule: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+/// Address lists are written as CIDR blocks (eg "192.168.1.0/24") or single
+/// addresses
+pub struct NetworkConfig {
+    /// Proxies whose X-Forwarded-For and X-Real-IP headers are believed
+    pub trusted_proxies: Vec<String>,
+    /// If not empty, only these clients may access anything
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    /// Clients that may access anything without logging in
+    pub trusted_networks: Vec<String>,
+    pub path_rules: Vec<NetworkRule>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        return NetworkConfig {
+            trusted_proxies: vec!["127.0.0.1".to_string(), "::1".to_string()],
+            allow: Vec::new(),
+            deny: Vec::new(),
+            trusted_networks: Vec::new(),
+            path_rules: Vec::new(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+/// Address lists that only apply to paths matching a glob pattern. These
+/// are checked in addition to the global lists.
+pub struct NetworkRule {
+    pub path: String,
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    pub trusted_networks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ListenConfig {
+    /// host:port, [ipv6]:port or unix:/path/to/socket
+    pub address: String,
+    /// Permissions of a unix socket file in octal, eg 660
+    pub mode: Option<String>,
+    /// Use X-Real-IP and X-Forwarded-For from clients of this unix socket for
+    /// network rules. Anyone who can connect can claim any address, so `mode`
+    /// decides who is trusted.
+    pub trusted_proxy: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Serve the admin page and RPC API. When false, users are managed by
+    /// editing the user db, which is reloaded when it changes, or with the
+    /// `potato_auth user` commands.
+    pub enabled: bool,
+    /// Addresses that serve the admin page and RPC API instead of the main
+    /// listeners, which then only serve logins and auth requests
+    pub listen: Vec<ListenConfig>,
+    /// Unix socket that serves the API to local admin tools without a login,
+    /// eg `potato_auth session list`. Only the server's user can connect.
+    pub socket: Option<String>,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        return AdminConfig {
+            enabled: true,
+            listen: Vec::new(),
+            socket: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// PEM file with the server certificate followed by any intermediates
+    pub cert: String,
+    /// PEM file with the certificate's private key
+    pub key: String,
+    /// PEM file with the CA certificates of clients allowed to call
+    /// /authrequest. Any client may call it when this is not set.
+    pub client_ca: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct PublicRule {
+    /// Glob pattern matched against the request's host name
+    pub host: String,
+    /// Glob pattern matched against the request's path
+    pub path: String,
+    /// Allowed methods (eg GET). Any method matches if empty.
+    pub methods: Vec<String>,
+}
+
+impl Default for PublicRule {
+    fn default() -> Self {
+        return PublicRule {
+            host: "*".to_string(),
+            path: String::new(),
+            methods: Vec::new(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ThemeConfig {
+    /// Name shown in the page titles and footer
+    pub title: String,
+    /// URL of the footer logo. The built in logo is used if this is not set.
+    pub logo: Option<String>,
+    /// CSS color of buttons, progress bars and the footer
+    pub accent_color: Option<String>,
+    /// CSS color of text on accent_color
+    pub accent_text_color: Option<String>,
+    /// Footer text. title is used if this is not set.
+    pub footer: Option<String>,
+    /// Where the footer links to
+    pub footer_url: String,
+    /// Notice shown above the login form
+    pub message: Option<String>,
+}
+
+impl Default for ThemeConfig {
+    fn default() -> Self {
+        return ThemeConfig {
+            title: "Potato Auth".to_string(),
+            logo: None,
+            accent_color: None,
+            accent_text_color: None,
+            footer: None,
+            footer_url: "https://github.com/sawyersteven/PotatoAuth".to_string(),
+            message: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+/// Settings for delegating logins to an OpenID Connect provider using the
+/// authorization code flow
+pub struct OidcConfig {
+    /// Issuer url, used to find `/.well-known/openid-configuration`
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Public url of PotatoAuth's callback route, as registered with the
+    /// provider (eg https://example.com/potato_auth/login/oidc/callback)
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    /// Userinfo claim used as the PotatoAuth user name. Defaults to `sub`,
+    /// which the provider keeps unique and users can't change.
+    pub username_claim: String,
+    /// Create a user in the db the first time an unknown identity logs in
+    pub auto_provision: bool,
+    /// Paths assigned to auto-provisioned users
+    pub default_paths: Vec<String>,
+}
+
+impl Default for OidcConfig {
+    fn default() -> Self {
+        return OidcConfig {
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            redirect_url: String::new(),
+            scopes: vec!["openid".to_string(), "profile".to_string()],
+            username_claim: "sub".to_string(),
+            auto_provision: false,
+            default_paths: Vec::new(),
         };
     }
 }
 
 impl UserConfig {
+    /// base_path with a leading slash and no trailing slash, or empty when
+    /// PotatoAuth is served from the root
+    pub fn base_path(&self) -> String {
+        let path = self.base_path.as_deref().unwrap_or_default().trim_matches('/');
+        if path.is_empty() {
+            return String::new();
+        }
+        return format!("/{}", path);
+    }
+
+    /// Path the routes are served under: base_path if serve_under_base_path
+    /// is set, otherwise empty since nginx strips base_path
+    pub fn route_prefix(&self) -> String {
+        if self.serve_under_base_path.unwrap_or(false) {
+            return self.base_path();
+        }
+        return String::new();
+    }
+
     /// Builds a UserConfig by merging json file contents then cmd line args
     /// over the default values
     pub fn new(args: &Vec<String>) -> Result<Self> {
@@ -149,6 +593,7 @@ impl UserConfig {
             Ok(c) => conf = c,
             Err(e) => return Err(e),
         };
+        conf.resolve_paths(&filepath);
 
         conf.merge(args);
 
@@ -161,9 +606,29 @@ impl UserConfig {
         return make_dirs_and_write(filepath, json);
     }
 
+    /// Loads the config file at filepath, applies edit, and writes it back.
+    /// Only the file's contents are changed, so values given as command line
+    /// arguments are not saved.
+    pub fn edit_file<F>(filepath: &str, edit: F) -> Result<()>
+    where
+        F: FnOnce(&mut UserConfig),
+    {
+        let mut conf = if file_exists(filepath) {
+            match Self::from_file(filepath) {
+                Ok(c) => c,
+                Err(e) => return Err(e),
+            }
+        } else {
+            UserConfig::default()
+        };
+
+        edit(&mut conf);
+        return conf.write_to_file(filepath);
+    }
+
     /// Deserializes JSON file into new UserConfig. Fields not included in the
     /// text file will use values in Default impl
-    fn from_file(filepath: &str) -> Result<Self> {
+    pub fn from_file(filepath: &str) -> Result<Self> {
         tracing::info!("Loading config from {filepath}");
         let json_string: String;
         match fs::read_to_string(filepath) {
@@ -176,11 +641,101 @@ impl UserConfig {
         return match serde_json::from_str::<UserConfig>(json_string.as_str()) {
             Ok(mut cfg) => {
                 cfg.cfg_path = Some(filepath.to_string());
+                match cfg.validate() {
+                    Ok(_) => {}
+                    Err(e) => return Err(e),
+                }
                 return Ok(cfg);
             }
             Err(e) => Err(crate::Error::convert(e)),
         };
     }
+
+    /// Makes relative paths in the file relative to the file's directory
+    /// instead of the directory the server was started from. Paths given on
+    /// the command line are merged in afterwards and left as they are.
+    fn resolve_paths(&mut self, filepath: &str) {
+        let dir = match Path::new(filepath).parent() {
+            Some(d) => d.to_path_buf(),
+            None => return,
+        };
+        let resolve = |p: &mut String| {
+            if Path::new(p.as_str()).is_relative() {
+                *p = dir.join(p.as_str()).to_string_lossy().to_string();
+            }
+        };
+
+        for p in [&mut self.user_db, &mut self.log_dir, &mut self.asset_dir].into_iter().flatten() {
+            resolve(p);
+        }
+        if let Some(tls) = &mut self.tls {
+            resolve(&mut tls.cert);
+            resolve(&mut tls.key);
+            tls.client_ca.iter_mut().for_each(resolve);
+        }
+        if let Some(socket) = self.admin.as_mut().and_then(|a| a.socket.as_mut()) {
+            resolve(socket);
+        }
+    }
+
+    /// Checks settings that deserialize but can't be used, so a bad file is
+    /// refused when it is loaded instead of failing later
+    fn validate(&self) -> Result<()> {
+        match &self.argon2 {
+            Some(a) => match a.validate() {
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            },
+            None => {}
+        }
+
+        // Names are saved as a field of the user's line in the user db
+        for name in self.schedules.iter().flat_map(|s| s.keys()) {
+            if name.is_empty() || name.contains([':', '\n', '\r']) {
+                return crate::err!("Invalid schedule name `{}`: it may not be empty or contain `:` or line breaks", name.escape_debug());
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+/// Settings for verifying logins with an LDAP simple bind
+pub struct LdapConfig {
+    /// Server url (eg ldap://localhost:389 or ldaps://ldap.example.com)
+    pub url: String,
+    /// DN to bind as. `{username}` is replaced with the escaped login name.
+    pub bind_dn: String,
+    /// Base DN to search for the user's groups. Groups are not looked up if
+    /// this is not set.
+    pub group_base_dn: Option<String>,
+    /// Filter used to find the user's groups. `{dn}` is replaced with the
+    /// escaped bind DN and `{username}` with the escaped login name.
+    pub group_filter: String,
+    /// Group attribute compared against admin_groups and group_paths
+    pub group_attribute: String,
+    /// Members of any of these groups are given Admin accounts
+    pub admin_groups: Vec<String>,
+    /// Paths added for members of each group
+    pub group_paths: HashMap<String, Vec<String>>,
+    /// Paths given to every directory user
+    pub default_paths: Vec<String>,
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        return LdapConfig {
+            url: String::new(),
+            bind_dn: String::new(),
+            group_base_dn: None,
+            group_filter: "(member={dn})".to_string(),
+            group_attribute: "cn".to_string(),
+            admin_groups: Vec::new(),
+            group_paths: HashMap::new(),
+            default_paths: Vec::new(),
+        };
+    }
 }
 
 #[cfg(test)]
@@ -207,6 +762,71 @@ pub mod tests {
         assert_eq!(cfg, cfg2);
     }
 
+    #[test]
+    fn invalid_argon2() {
+        let fp = make_tmp_file();
+        let mut cfg = UserConfig::default();
+        cfg.argon2 = Some(Argon2Config {
+            mem_cost: 4,
+            lanes: 2,
+            ..Default::default()
+        });
+        cfg.write_to_file(&fp).unwrap();
+        assert!(UserConfig::from_file(&fp).is_err());
+
+        cfg.argon2 = Some(Argon2Config {
+            mem_cost: 16,
+            lanes: 2,
+            ..Default::default()
+        });
+        cfg.write_to_file(&fp).unwrap();
+        assert!(UserConfig::from_file(&fp).is_ok());
+
+        // The limits match the ones argon2 checks
+        let no_passes = Argon2Config {
+            time_cost: 0,
+            ..Default::default()
+        };
+        for (argon2, valid) in [(cfg.argon2.clone().unwrap(), true), (no_passes, false)] {
+            assert_eq!(argon2.validate().is_ok(), valid);
+            assert_eq!(argon2::hash_raw(&[], &[0u8; 16], &argon2.params()).is_ok(), valid);
+        }
+    }
+
+    #[test]
+    fn invalid_schedule_name() {
+        let fp = make_tmp_file();
+        for (name, valid) in [("kids", true), ("kids:disabled=true", false), ("kids\nevil", false), ("", false)] {
+            let mut cfg = UserConfig::default();
+            cfg.schedules = Some(HashMap::from([(name.to_string(), Schedule::default())]));
+            cfg.write_to_file(&fp).unwrap();
+            assert_eq!(UserConfig::from_file(&fp).is_ok(), valid, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_schedules() {
+        let cfg: UserConfig = serde_json::from_str(
+            r#"{
+                "schedules": {
+                    "kids": {
+                        "timezone": "America/New_York",
+                        "windows": [{"days": ["Mon", "Fri"], "start": "15:00", "end": "20:30:00"}]
+                    }
+                },
+                "path_schedules": [{"path": "/games/**", "schedule": "kids"}]
+            }"#,
+        )
+        .unwrap();
+
+        let kids = &cfg.schedules.unwrap()["kids"];
+        assert_eq!(kids.timezone, chrono_tz::America::New_York);
+        assert_eq!(kids.windows[0].days, vec![chrono::Weekday::Mon, chrono::Weekday::Fri]);
+        assert_eq!(kids.windows[0].start, chrono::NaiveTime::from_hms_opt(15, 0, 0).unwrap());
+        assert_eq!(kids.windows[0].end, chrono::NaiveTime::from_hms_opt(20, 30, 0).unwrap());
+        assert_eq!(cfg.path_schedules.unwrap()[0].schedule, "kids");
+    }
+
     #[test]
     fn from_args() {
         let args = [
@@ -257,8 +877,46 @@ pub mod tests {
         assert_eq!(merged_cfg.port, Some(4242));
         assert_eq!(merged_cfg.session_timeout, Some(9999));
         assert_eq!(merged_cfg.user_db, file_cfg.user_db);
-        assert_eq!(merged_cfg.log_dir, Some("a_log_dir".to_string()));
+        // Relative to the config file
+        let dir = Path::new(&fp).parent().unwrap();
+        assert_eq!(merged_cfg.log_dir, Some(dir.join("a_log_dir").to_string_lossy().to_string()));
         assert_eq!(merged_cfg.cfg_path, Some(f.to_string()));
         assert_eq!(merged_cfg.console, false);
     }
+
+    #[test]
+    fn relative_paths() {
+        let fp = make_tmp_file();
+        let dir = Path::new(&fp).parent().unwrap();
+        let in_dir = |p: &str| dir.join(p).to_string_lossy().to_string();
+
+        let mut file_cfg = UserConfig::default();
+        file_cfg.user_db = Some("users.db".to_string());
+        file_cfg.asset_dir = Some("/etc/PotatoAuth/assets".to_string());
+        file_cfg.tls = Some(TlsConfig {
+            cert: "tls/cert.pem".to_string(),
+            key: "tls/key.pem".to_string(),
+            client_ca: Some("tls/ca.pem".to_string()),
+        });
+        file_cfg.admin = Some(AdminConfig {
+            socket: Some("admin.socket".to_string()),
+            ..Default::default()
+        });
+        file_cfg.write_to_file(&fp).unwrap();
+
+        let cfg = UserConfig::new(&vec!["_".to_string(), "-c".to_string(), fp.clone()]).unwrap();
+        assert_eq!(cfg.user_db, Some(in_dir("users.db")));
+        assert_eq!(cfg.asset_dir, Some("/etc/PotatoAuth/assets".to_string()));
+        let tls = cfg.tls.unwrap();
+        assert_eq!((tls.cert, tls.key), (in_dir("tls/cert.pem"), in_dir("tls/key.pem")));
+        assert_eq!(tls.client_ca, Some(in_dir("tls/ca.pem")));
+        assert_eq!(cfg.admin.unwrap().socket, Some(in_dir("admin.socket")));
+
+        // Command line paths are relative to where the command was run
+        let args = ["_", "-c", &fp, "-u", "users.db"].iter().map(|x| x.to_string()).collect();
+        assert_eq!(UserConfig::new(&args).unwrap().user_db, Some("users.db".to_string()));
+
+        // The file itself is left as it was
+        assert_eq!(UserConfig::from_file(&fp).unwrap().user_db, Some("users.db".to_string()));
+    }
 }
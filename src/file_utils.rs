@@ -19,15 +19,19 @@ where
 }
 
 // Attempts to create directory tree and write contents to file
+// The contents are written to a temporary file first and renamed over
+// filepath so a reader never sees a partly written file
 pub fn make_dirs_and_write<P, C>(filepath: P, contents: C) -> Result<()>
 where
     P: AsRef<Path> + ToString,
     C: AsRef<[u8]>,
 {
     let filepath = filepath.as_ref();
+    let mut tmp_path = filepath.as_os_str().to_owned();
+    tmp_path.push(".tmp");
     match filepath.parent() {
         Some(dir) => match fs::create_dir_all(dir) {
-            Ok(_) => match fs::write(filepath, contents) {
+            Ok(_) => match fs::write(&tmp_path, contents).and_then(|_| fs::rename(&tmp_path, filepath)) {
                 Ok(_) => Ok(()),
                 Err(e) => {
                     return Err(Error::convert(e));
@@ -43,6 +47,26 @@ where
     }
 }
 
+/// Exclusive lock on a file, released when dropped
+pub struct FileLock {
+    _file: fs::File,
+}
+
+/// Waits for an exclusive lock on filepath, shared with other processes.
+/// Files are replaced by renaming, so the lock is held on a separate
+/// `<filepath>.lock` file that stays put.
+pub fn lock_file(filepath: &str) -> Result<FileLock> {
+    let lock_path = format!("{}.lock", filepath);
+    let file = match fs::OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path) {
+        Ok(f) => f,
+        Err(e) => return Err(Error::new(format!("Unable to open {}: {}", lock_path, e))),
+    };
+    return match file.lock() {
+        Ok(_) => Ok(FileLock { _file: file }),
+        Err(e) => Err(Error::new(format!("Unable to lock {}: {}", lock_path, e))),
+    };
+}
+
 pub fn file_exists<S>(filepath: &S) -> bool
 where
     S: AsRef<std::ffi::OsStr> + ?Sized,
@@ -0,0 +1,216 @@
+/*
+Support for Apache htpasswd files so existing nginx `auth_basic` users can be
+moved over without resetting their passwords. Hashes imported from htpasswd
+files are verified here and replaced with argon2 hashes on the user's next
+successful login.
+*/
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use constant_time_eq::constant_time_eq;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+const APR1_PREFIX: &str = "$apr1$";
+const SHA_PREFIX: &str = "{SHA}";
+const BCRYPT_PREFIXES: [&str; 3] = ["$2y$", "$2a$", "$2b$"];
+/// Alphabet of the salts and digests of apr1 and bcrypt hashes
+const ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Splits htpasswd content into (name, hash) pairs. Any fields after the hash
+/// are ignored. Blank lines and comments are skipped; malformed lines are
+/// returned as Err with their line number.
+pub fn parse(contents: &str) -> Vec<Result<(String, String), usize>> {
+    let mut entries = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        match (fields.next(), fields.next()) {
+            (Some(name), Some(hash)) if !name.is_empty() && !hash.is_empty() => {
+                entries.push(Ok((name.to_string(), hash.to_string())));
+            }
+            _ => entries.push(Err(i + 1)),
+        }
+    }
+    return entries;
+}
+
+/// Returns true if hash is a well-formed hash in one of the htpasswd formats
+/// that can be verified
+pub fn is_supported(hash: &str) -> bool {
+    let is_itoa64 = |s: &str| s.bytes().all(|b| ITOA64.contains(&b));
+
+    if BCRYPT_PREFIXES.iter().any(|p| hash.starts_with(p)) {
+        return hash.parse::<bcrypt::HashParts>().is_ok() && hash.rsplit('$').next().is_some_and(is_itoa64);
+    }
+
+    if let Some(digest) = hash.strip_prefix(SHA_PREFIX) {
+        return matches!(STANDARD.decode(digest), Ok(d) if d.len() == 20);
+    }
+
+    if let Some(rest) = hash.strip_prefix(APR1_PREFIX) {
+        return match rest.split_once('$') {
+            Some((salt, digest)) => {
+                (1..=8).contains(&salt.len()) && digest.len() == 22 && is_itoa64(salt) && is_itoa64(digest)
+            }
+            None => false,
+        };
+    }
+
+    return false;
+}
+
+/// Checks plain_password against an htpasswd hash. Unsupported formats never
+/// match.
+pub fn verify(hash: &str, plain_password: &str) -> bool {
+    if !is_supported(hash) {
+        return false;
+    }
+
+    if BCRYPT_PREFIXES.iter().any(|p| hash.starts_with(p)) {
+        return match bcrypt::verify(plain_password, hash) {
+            Ok(m) => m,
+            Err(e) => {
+                tracing::error!("Could not compare password and bcrypt hash: {}", e);
+                false
+            }
+        };
+    }
+
+    if let Some(digest) = hash.strip_prefix(SHA_PREFIX) {
+        let computed = STANDARD.encode(Sha1::digest(plain_password.as_bytes()));
+        return constant_time_eq(computed.as_bytes(), digest.as_bytes());
+    }
+
+    if let Some(rest) = hash.strip_prefix(APR1_PREFIX) {
+        let salt = match rest.split_once('$') {
+            Some((s, _)) => s,
+            None => return false,
+        };
+        return constant_time_eq(
+            apr1(plain_password.as_bytes(), salt.as_bytes()).as_bytes(),
+            hash.as_bytes(),
+        );
+    }
+
+    return false;
+}
+
+/// Apache's variant of the md5-crypt algorithm
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let salt = &salt[..salt.len().min(8)];
+
+    let alt = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+
+    let mut ctx = Md5::new()
+        .chain_update(password)
+        .chain_update(APR1_PREFIX)
+        .chain_update(salt);
+
+    let mut remaining = password.len();
+    while remaining > 0 {
+        let n = remaining.min(16);
+        ctx.update(&alt[..n]);
+        remaining -= n;
+    }
+
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            ctx.update([0u8]);
+        } else {
+            ctx.update(&password[..1]);
+        }
+        i >>= 1;
+    }
+
+    let mut digest = ctx.finalize();
+
+    for round in 0..1000 {
+        let mut ctx = Md5::new();
+        if round & 1 == 1 {
+            ctx.update(password);
+        } else {
+            ctx.update(digest);
+        }
+        if round % 3 != 0 {
+            ctx.update(salt);
+        }
+        if round % 7 != 0 {
+            ctx.update(password);
+        }
+        if round & 1 == 1 {
+            ctx.update(digest);
+        } else {
+            ctx.update(password);
+        }
+        digest = ctx.finalize();
+    }
+
+    let mut encoded = String::new();
+    let mut push = |mut v: u32, n: usize| {
+        for _ in 0..n {
+            encoded.push(ITOA64[(v & 0x3f) as usize] as char);
+            v >>= 6;
+        }
+    };
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(
+            ((digest[a] as u32) << 16) | ((digest[b] as u32) << 8) | digest[c] as u32,
+            4,
+        );
+    }
+    push(digest[11] as u32, 2);
+
+    return format!("{}{}${}", APR1_PREFIX, String::from_utf8_lossy(salt), encoded);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lines() {
+        let entries = parse("# comment\nalice:{SHA}abc\n\nbad_line\nbob:$apr1$a$b:*:Admin\n:{SHA}abc\n");
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0], Ok(("alice".to_string(), "{SHA}abc".to_string())));
+        assert_eq!(entries[1], Err(4));
+        // Extra fields never become part of the hash
+        assert_eq!(entries[2], Ok(("bob".to_string(), "$apr1$a$b".to_string())));
+        assert_eq!(entries[3], Err(6));
+    }
+
+    #[test]
+    fn verify_formats() {
+        // Reference hashes from `openssl passwd -apr1` and `openssl sha1`
+        assert!(verify("$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1", "password"));
+        assert!(verify("$apr1$r31.....$HqJZimcKQFAMYayBlzkrA/", "myPassword"));
+        assert!(!verify("$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1", "Password"));
+
+        assert!(verify("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", "password"));
+        assert!(!verify("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=", "password1"));
+
+        let bcrypt_hash = bcrypt::hash("password", 4).unwrap();
+        assert!(is_supported(&bcrypt_hash));
+        assert!(verify(&bcrypt_hash, "password"));
+        assert!(!verify(&bcrypt_hash, "password1"));
+
+        assert!(!is_supported("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=:*:Admin"));
+        assert!(!is_supported("$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1\nevil"));
+        assert!(!is_supported(&format!(
+            "{}:*:Admin",
+            &bcrypt_hash[..bcrypt_hash.len() - 8]
+        )));
+        assert!(!verify("{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=:*:Admin", "password"));
+
+        // crypt(3) DES hashes are not supported
+        assert!(!is_supported("rOHb0pyx4yR6c"));
+        assert!(!verify("rOHb0pyx4yR6c", "password"));
+    }
+}
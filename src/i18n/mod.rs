@@ -0,0 +1,229 @@
+/*
+Translations of the login and admin pages and of the messages returned by the
+RPC endpoints.
+
+Every message has a stable code, eg `password.too_short`, which is looked up in
+one of the catalogs bundled from this directory. A catalog is a flat JSON object
+of code -> text, and `{name}` in a text is replaced by the message argument of
+the same name. Codes missing from a catalog fall back to English.
+
+The language is chosen from the request's Accept-Language header.
+*/
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::OnceLock,
+};
+
+use actix_web::{http::header::ACCEPT_LANGUAGE, HttpRequest};
+use serde::Serialize;
+
+use crate::Error;
+
+/// Language tag and catalog of every bundled language. The first is the
+/// default and the fallback for missing codes.
+const CATALOGS: &[(&str, &str)] = &[
+    ("en", include_str!("en.json")),
+    ("de", include_str!("de.json")),
+    ("fr", include_str!("fr.json")),
+];
+
+/// Code of messages that are only English text, eg I/O errors
+pub const OTHER_ERROR: &str = "error.other";
+
+/// A translatable message. Clients can use the code and args to show it in
+/// their own words.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Message {
+    pub code: &'static str,
+    /// Request field the message is about
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<&'static str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<&'static str, String>,
+}
+
+impl Message {
+    pub fn new(code: &'static str) -> Self {
+        return Message {
+            code,
+            field: None,
+            args: BTreeMap::new(),
+        };
+    }
+
+    pub fn field(mut self, field: &'static str) -> Self {
+        self.field = Some(field);
+        return self;
+    }
+
+    pub fn arg<T>(mut self, name: &'static str, value: T) -> Self
+    where
+        T: ToString,
+    {
+        self.args.insert(name, value.to_string());
+        return self;
+    }
+}
+
+/// English text of the message
+impl Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", default_locale().format(self))
+    }
+}
+
+pub struct Locale {
+    pub tag: &'static str,
+    messages: HashMap<String, String>,
+}
+
+impl Locale {
+    /// Text for code, falling back to English and then to the code itself
+    pub fn text<'a>(&'a self, code: &'a str) -> &'a str {
+        return match self.messages.get(code) {
+            Some(t) => t,
+            None => match default_locale().messages.get(code) {
+                Some(t) => t,
+                None => code,
+            },
+        };
+    }
+
+    pub fn format(&self, message: &Message) -> String {
+        let mut text = self.text(message.code).to_string();
+        for (name, value) in &message.args {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        return text;
+    }
+
+    /// Every message of an error joined into one line
+    pub fn error(&self, error: &Error) -> String {
+        return error
+            .messages()
+            .iter()
+            .map(|m| self.format(m))
+            .collect::<Vec<String>>()
+            .join("; ");
+    }
+
+    /// The whole catalog as a JSON object, with English filling in missing
+    /// codes. Safe to put in a <script> element.
+    pub fn to_json(&self) -> String {
+        let mut all: BTreeMap<&str, &str> = BTreeMap::new();
+        for (k, v) in default_locale().messages.iter().chain(self.messages.iter()) {
+            all.insert(k, v);
+        }
+        return serde_json::to_string(&all)
+            .unwrap_or_else(|_| "{}".to_string())
+            .replace('<', "\\u003c");
+    }
+}
+
+fn locales() -> &'static Vec<Locale> {
+    static LOCALES: OnceLock<Vec<Locale>> = OnceLock::new();
+    return LOCALES.get_or_init(|| {
+        CATALOGS
+            .iter()
+            .map(|(tag, json)| Locale {
+                tag,
+                messages: serde_json::from_str(json).expect("bundled catalogs are valid JSON"),
+            })
+            .collect()
+    });
+}
+
+pub fn default_locale() -> &'static Locale {
+    return &locales()[0];
+}
+
+/// Picks the best bundled language for an Accept-Language header, eg
+/// `de-CH, fr;q=0.8, *;q=0.1`
+pub fn negotiate(accept_language: Option<&str>) -> &'static Locale {
+    let header = match accept_language {
+        Some(h) => h,
+        None => return default_locale(),
+    };
+
+    let mut ranges: Vec<(&str, f32)> = header
+        .split(',')
+        .filter_map(|r| {
+            let mut parts = r.split(';');
+            let tag = parts.next()?.trim();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+            if tag.is_empty() || q <= 0.0 {
+                return None;
+            }
+            return Some((tag, q));
+        })
+        .collect();
+    // Stable, so equal weights keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    for (tag, _) in ranges {
+        if tag == "*" {
+            return default_locale();
+        }
+        let primary = tag.split('-').next().unwrap_or(tag);
+        match locales().iter().find(|l| l.tag.eq_ignore_ascii_case(primary)) {
+            Some(l) => return l,
+            None => {}
+        }
+    }
+    return default_locale();
+}
+
+pub fn from_request(req: &HttpRequest) -> &'static Locale {
+    return negotiate(req.headers().get(ACCEPT_LANGUAGE).and_then(|h| h.to_str().ok()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_language() {
+        assert_eq!(negotiate(None).tag, "en");
+        assert_eq!(negotiate(Some("de")).tag, "de");
+        assert_eq!(negotiate(Some("de-CH, fr;q=0.8")).tag, "de");
+        assert_eq!(negotiate(Some("es, fr;q=0.5, de;q=0.7")).tag, "de");
+        assert_eq!(negotiate(Some("fr-CA;q=0.9, DE;q=0")).tag, "fr");
+        assert_eq!(negotiate(Some("es, *;q=0.1, de;q=0.01")).tag, "en");
+        assert_eq!(negotiate(Some(";;, q=bad")).tag, "en");
+    }
+
+    #[test]
+    fn catalogs_complete() {
+        for locale in locales() {
+            for (code, text) in &default_locale().messages {
+                let translated = match locale.messages.get(code) {
+                    Some(t) => t,
+                    None => panic!("{} is missing {}", locale.tag, code),
+                };
+                // Every argument of the English text is used by the translation
+                text.split('{')
+                    .skip(1)
+                    .filter_map(|s| s.split_once('}'))
+                    .for_each(|(arg, _)| {
+                        assert!(translated.contains(&format!("{{{}}}", arg)), "{} {}", locale.tag, code)
+                    });
+            }
+        }
+    }
+
+    #[test]
+    fn format_message() {
+        let msg = Message::new("password.too_short").arg("min", 8);
+        assert_eq!(msg.to_string(), "Password too short (minimum 8 characters)");
+        assert_eq!(
+            negotiate(Some("de")).format(&msg),
+            "Passwort zu kurz (mindestens 8 Zeichen)"
+        );
+        assert_eq!(default_locale().text("not.a.code"), "not.a.code");
+        assert!(!default_locale().to_json().contains('<'));
+    }
+}
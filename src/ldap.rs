@@ -0,0 +1,290 @@
+use ldap3::{dn_escape, ldap_escape, LdapConnAsync, Scope, SearchEntry};
+
+use crate::{config::LdapConfig, shared_data::Sharable, userdb::AcctType, Error, Result};
+
+/// LDAP result code for a failed bind
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Account details granted to a directory user based on their groups
+#[derive(Debug, PartialEq)]
+pub struct LdapIdentity {
+    pub acct_type: AcctType,
+    pub paths: Vec<String>,
+}
+
+pub struct LdapAuthenticator {
+    cfg: LdapConfig,
+}
+
+impl LdapAuthenticator {
+    pub fn new(cfg: LdapConfig) -> Self {
+        return LdapAuthenticator { cfg };
+    }
+
+    /// Binds to the directory as the user. Returns None if the directory
+    /// rejects the credentials, or an error if the directory could not be
+    /// reached.
+    pub async fn authenticate(&self, name: &str, password: &str) -> Result<Option<LdapIdentity>> {
+        // An empty password is an unauthenticated bind, which most servers
+        // report as a success
+        if name.is_empty() || password.is_empty() {
+            return Ok(None);
+        }
+
+        let (conn, mut ldap) = match LdapConnAsync::new(&self.cfg.url).await {
+            Ok(c) => c,
+            Err(e) => return crate::err!("Unable to connect to {}: {}", self.cfg.url, e),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                tracing::warn!("LDAP connection error: {}", e);
+            }
+        });
+
+        let dn = self.cfg.bind_dn.replace("{username}", &dn_escape(name));
+
+        let bind = match ldap.simple_bind(&dn, password).await {
+            Ok(r) => r,
+            Err(e) => return Err(Error::convert(e)),
+        };
+        if bind.rc == INVALID_CREDENTIALS {
+            _ = ldap.unbind().await;
+            return Ok(None);
+        }
+        match bind.success() {
+            Ok(_) => {}
+            Err(e) => return Err(Error::convert(e)),
+        }
+
+        let mut groups: Vec<String> = Vec::new();
+        if let Some(base) = &self.cfg.group_base_dn {
+            let filter = self
+                .cfg
+                .group_filter
+                .replace("{dn}", &ldap_escape(&dn))
+                .replace("{username}", &ldap_escape(name));
+
+            let entries = match ldap
+                .search(base, Scope::Subtree, &filter, vec![self.cfg.group_attribute.as_str()])
+                .await
+                .and_then(|r| r.success())
+            {
+                Ok((entries, _)) => entries,
+                Err(e) => {
+                    _ = ldap.unbind().await;
+                    return crate::err!("Group search for {} failed: {}", name, e);
+                }
+            };
+
+            for entry in entries {
+                let mut entry = SearchEntry::construct(entry);
+                match entry.attrs.remove(&self.cfg.group_attribute) {
+                    Some(vals) => groups.extend(vals),
+                    None => {}
+                }
+            }
+        }
+        _ = ldap.unbind().await;
+
+        return Ok(Some(self.identity_for_groups(&groups)));
+    }
+
+    fn identity_for_groups(&self, groups: &Vec<String>) -> LdapIdentity {
+        let mut paths = self.cfg.default_paths.clone();
+        let mut acct_type = AcctType::User;
+
+        for group in groups {
+            if self.cfg.admin_groups.contains(group) {
+                acct_type = AcctType::Admin;
+            }
+            for path in self.cfg.group_paths.get(group).into_iter().flatten() {
+                if !paths.contains(path) {
+                    paths.push(path.to_owned());
+                }
+            }
+        }
+
+        return LdapIdentity { acct_type, paths };
+    }
+}
+
+impl Sharable for LdapAuthenticator {
+    type Shared = LdapAuthenticator;
+
+    fn to_sharable(self) -> actix_web::web::Data<Self::Shared> {
+        return actix_web::web::Data::new(self);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::collections::HashMap;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Returns (tag, contents, remainder) of the first BER element in buf
+    fn read_tlv(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        if buf.len() < 2 {
+            return None;
+        }
+        let (len, hdr) = if buf[1] < 0x80 {
+            (buf[1] as usize, 2)
+        } else {
+            let n = (buf[1] & 0x7f) as usize;
+            if buf.len() < 2 + n {
+                return None;
+            }
+            (
+                buf[2..2 + n].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize),
+                2 + n,
+            )
+        };
+        if buf.len() < hdr + len {
+            return None;
+        }
+        return Some((buf[0], &buf[hdr..hdr + len], &buf[hdr + len..]));
+    }
+
+    fn tlv(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if contents.len() < 0x80 {
+            out.push(contents.len() as u8);
+        } else {
+            out.push(0x82);
+            out.extend((contents.len() as u16).to_be_bytes());
+        }
+        out.extend(contents);
+        return out;
+    }
+
+    fn ldap_result(tag: u8, rc: u8) -> Vec<u8> {
+        return tlv(tag, &[&[0x0a, 0x01, rc][..], &tlv(0x04, b""), &tlv(0x04, b"")].concat());
+    }
+
+    /// Starts a tiny LDAP server that understands simple binds and answers
+    /// every search with the groups of the last bound DN.
+    /// Users are `uid=<name>,ou=people,dc=test` with password `<name>_pass`;
+    /// `alice` is in `media` and `admins`, `bob` is in no groups.
+    pub fn start_mock_directory(port: u16) {
+        let groups: HashMap<&str, Vec<&str>> = HashMap::from([
+            ("uid=alice,ou=people,dc=test", vec!["media", "admins"]),
+            ("uid=bob,ou=people,dc=test", vec![]),
+        ]);
+
+        let listener = std::net::TcpListener::bind(("localhost", port)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+
+        tokio::spawn(async move {
+            let listener = TcpListener::from_std(listener).unwrap();
+            loop {
+                let (mut sock, _) = listener.accept().await.unwrap();
+                let groups = groups.clone();
+                tokio::spawn(async move {
+                    let mut buf: Vec<u8> = Vec::new();
+                    let mut bound_dn = String::new();
+                    loop {
+                        let mut chunk = [0u8; 1024];
+                        match sock.read(&mut chunk).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => buf.extend(&chunk[..n]),
+                        }
+
+                        while let Some((_, msg, rest)) = read_tlv(&buf) {
+                            let (_, id, op) = read_tlv(msg).unwrap();
+                            let id = tlv(0x02, id);
+                            let (op_tag, op_body, _) = read_tlv(op).unwrap();
+                            let mut reply: Vec<u8> = Vec::new();
+                            match op_tag {
+                                // Bind
+                                0x60 => {
+                                    let (_, _, rest) = read_tlv(op_body).unwrap();
+                                    let (_, dn, rest) = read_tlv(rest).unwrap();
+                                    let (_, pw, _) = read_tlv(rest).unwrap();
+                                    let dn = String::from_utf8_lossy(dn).to_string();
+                                    let pw = String::from_utf8_lossy(pw).to_string();
+                                    let uid = dn.trim_start_matches("uid=").split(',').next().unwrap().to_string();
+                                    let rc = if groups.contains_key(dn.as_str()) && pw == format!("{}_pass", uid) {
+                                        bound_dn = dn;
+                                        0
+                                    } else {
+                                        49
+                                    };
+                                    reply.extend(tlv(0x30, &[id.clone(), ldap_result(0x61, rc)].concat()));
+                                }
+                                // Search
+                                0x63 => {
+                                    for g in groups.get(bound_dn.as_str()).unwrap() {
+                                        let attr = tlv(
+                                            0x30,
+                                            &[tlv(0x04, b"cn"), tlv(0x31, &tlv(0x04, g.as_bytes()))].concat(),
+                                        );
+                                        let entry = tlv(
+                                            0x64,
+                                            &[
+                                                tlv(0x04, format!("cn={},ou=groups,dc=test", g).as_bytes()),
+                                                tlv(0x30, &attr),
+                                            ]
+                                            .concat(),
+                                        );
+                                        reply.extend(tlv(0x30, &[id.clone(), entry].concat()));
+                                    }
+                                    reply.extend(tlv(0x30, &[id.clone(), ldap_result(0x65, 0)].concat()));
+                                }
+                                // Unbind
+                                _ => return,
+                            }
+                            _ = sock.write_all(&reply).await;
+                            buf = rest.to_vec();
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn mock_directory_config(port: u16) -> LdapConfig {
+        return LdapConfig {
+            url: format!("ldap://localhost:{}", port),
+            bind_dn: "uid={username},ou=people,dc=test".to_string(),
+            group_base_dn: Some("ou=groups,dc=test".to_string()),
+            admin_groups: vec!["admins".to_string()],
+            group_paths: HashMap::from([("media".to_string(), vec!["/media/**".to_string()])]),
+            default_paths: vec!["/public/**".to_string()],
+            ..Default::default()
+        };
+    }
+
+    #[tokio::test]
+    async fn authenticate() {
+        const PORT: u16 = 8661;
+        start_mock_directory(PORT);
+        let ldap = LdapAuthenticator::new(mock_directory_config(PORT));
+
+        assert_eq!(
+            ldap.authenticate("alice", "alice_pass").await.unwrap(),
+            Some(LdapIdentity {
+                acct_type: AcctType::Admin,
+                paths: vec!["/public/**".to_string(), "/media/**".to_string()]
+            })
+        );
+        assert_eq!(
+            ldap.authenticate("bob", "bob_pass").await.unwrap(),
+            Some(LdapIdentity {
+                acct_type: AcctType::User,
+                paths: vec!["/public/**".to_string()]
+            })
+        );
+
+        assert_eq!(ldap.authenticate("alice", "bob_pass").await.unwrap(), None);
+        assert_eq!(ldap.authenticate("alice", "").await.unwrap(), None);
+        assert_eq!(ldap.authenticate("carol", "carol_pass").await.unwrap(), None);
+
+        let unreachable = LdapAuthenticator::new(mock_directory_config(1));
+        assert!(unreachable.authenticate("alice", "alice_pass").await.is_err());
+    }
+}
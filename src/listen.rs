@@ -0,0 +1,202 @@
+/*
+Sockets the server accepts connections on. These are the `listen` entries of
+the config, or `address` and `port` when there are none. The admin page can be given its own
+sockets with `admin.listen`, and local admin tools a unix socket with
+`admin.socket`.
+*/
+
+use std::{fmt::Display, net::TcpListener};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+use crate::{
+    config::{ListenConfig, UserConfig},
+    file_utils::{file_exists, make_parent_dirs},
+    Error, Result,
+};
+
+/// Prefix of listen addresses that are unix socket paths
+pub const UNIX_PREFIX: &str = "unix:";
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// Set for sockets whose clients may forward the address of the client
+    /// they proxy for
+    #[cfg(unix)]
+    Unix(UnixListener, bool),
+}
+
+impl Listener {
+    /// Path of a unix socket file
+    pub fn socket_path(&self) -> Option<std::path::PathBuf> {
+        return match self {
+            Listener::Tcp(_) => None,
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.local_addr().ok()?.as_pathname().map(|p| p.to_path_buf()),
+        };
+    }
+}
+
+impl Display for Listener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            Listener::Tcp(l) => match l.local_addr() {
+                Ok(a) => write!(f, "{}", a),
+                Err(_) => write!(f, "unknown address"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(l, _) => match self.socket_path() {
+                Some(p) => write!(f, "{}{}", UNIX_PREFIX, p.display()),
+                None => write!(f, "{}{:?}", UNIX_PREFIX, l.local_addr()),
+            },
+        };
+    }
+}
+
+/// Opens every socket the server should accept connections on
+pub fn bind(cfg: &UserConfig) -> Result<Vec<Listener>> {
+    let configs = match &cfg.listen {
+        Some(l) if !l.is_empty() => l.clone(),
+        _ => vec![ListenConfig {
+            address: format!("{}:{}", cfg.address.as_ref().unwrap(), cfg.port.as_ref().unwrap()),
+            ..Default::default()
+        }],
+    };
+
+    return bind_all(&configs);
+}
+
+/// Opens a socket for each config
+pub fn bind_all(configs: &[ListenConfig]) -> Result<Vec<Listener>> {
+    let mut listeners = Vec::with_capacity(configs.len());
+    for c in configs {
+        match bind_one(c) {
+            Ok(l) => listeners.push(l),
+            Err(e) => return Err(Error::new(format!("Unable to listen on {}: {}", c.address, e))),
+        }
+    }
+    return Ok(listeners);
+}
+
+/// Opens `admin.socket`, which only the server's user may connect to
+pub fn bind_admin_socket(path: &str) -> Result<Listener> {
+    let cfg = ListenConfig {
+        address: format!("{}{}", UNIX_PREFIX, path),
+        mode: Some("600".to_string()),
+        ..Default::default()
+    };
+    return match bind_one(&cfg) {
+        Ok(l) => Ok(l),
+        Err(e) => Err(Error::new(format!("Unable to listen on {}: {}", path, e))),
+    };
+}
+
+fn bind_one(cfg: &ListenConfig) -> Result<Listener> {
+    if let Some(path) = cfg.address.strip_prefix(UNIX_PREFIX) {
+        return bind_unix(path, cfg.mode.as_deref(), cfg.trusted_proxy);
+    }
+
+    if cfg.mode.is_some() {
+        tracing::warn!("`mode` only applies to unix sockets and is ignored for {}", cfg.address);
+    }
+    if cfg.trusted_proxy {
+        tracing::warn!(
+            "`trusted_proxy` only applies to unix sockets and is ignored for {}. Use network.trusted_proxies instead.",
+            cfg.address
+        );
+    }
+    return match TcpListener::bind(cfg.address.as_str()) {
+        Ok(l) => Ok(Listener::Tcp(l)),
+        Err(e) => Err(Error::convert(e)),
+    };
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str, mode: Option<&str>, proxy: bool) -> Result<Listener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    let mode = match mode.map(|m| u32::from_str_radix(m, 8)) {
+        Some(Ok(m)) => Some(m),
+        Some(Err(_)) => return crate::err!("Socket mode must be an octal number such as 660"),
+        None => None,
+    };
+
+    // A socket left behind by an earlier run would make bind fail
+    if file_exists(path) {
+        match std::fs::symlink_metadata(path) {
+            Ok(m) if m.file_type().is_socket() => _ = std::fs::remove_file(path),
+            _ => return crate::err!("{} exists and is not a socket", path),
+        }
+    }
+    match make_parent_dirs(path) {
+        Ok(_) => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = match UnixListener::bind(path) {
+        Ok(l) => l,
+        Err(e) => return Err(Error::convert(e)),
+    };
+    if let Some(m) = mode {
+        match std::fs::set_permissions(path, std::fs::Permissions::from_mode(m)) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::convert(e)),
+        }
+    }
+    return Ok(Listener::Unix(listener, proxy));
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &str, _mode: Option<&str>, _proxy: bool) -> Result<Listener> {
+    return crate::err!("Unix sockets are not supported on this platform");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::make_tmp_file;
+
+    fn listen_config(address: &str, mode: Option<&str>) -> ListenConfig {
+        return ListenConfig {
+            address: address.to_string(),
+            mode: mode.map(|m| m.to_string()),
+            ..Default::default()
+        };
+    }
+
+    #[test]
+    fn bind_listeners() {
+        let mut cfg = UserConfig::default();
+        cfg.listen = Some(vec![listen_config("127.0.0.1:0", None), listen_config("[::1]:0", None)]);
+        let listeners = bind(&cfg).unwrap();
+        assert_eq!(listeners.len(), 2);
+        assert!(listeners[1].to_string().starts_with("[::1]:"));
+
+        cfg.listen = Some(vec![listen_config("not an address", None)]);
+        assert!(bind(&cfg).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Replaces the stale socket a previous run would leave behind
+        let path = make_tmp_file();
+        std::fs::remove_file(&path).unwrap();
+        drop(bind_one(&listen_config(&format!("unix:{}", path), None)).unwrap());
+
+        let listener = bind_one(&listen_config(&format!("unix:{}", path), Some("660"))).unwrap();
+        assert_eq!(listener.to_string(), format!("unix:{}", path));
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        assert!(bind_one(&listen_config(&format!("unix:{}", path), Some("rw"))).is_err());
+
+        // Never replaces other files
+        let file = make_tmp_file();
+        assert!(bind_one(&listen_config(&format!("unix:{}", file), None)).is_err());
+        assert!(file_exists(&file));
+    }
+}
@@ -78,4 +78,11 @@ impl Logging {
 
         self.rolling_file_handle.modify(|l| *l = Some(file_layer)).unwrap();
     }
+
+    /// Writes out any log lines still buffered for the log file. Nothing is
+    /// written to the file afterwards.
+    pub fn flush(&mut self) {
+        // Dropping the guard waits for the writer thread to finish
+        self.file_write_guard.take();
+    }
 }
@@ -5,29 +5,35 @@ use config::UserConfig;
 
 use crate::logging::Logging;
 
+mod access;
 mod app;
+mod assets;
+mod cli;
 mod config;
 mod file_utils;
+mod htpasswd;
+mod i18n;
+mod ldap;
+mod listen;
 mod logging;
 mod middleware;
+mod oidc;
+mod pages;
+mod password_policy;
+mod reload;
 mod routes;
 mod sessions;
 mod shared_data;
+mod signals;
+mod systemd;
+mod tls;
+mod transfer;
 mod userdb;
 
 const APP_NAME: &str = "PotatoAuth";
 
 #[tokio::main]
 async fn main() {
-    let bin_path = std::env::current_exe().expect("Can't get path to binary");
-    match std::env::set_current_dir(&bin_path.parent().unwrap()) {
-        Ok(_) => {}
-        Err(e) => {
-            println!("{}", e);
-            std::process::exit(1);
-        }
-    }
-
     let mut l = Logging::new();
     let args: Vec<String> = std::env::args().map(|x| x.to_string()).collect();
     let cfg = match UserConfig::new(&args) {
@@ -38,6 +44,16 @@ async fn main() {
         }
     };
 
+    if let Some(command) = cfg.command.clone() {
+        match cli::run(command, &cfg) {
+            Ok(_) => std::process::exit(0),
+            Err(e) => {
+                tracing::error!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
     l.start_file_log(
         cfg.log_dir.as_ref().unwrap(),
         *cfg.log_archive_count.as_ref().unwrap(),
@@ -45,6 +61,7 @@ async fn main() {
     );
 
     app::run_server(cfg).await;
+    l.flush();
 }
 
 /*
@@ -61,9 +78,22 @@ macro_rules! err {
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Each kind of error has a stable code and HTTP status, and is described by
+/// translatable messages. See i18n.
 #[derive(Debug)]
-pub struct Error {
-    message: String,
+pub enum Error {
+    /// Input that failed validation, with one message per problem. Messages
+    /// name the field they are about where there is one.
+    Invalid(Vec<i18n::Message>),
+    /// Not logged in as someone allowed to do this
+    Unauthorized(i18n::Message),
+    /// Allowed in general but not in this case, eg removing your own account
+    Forbidden(i18n::Message),
+    NotFound(i18n::Message),
+    /// Clashes with existing data, eg a user name that is already taken
+    Conflict(i18n::Message),
+    /// Anything else, eg I/O errors
+    Internal(i18n::Message),
 }
 
 impl Error {
@@ -71,30 +101,64 @@ impl Error {
     where
         T: ToString,
     {
-        return Error {
-            message: message.to_string(),
-        };
+        return Error::Internal(i18n::Message::new(i18n::OTHER_ERROR).arg("message", message));
     }
 
     // Convert any impl of std::error::Error to crate::Error
     pub fn convert(err: impl std::error::Error) -> Self {
-        return Error {
-            message: err.to_string(),
+        return Error::new(err);
+    }
+
+    pub fn invalid(message: i18n::Message) -> Self {
+        return Error::Invalid(vec![message]);
+    }
+
+    /// Machine readable kind of error
+    pub fn code(&self) -> &'static str {
+        return match self {
+            Error::Invalid(_) => "invalid",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound(_) => "not_found",
+            Error::Conflict(_) => "conflict",
+            Error::Internal(_) => "internal",
+        };
+    }
+
+    pub fn status(&self) -> actix_web::http::StatusCode {
+        use actix_web::http::StatusCode;
+        return match self {
+            Error::Invalid(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+    }
+
+    pub fn messages(&self) -> &[i18n::Message] {
+        return match self {
+            Error::Invalid(m) => m,
+            Error::Unauthorized(m)
+            | Error::Forbidden(m)
+            | Error::NotFound(m)
+            | Error::Conflict(m)
+            | Error::Internal(m) => std::slice::from_ref(m),
         };
     }
 }
 
 impl Default for Error {
     fn default() -> Self {
-        Self {
-            message: Default::default(),
-        }
+        return Error::new("");
     }
 }
 
+/// English text of every message
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.message)
+        write!(f, "{}", i18n::default_locale().error(self))
     }
 }
 
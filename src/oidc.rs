@@ -0,0 +1,258 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use actix_web::cookie::time::{Duration, OffsetDateTime};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use ring::digest;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{config::OidcConfig, shared_data::Sharable, Error, Result};
+
+/// Time allowed between redirecting a user to the provider and receiving the
+/// callback before the login attempt is discarded
+pub const PENDING_TTL: Duration = Duration::minutes(10);
+
+/// The subset of the provider's discovery document used by the code flow
+#[derive(Deserialize, Clone)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    /// Plain OAuth2 providers only send an access token
+    id_token: Option<String>,
+}
+
+/// The id token claims checked against the login that was started
+#[derive(Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    aud: serde_json::Value,
+    nonce: Option<String>,
+}
+
+/// A login that was sent to the provider and has not come back yet
+struct Pending {
+    expires: OffsetDateTime,
+    /// PKCE code verifier, only its hash is sent with the authorization request
+    verifier: String,
+    nonce: String,
+}
+
+/// Client for the OpenID Connect authorization code flow with PKCE.
+/// The identity is read from the provider's userinfo endpoint using the
+/// access token received from the back-channel token exchange, so providers
+/// that send no id token work too. When there is one it arrives over the same
+/// TLS connection, so only its issuer, audience and nonce are checked, not its
+/// signature.
+pub struct OidcClient {
+    cfg: OidcConfig,
+    http: reqwest::Client,
+    metadata: Mutex<Option<ProviderMetadata>>,
+    pending: Mutex<HashMap<String, Pending>>, // K: state
+}
+
+impl OidcClient {
+    pub fn new(cfg: OidcConfig) -> Self {
+        return OidcClient {
+            cfg,
+            http: reqwest::Client::new(),
+            metadata: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        };
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        return &self.cfg;
+    }
+
+    /// Fetches the provider's discovery document. The result is cached after
+    /// the first successful request.
+    async fn metadata(&self) -> Result<ProviderMetadata> {
+        match &*self.metadata.lock().unwrap() {
+            Some(m) => return Ok(m.clone()),
+            None => {}
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.cfg.issuer.trim_end_matches('/')
+        );
+
+        let md = match self.http.get(&url).send().await {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => r.json::<ProviderMetadata>().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+
+        return match md {
+            Ok(m) => {
+                *self.metadata.lock().unwrap() = Some(m.clone());
+                Ok(m)
+            }
+            Err(e) => crate::err!("Unable to load provider metadata from {}: {}", url, e),
+        };
+    }
+
+    /// Builds the url to send the user to in order to log in with the provider.
+    /// Returns it with the login's state, which the callback must be given
+    /// back by the same browser.
+    pub async fn authorization_url(&self) -> Result<(String, String)> {
+        let md = match self.metadata().await {
+            Ok(m) => m,
+            Err(e) => return Err(e),
+        };
+
+        let state = Uuid::new_v4().to_string();
+        let nonce = Uuid::new_v4().simple().to_string();
+        let mut verifier = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut verifier);
+        let verifier = URL_SAFE_NO_PAD.encode(verifier);
+        let challenge = URL_SAFE_NO_PAD.encode(digest::digest(&digest::SHA256, verifier.as_bytes()));
+
+        let url = match reqwest::Url::parse_with_params(
+            &md.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", &self.cfg.client_id),
+                ("redirect_uri", &self.cfg.redirect_url),
+                ("scope", &self.cfg.scopes.join(" ")),
+                ("state", &state),
+                ("nonce", &nonce),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+            ],
+        ) {
+            Ok(u) => u,
+            Err(e) => return Err(Error::convert(e)),
+        };
+
+        let mut pending = self.pending.lock().unwrap();
+        let now = OffsetDateTime::now_utc();
+        pending.retain(|_, p| p.expires > now);
+        pending.insert(
+            state.clone(),
+            Pending {
+                expires: now + PENDING_TTL,
+                verifier,
+                nonce,
+            },
+        );
+
+        return Ok((url.to_string(), state));
+    }
+
+    /// Checks the id token was issued by the provider, for this client and
+    /// for this login
+    fn check_id_token(&self, md: &ProviderMetadata, id_token: &str, nonce: &str) -> Result<()> {
+        let payload = match id_token.split('.').nth(1).map(|p| URL_SAFE_NO_PAD.decode(p)) {
+            Some(Ok(p)) => p,
+            _ => return crate::err!("Malformed id token"),
+        };
+        let claims = match serde_json::from_slice::<IdTokenClaims>(&payload) {
+            Ok(c) => c,
+            Err(e) => return crate::err!("Malformed id token: {}", e),
+        };
+
+        if claims.iss != md.issuer {
+            return crate::err!("Id token issued by `{}`", claims.iss);
+        }
+        let for_client = match &claims.aud {
+            serde_json::Value::String(a) => a == &self.cfg.client_id,
+            serde_json::Value::Array(a) => a.iter().any(|a| a.as_str() == Some(&self.cfg.client_id)),
+            _ => false,
+        };
+        if !for_client {
+            return crate::err!("Id token issued for another client");
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return crate::err!("Id token nonce does not match the login");
+        }
+        return Ok(());
+    }
+
+    /// Completes a login started by authorization_url. Returns the user name
+    /// taken from the configured userinfo claim.
+    pub async fn complete(&self, code: &str, state: &str) -> Result<String> {
+        let pending = match self.pending.lock().unwrap().remove(state) {
+            Some(p) if p.expires > OffsetDateTime::now_utc() => p,
+            _ => return crate::err!("Unknown or expired login state"),
+        };
+
+        let md = match self.metadata().await {
+            Ok(m) => m,
+            Err(e) => return Err(e),
+        };
+
+        let token = match self
+            .http
+            .post(&md.token_endpoint)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.cfg.redirect_url),
+                ("client_id", &self.cfg.client_id),
+                ("client_secret", &self.cfg.client_secret),
+                ("code_verifier", &pending.verifier),
+            ])
+            .send()
+            .await
+        {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => r.json::<TokenResponse>().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let token = match token {
+            Ok(t) => t,
+            Err(e) => return crate::err!("Token exchange failed: {}", e),
+        };
+        match &token.id_token {
+            Some(id_token) => match self.check_id_token(&md, id_token, &pending.nonce) {
+                Ok(_) => {}
+                Err(e) => return Err(e),
+            },
+            None => {}
+        }
+
+        let claims = match self
+            .http
+            .get(&md.userinfo_endpoint)
+            .bearer_auth(&token.access_token)
+            .send()
+            .await
+        {
+            Ok(r) => match r.error_for_status() {
+                Ok(r) => r.json::<serde_json::Value>().await,
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        let claims = match claims {
+            Ok(c) => c,
+            Err(e) => return crate::err!("Userinfo request failed: {}", e),
+        };
+
+        return match claims.get(&self.cfg.username_claim).and_then(|c| c.as_str()) {
+            Some(name) if !name.is_empty() => Ok(name.to_string()),
+            _ => crate::err!("Userinfo does not contain claim `{}`", self.cfg.username_claim),
+        };
+    }
+}
+
+impl Sharable for OidcClient {
+    type Shared = OidcClient;
+
+    fn to_sharable(self) -> actix_web::web::Data<Self::Shared> {
+        return actix_web::web::Data::new(self);
+    }
+}
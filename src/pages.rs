@@ -0,0 +1,207 @@
+/*
+Fills in the placeholders of the HTML pages as they are served. A page refers
+to a value with `{{name}}`, and everything taken from the config is escaped
+before it is put in the page.
+
+Text is translated with `{{t:code}}`, and `{{messages}}` is the client's whole
+catalog for use by scripts. See i18n.
+*/
+
+use actix_web::web;
+
+use crate::{
+    config::{ThemeConfig, UserConfig},
+    i18n::Locale,
+    shared_data::Sharable,
+};
+
+pub struct PageConfig {
+    /// See UserConfig::base_path
+    pub base_path: String,
+    theme: ThemeConfig,
+    /// <style> element with the theme's colors, or empty
+    style: String,
+}
+
+impl PageConfig {
+    pub fn new(cfg: &UserConfig) -> Self {
+        let theme = cfg.theme.clone().unwrap_or_default();
+        let style = theme_style(&theme);
+        return PageConfig {
+            base_path: cfg.base_path(),
+            theme,
+            style,
+        };
+    }
+
+    fn value(&self, name: &str, locale: &Locale) -> Option<String> {
+        let theme = &self.theme;
+        if let Some(code) = name.strip_prefix("t:") {
+            return Some(escape(locale.text(code)));
+        }
+        return match name {
+            "base_path" => Some(escape(&self.base_path)),
+            "title" => Some(escape(&theme.title)),
+            "logo" => match &theme.logo {
+                Some(l) => Some(escape(l)),
+                None => Some(escape(&format!("{}/static/logo.png", self.base_path))),
+            },
+            "footer" => Some(escape(theme.footer.as_ref().unwrap_or(&theme.title))),
+            "footer_url" => Some(escape(&theme.footer_url)),
+            "message" => match &theme.message {
+                Some(m) if !m.is_empty() => Some(format!("<div class=\"notification\">{}</div>", escape(m))),
+                _ => Some(String::new()),
+            },
+            "theme_style" => Some(self.style.clone()),
+            "lang" => Some(locale.tag.to_string()),
+            "messages" => Some(locale.to_json()),
+            _ => None,
+        };
+    }
+
+    /// Replaces every known placeholder in page. Unknown ones are left as
+    /// they are, and values are never searched for placeholders themselves.
+    pub fn render(&self, page: &str, locale: &Locale) -> String {
+        let mut rendered = String::with_capacity(page.len());
+        let mut rest = page;
+        while let Some(start) = rest.find("{{") {
+            rendered.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            match after
+                .find("}}")
+                .and_then(|end| Some((end, self.value(&after[..end], locale)?)))
+            {
+                Some((end, value)) => {
+                    rendered.push_str(&value);
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    rendered.push_str("{{");
+                    rest = after;
+                }
+            }
+        }
+        rendered.push_str(rest);
+        return rendered;
+    }
+}
+
+impl Sharable for PageConfig {
+    type Shared = PageConfig;
+
+    fn to_sharable(self) -> web::Data<Self::Shared> {
+        return web::Data::new(self);
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+/// A color from the theme if it only has characters CSS colors use, since it
+/// goes in a <style> element
+fn css_color<'a>(name: &str, color: &'a Option<String>) -> Option<&'a str> {
+    let color = color.as_deref()?.trim();
+    if !color.is_empty()
+        && color
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "#(),.% -".contains(c))
+    {
+        return Some(color);
+    }
+    tracing::warn!("Ignoring theme {} `{}`, which is not a CSS color", name, color);
+    return None;
+}
+
+fn theme_style(theme: &ThemeConfig) -> String {
+    let mut rules = Vec::new();
+    if let Some(c) = css_color("accent_color", &theme.accent_color) {
+        rules.push(format!(
+            ".button.is-info, .button.is-info:hover, .tag.is-info {{ background-color: {c}; }}\n\
+             .progress.is-info::-webkit-progress-value {{ background-color: {c}; }}\n\
+             .progress.is-info::-moz-progress-bar {{ background-color: {c}; }}"
+        ));
+    }
+    if let Some(c) = css_color("accent_text_color", &theme.accent_text_color) {
+        rules.push(format!(".button.is-info, .tag.is-info {{ color: {c}; }}"));
+    }
+    if rules.is_empty() {
+        return String::new();
+    }
+    return format!("<style>\n{}\n</style>", rules.join("\n"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i18n::{default_locale, negotiate};
+
+    impl PageConfig {
+        fn render_en(&self, page: &str) -> String {
+            return self.render(page, default_locale());
+        }
+    }
+
+    #[test]
+    fn render_defaults() {
+        let pages = PageConfig::new(&UserConfig::default());
+        assert_eq!(
+            pages.render_en("<title>{{title}}</title><img src=\"{{logo}}\">{{message}}{{theme_style}}"),
+            "<title>Potato Auth</title><img src=\"/potato_auth/static/logo.png\">"
+        );
+        // Unknown and unterminated placeholders are left alone
+        assert_eq!(pages.render_en("{{nope}} {{base_path}} {{"), "{{nope}} /potato_auth {{");
+    }
+
+    #[test]
+    fn render_theme() {
+        let mut cfg = UserConfig::default();
+        cfg.theme = Some(ThemeConfig {
+            title: "Smith & Sons".to_string(),
+            logo: Some("https://example.com/logo.svg".to_string()),
+            accent_color: Some("#3a7d44".to_string()),
+            accent_text_color: Some("white</style><script>".to_string()),
+            message: Some("<b>{{title}}</b>".to_string()),
+            ..Default::default()
+        });
+        let pages = PageConfig::new(&cfg);
+
+        assert_eq!(
+            pages.render_en("{{footer}}|{{logo}}"),
+            "Smith &amp; Sons|https://example.com/logo.svg"
+        );
+        assert_eq!(
+            pages.render_en("{{message}}"),
+            "<div class=\"notification\">&lt;b&gt;{{title}}&lt;/b&gt;</div>"
+        );
+
+        let style = pages.render_en("{{theme_style}}");
+        assert!(style.contains("background-color: #3a7d44;"));
+        assert!(!style.contains("script"));
+    }
+
+    #[test]
+    fn render_translated() {
+        let pages = PageConfig::new(&UserConfig::default());
+        let page = "<html lang=\"{{lang}}\"><h2>{{t:login.title}}</h2>{{t:nope}}";
+        assert_eq!(pages.render_en(page), "<html lang=\"en\"><h2>Log In</h2>nope");
+        assert_eq!(
+            pages.render(page, negotiate(Some("fr"))),
+            "<html lang=\"fr\"><h2>Connexion</h2>nope"
+        );
+        assert!(pages
+            .render("const MESSAGES = {{messages}};", negotiate(Some("de")))
+            .contains("\"login.title\":\"Anmelden\""));
+    }
+}
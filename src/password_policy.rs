@@ -0,0 +1,167 @@
+/*
+Checks for new passwords. Every rule is evaluated so all problems can be
+reported to the user at once.
+
+Breached passwords are looked up offline, either in the small list bundled
+with PotatoAuth or in a directory of Have I Been Pwned range files. Range
+files are named after the first 5 hex characters of a password's SHA1 hash
+and contain one `SUFFIX:COUNT` line per breached hash with that prefix.
+*/
+
+use std::{fs, path::Path};
+
+use sha1::{Digest, Sha1};
+
+use crate::{config::PasswordPolicy, i18n::Message};
+
+/// Hard upper limit regardless of policy
+pub const MAX_PASSWORD_LEN: usize = 72;
+
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+impl PasswordPolicy {
+    /// Returns a message for every rule plain_password breaks. An empty list
+    /// means the password is acceptable.
+    pub fn violations(&self, name: &str, plain_password: &str) -> Vec<Message> {
+        let mut errors: Vec<Message> = Vec::new();
+
+        if plain_password.chars().count() < self.min_length {
+            errors.push(Message::new("password.too_short").arg("min", self.min_length));
+        }
+
+        // Sounds like a good enough limit
+        if plain_password.chars().count() > MAX_PASSWORD_LEN {
+            errors.push(Message::new("password.too_long").arg("max", MAX_PASSWORD_LEN));
+        }
+
+        if self.require_lowercase && !plain_password.chars().any(|c| c.is_lowercase()) {
+            errors.push(Message::new("password.lowercase"));
+        }
+
+        if self.require_uppercase && !plain_password.chars().any(|c| c.is_uppercase()) {
+            errors.push(Message::new("password.uppercase"));
+        }
+
+        if self.require_digit && !plain_password.chars().any(|c| c.is_numeric()) {
+            errors.push(Message::new("password.digit"));
+        }
+
+        if self.require_symbol && !plain_password.chars().any(|c| !c.is_alphanumeric()) {
+            errors.push(Message::new("password.symbol"));
+        }
+
+        if self.reject_username && !name.is_empty() && plain_password.to_lowercase().contains(&name.to_lowercase()) {
+            errors.push(Message::new("password.contains_name"));
+        }
+
+        if self.reject_common && is_common(plain_password) {
+            errors.push(Message::new("password.common"));
+        }
+
+        match &self.breached_passwords_dir {
+            Some(dir) if is_breached(dir, plain_password) => {
+                errors.push(Message::new("password.breached"));
+            }
+            _ => {}
+        }
+
+        return errors;
+    }
+}
+
+fn is_common(plain_password: &str) -> bool {
+    let lower = plain_password.to_lowercase();
+    return COMMON_PASSWORDS.lines().any(|l| l.trim().to_lowercase() == lower);
+}
+
+fn is_breached(dir: &str, plain_password: &str) -> bool {
+    let digest: String = Sha1::digest(plain_password.as_bytes())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    let (prefix, suffix) = digest.split_at(5);
+
+    let mut contents: Option<String> = None;
+    for filename in [prefix.to_string(), format!("{}.txt", prefix)] {
+        match fs::read_to_string(Path::new(dir).join(filename)) {
+            Ok(c) => {
+                contents = Some(c);
+                break;
+            }
+            Err(_) => {}
+        }
+    }
+
+    return match contents {
+        Some(c) => c.lines().any(|l| match l.split_once(':') {
+            Some((s, _)) => s.trim().eq_ignore_ascii_case(suffix),
+            None => l.trim().eq_ignore_ascii_case(suffix),
+        }),
+        None => {
+            tracing::debug!("No breached password range file for {} in {}", prefix, dir);
+            false
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::make_tmp_dir;
+
+    #[test]
+    fn violations() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.violations("someone", "a perfectly fine pass").is_empty());
+        assert_eq!(policy.violations("someone", "short").len(), 1);
+        assert_eq!(policy.violations("someone", &"x".repeat(73)).len(), 1);
+        // Both limits count characters, not bytes
+        assert!(policy.violations("someone", &"é".repeat(72)).is_empty());
+        assert_eq!(policy.violations("someone", &"é".repeat(73)).len(), 1);
+        assert_eq!(policy.violations("someone", &"é".repeat(7)).len(), 1);
+
+        // Opt in
+        assert!(policy.violations("someone", "Password1").is_empty());
+        assert!(policy.violations("someone", "Someone_else").is_empty());
+        let careful = PasswordPolicy {
+            reject_username: true,
+            reject_common: true,
+            ..Default::default()
+        };
+        assert_eq!(careful.violations("someone", "Password1").len(), 1);
+        assert_eq!(careful.violations("someone", "Someone_else").len(), 1);
+
+        let strict = PasswordPolicy {
+            min_length: 12,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            ..Default::default()
+        };
+        assert_eq!(strict.violations("someone", "abc").len(), 4);
+        assert_eq!(strict.violations("someone", "ABC").len(), 4);
+        assert!(strict.violations("someone", "Correct-Horse-9").is_empty());
+    }
+
+    #[test]
+    fn breached_dir() {
+        let dir = make_tmp_dir();
+        // SHA1("correct horse battery staple") = ABF7AAD6438836DBE526AA231ABDE2D0EEF74D42
+        fs::write(
+            Path::new(&dir).join("ABF7A"),
+            "0000000000000000000000000000000000A:3\nAD6438836DBE526AA231ABDE2D0EEF74D42:42\n",
+        )
+        .unwrap();
+
+        let policy = PasswordPolicy {
+            breached_passwords_dir: Some(dir),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.violations("someone", "correct horse battery staple"),
+            vec![Message::new("password.breached")]
+        );
+        assert!(policy.violations("someone", "a perfectly fine pass").is_empty());
+    }
+}
@@ -0,0 +1,303 @@
+/*
+Reloads the config file and user db while the server is running. Both files
+are checked for changes every few seconds, and SIGHUP forces a reload through
+the Notify given to Reloader::watch. Files that fail to load are rejected and
+the server keeps its current settings.
+*/
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::web::Data;
+use tokio::sync::Notify;
+
+use crate::{
+    access::AccessControl, config::UserConfig, file_utils::file_exists, sessions::SessionStore, systemd,
+    userdb::UserDB, Result,
+};
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+fn modified(filepath: &Option<String>) -> Option<SystemTime> {
+    return filepath
+        .as_ref()
+        .and_then(|p| std::fs::metadata(p).ok())
+        .and_then(|m| m.modified().ok());
+}
+
+pub struct Reloader {
+    cfg: UserConfig,
+    /// Contents of the config file when it was last loaded, used to notice
+    /// changes to settings that are only read at startup
+    file_cfg: Option<UserConfig>,
+    user_db: Data<RwLock<UserDB>>,
+    session_store: Data<RwLock<SessionStore>>,
+    access_control: Data<RwLock<AccessControl>>,
+    /// Modification times of the config file and user db
+    modified: (Option<SystemTime>, Option<SystemTime>),
+}
+
+impl Reloader {
+    pub fn new(
+        cfg: UserConfig,
+        user_db: Data<RwLock<UserDB>>,
+        session_store: Data<RwLock<SessionStore>>,
+        access_control: Data<RwLock<AccessControl>>,
+    ) -> Self {
+        let file_cfg = cfg.cfg_path.as_ref().and_then(|p| UserConfig::from_file(p).ok());
+        let modified = (modified(&cfg.cfg_path), modified(&cfg.user_db));
+        return Reloader {
+            cfg,
+            file_cfg,
+            user_db,
+            session_store,
+            access_control,
+            modified,
+        };
+    }
+
+    /// Reloads whenever either file changes or reload is notified. Runs
+    /// until its task is aborted.
+    pub async fn watch(mut self, reload: Arc<Notify>) {
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.reload_if_changed(),
+                _ = reload.notified() => {
+                    tracing::info!("Reloading config and user db");
+                    systemd::reloading();
+                    self.reload_and_log();
+                    systemd::ready("Running");
+                }
+            }
+        }
+    }
+
+    fn reload_if_changed(&mut self) {
+        let modified = (modified(&self.cfg.cfg_path), modified(&self.cfg.user_db));
+        if modified == self.modified {
+            return;
+        }
+        self.reload_and_log();
+    }
+
+    fn reload_and_log(&mut self) {
+        // Set first so a rejected file is not retried until it changes again
+        self.modified = (modified(&self.cfg.cfg_path), modified(&self.cfg.user_db));
+        match self.reload() {
+            Ok(_) => {}
+            Err(e) => tracing::error!("Reload rejected, keeping current settings: {}", e),
+        }
+    }
+
+    /// Loads both files and swaps them in if they are valid. Sessions are
+    /// kept and moved over to the reloaded users.
+    pub fn reload(&mut self) -> Result<()> {
+        let (cfg, file_cfg) = match self.load_config() {
+            Ok(c) => c,
+            Err(e) => return Err(e),
+        };
+        // Held from reading the file until it is swapped in, so a change
+        // saved in between is never replaced with what was read before it
+        let mut current = self.user_db.write().unwrap();
+        let user_db = match UserDB::from_config_strict(&cfg) {
+            Ok(u) => u,
+            Err(e) => return Err(e),
+        };
+        if user_db.count() == 0 {
+            return crate::err!("{} has no users", cfg.user_db.as_ref().unwrap());
+        }
+        let access_control = match AccessControl::new(&cfg) {
+            Ok(a) => a,
+            Err(e) => return Err(e),
+        };
+
+        // Everything loaded, nothing past here can fail
+        for section in changed_sections(&self.cfg, &cfg) {
+            tracing::info!("Reloaded `{}` from the config file", section);
+        }
+        self.access_control.write().unwrap().replace_rules(access_control);
+
+        let changes = current.diff(&user_db);
+        if changes.is_empty() {
+            // Usually our own write. Keep the current users so nothing that
+            // holds one is left with a stale copy.
+            current.set_argon2_config(cfg.argon2.clone().unwrap_or_default());
+            current.set_password_policy(cfg.password_policy.clone().unwrap_or_default());
+        } else {
+            for change in &changes {
+                tracing::info!("Reloaded user db: {}", change);
+            }
+            *current = user_db;
+            self.session_store.write().unwrap().swap_users(&current);
+        }
+        drop(current);
+
+        self.cfg = cfg;
+        self.file_cfg = file_cfg;
+        return Ok(());
+    }
+
+    /// Reads the config file again. Settings that are only used at startup
+    /// keep their current values, with a warning if the file changed them.
+    fn load_config(&self) -> Result<(UserConfig, Option<UserConfig>)> {
+        let filepath = match &self.cfg.cfg_path {
+            Some(p) if file_exists(p) => p,
+            _ => return Ok((self.cfg.clone(), self.file_cfg.clone())),
+        };
+        let file_cfg = match UserConfig::from_file(filepath) {
+            Ok(c) => c,
+            Err(e) => return Err(e),
+        };
+
+        let mut cfg = file_cfg.clone();
+        macro_rules! keep_startup_settings {
+            ($($field:ident),*) => {$(
+                if self.file_cfg.as_ref().is_some_and(|f| f.$field != file_cfg.$field) {
+                    tracing::warn!("`{}` changed in {}; restart the server to apply it", stringify!($field), filepath);
+                }
+                cfg.$field = self.cfg.$field.clone();
+            )*};
+        }
+        keep_startup_settings!(
+            address,
+            port,
+            listen,
+            tls,
+            admin,
+            base_path,
+            serve_under_base_path,
+            asset_dir,
+            theme,
+            session_timeout,
+            user_db,
+            log_dir,
+            log_archive_count,
+            console,
+            oidc,
+            ldap,
+            cfg_path,
+            command
+        );
+
+        return Ok((cfg, Some(file_cfg)));
+    }
+}
+
+/// Names of the reloadable config sections that differ between old and new
+fn changed_sections(old: &UserConfig, new: &UserConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    if old.argon2 != new.argon2 {
+        changed.push("argon2");
+    }
+    if old.password_policy != new.password_policy {
+        changed.push("password_policy");
+    }
+    if old.schedules != new.schedules || old.path_schedules != new.path_schedules {
+        changed.push("schedules");
+    }
+    if old.network != new.network {
+        changed.push("network");
+    }
+    if old.public_rules != new.public_rules {
+        changed.push("public_rules");
+    }
+    return changed;
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{
+        cookie::{time::Duration, Cookie},
+        test::TestRequest,
+        HttpRequest,
+    };
+
+    use super::*;
+    use crate::{
+        config::PublicRule, routes::tests::make_test_userdb, shared_data::Sharable, test_utils::make_tmp_file,
+    };
+
+    #[test]
+    fn reload() {
+        let cfg_path = make_tmp_file();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.cfg_path = Some(cfg_path.to_owned());
+        cfg.write_to_file(&cfg_path).unwrap();
+
+        let user_db = UserDB::from_config(&cfg).unwrap().to_sharable();
+        let session_store = SessionStore::new(Duration::minutes(5)).to_sharable();
+        let access_control = AccessControl::new(&cfg).unwrap().to_sharable();
+        let mut reloader = Reloader::new(
+            cfg.clone(),
+            user_db.clone(),
+            session_store.clone(),
+            access_control.clone(),
+        );
+
+        let user = user_db.read().unwrap().get(&"User_user".to_string()).unwrap().clone();
+        let admin = user_db.read().unwrap().get(&"Admin_user".to_string()).unwrap().clone();
+        let with_cookie = |c: Cookie| -> HttpRequest { TestRequest::default().cookie(c).to_http_request() };
+        let user_req = with_cookie(session_store.write().unwrap().new_session(&user).cookie().into_owned());
+        let admin_req = with_cookie(session_store.write().unwrap().new_session(&admin).cookie().into_owned());
+
+        // Reloading unchanged files keeps the same users
+        reloader.reload().unwrap();
+        assert!(Arc::ptr_eq(
+            user_db.read().unwrap().get(&"User_user".to_string()).unwrap(),
+            &user
+        ));
+
+        // Changed users replace the ones held by sessions, removed users lose theirs
+        let udb = cfg.user_db.as_ref().unwrap();
+        let contents = std::fs::read_to_string(udb).unwrap();
+        let user_line = contents.lines().find(|l| l.starts_with("User_user")).unwrap();
+        std::fs::write(udb, user_line.replace(":*:", ":/movies/*:")).unwrap();
+        reloader.reload().unwrap();
+
+        let mut sessions = session_store.write().unwrap();
+        let reloaded = sessions.get_from_request(&user_req).unwrap().get_user();
+        assert!(!Arc::ptr_eq(&reloaded, &user));
+        assert!(reloaded.read().unwrap().path_allowed("/movies/a"));
+        assert!(!reloaded.read().unwrap().path_allowed("/music/a"));
+        assert!(sessions.get_from_request(&admin_req).is_none());
+        drop(sessions);
+
+        // Config changes apply to access rules
+        let mut edited = cfg.clone();
+        edited.public_rules = Some(vec![PublicRule {
+            path: "/status".to_string(),
+            ..Default::default()
+        }]);
+        edited.write_to_file(&cfg_path).unwrap();
+        reloader.reload().unwrap();
+        assert!(access_control
+            .read()
+            .unwrap()
+            .is_public("example.com", "GET", "/status"));
+
+        // Invalid files are rejected and nothing changes
+        std::fs::write(udb, "User_user:hash").unwrap();
+        assert!(reloader.reload().is_err());
+        std::fs::write(udb, "").unwrap();
+        assert!(reloader.reload().is_err());
+        std::fs::write(udb, user_line).unwrap();
+        std::fs::write(&cfg_path, "{ not json").unwrap();
+        assert!(reloader.reload().is_err());
+        assert!(user_db
+            .read()
+            .unwrap()
+            .get(&"User_user".to_string())
+            .unwrap()
+            .read()
+            .unwrap()
+            .path_allowed("/movies/a"));
+        assert!(access_control
+            .read()
+            .unwrap()
+            .is_public("example.com", "GET", "/status"));
+    }
+}
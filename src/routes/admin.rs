@@ -8,7 +8,7 @@ pub async fn get(req: HttpRequest) -> HttpResponse {
     return match req_user(&req) {
         Some(usr) => {
             if usr.read().unwrap().get_type() == &AcctType::Admin {
-                serve_file("./static/admin.html").await
+                serve_page(&req, "admin.html").await
             } else {
                 simple_response(StatusCode::UNAUTHORIZED)
             }
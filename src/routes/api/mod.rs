@@ -0,0 +1,315 @@
+/*
+Versioned admin API, served under `<base_path>/api/v1`. Resources are named
+in the path and changed with the usual HTTP methods. Replies use the same
+envelope as routes::rpc, whose calls are kept as aliases and share the same
+handlers:
+
+    GET    /users                   listusers
+    POST   /users                   adduser
+    PATCH  /users/{name}            updateuser
+    DELETE /users/{name}            removeuser
+    POST   /users/{name}/disable    disableuser
+    POST   /users/{name}/enable     enableuser
+    GET    /export                  exportusers
+    POST   /import                  importusers
+    GET    /public-rules            publicrules
+    POST   /public-rules            addpublicrule
+    DELETE /public-rules/{index}    removepublicrule
+    GET    /sessions                listsessions
+    DELETE /sessions/{id}           revokesession
+    POST   /server/restart          restartserver
+    POST   /server/quit             restartserver
+
+The OpenAPI document describing all of this is served at /openapi.json.
+
+The API is also served on `admin.socket` for the command line tools. Anyone
+who can connect to it is trusted as an admin, acting as LocalAdmin's user.
+*/
+
+use std::sync::{Arc, RwLock};
+
+use actix_web::{
+    web::{self, Bytes, Path},
+    HttpRequest, HttpResponse, Scope,
+};
+
+use crate::{
+    app::ExitCommand,
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{
+            add_user, admin_user, disable_user, export_users, import_users, list_users, public_rules, remove_user,
+            restart_server, rpc_error, sessions, unauthorized, unknown_call, update_user,
+        },
+    },
+    shared_data::Sharable,
+    userdb::{AcctType, User},
+    Error,
+};
+
+pub mod openapi;
+
+pub const PREFIX: &str = "/api/v1";
+
+/// Marks requests from admin.socket. user has no name so it can't clash with
+/// a real account and is never found in the user db.
+pub struct LocalAdmin {
+    pub user: Arc<RwLock<User>>,
+}
+
+impl LocalAdmin {
+    pub fn new() -> Self {
+        let user = User::new(&String::new(), &String::new(), &Vec::new(), AcctType::Admin).unwrap();
+        return LocalAdmin {
+            user: Arc::new(RwLock::new(user)),
+        };
+    }
+}
+
+impl Sharable for LocalAdmin {
+    type Shared = LocalAdmin;
+    fn to_sharable(self) -> web::Data<Self::Shared> {
+        return web::Data::new(self);
+    }
+}
+
+/// Every API route, with unknown paths answered by not_found
+pub fn scope() -> Scope {
+    return web::scope(PREFIX)
+        .configure(configure)
+        .default_service(web::to(not_found));
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/openapi.json", web::get().to(openapi::get))
+        .route("/users", web::get().to(get_users))
+        .route("/users", web::post().to(post_users))
+        .route("/users/{name}", web::patch().to(patch_user))
+        .route("/users/{name}", web::delete().to(delete_user))
+        .route("/users/{name}/disable", web::post().to(post_disable))
+        .route("/users/{name}/enable", web::post().to(post_enable))
+        .route("/export", web::get().to(get_export))
+        .route("/import", web::post().to(post_import))
+        .route("/public-rules", web::get().to(get_public_rules))
+        .route("/public-rules", web::post().to(post_public_rules))
+        .route("/public-rules/{index}", web::delete().to(delete_public_rule))
+        .route("/sessions", web::get().to(get_sessions))
+        .route("/sessions/{id}", web::delete().to(delete_session))
+        .route("/server/restart", web::post().to(post_restart))
+        .route("/server/quit", web::post().to(post_quit));
+}
+
+/// Reply for paths under PREFIX that do not exist
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    return rpc_error(&req, unknown_call(req.path()));
+}
+
+async fn get_users(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => list_users::get(req).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_users(req: HttpRequest, body: Bytes) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => add_user::post(req, body).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn patch_user(req: HttpRequest, body: Bytes, name: Path<String>) -> HttpResponse {
+    match admin_user(&req) {
+        Some(_) => {}
+        None => return rpc_error(&req, unauthorized()),
+    }
+    let changes: update_user::Changes = match parse_post_body(body) {
+        Ok(c) => c,
+        Err(e) => return rpc_error(&req, e),
+    };
+    return update_user::update(&req, &name, changes);
+}
+
+async fn delete_user(req: HttpRequest, name: Path<String>) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(admin) => remove_user::remove(&req, &name, admin),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_disable(req: HttpRequest, name: Path<String>) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(admin) => disable_user::set_disabled(&req, &name, admin, true),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_enable(req: HttpRequest, name: Path<String>) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(admin) => disable_user::set_disabled(&req, &name, admin, false),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn get_export(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => export_users::get(req).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_import(req: HttpRequest, body: Bytes) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => import_users::post(req, body).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn get_public_rules(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => public_rules::get(req).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_public_rules(req: HttpRequest, body: Bytes) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => public_rules::add(req, body).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn delete_public_rule(req: HttpRequest, index: Path<String>) -> HttpResponse {
+    match admin_user(&req) {
+        Some(_) => {}
+        None => return rpc_error(&req, unauthorized()),
+    }
+    // Parsed here rather than by Path so a bad index gets the usual error reply
+    return match index.parse::<usize>() {
+        Ok(i) => public_rules::remove_index(&req, i),
+        Err(_) => rpc_error(
+            &req,
+            Error::NotFound(Message::new("rule.not_found").field("index").arg("index", &*index)),
+        ),
+    };
+}
+
+async fn get_sessions(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => sessions::get(req).await,
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn delete_session(req: HttpRequest, id: Path<String>) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => sessions::revoke(&req, &id),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_restart(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => restart_server::send(&req, ExitCommand::Restart),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+async fn post_quit(req: HttpRequest) -> HttpResponse {
+    return match admin_user(&req) {
+        Some(_) => restart_server::send(&req, ExitCommand::Quit),
+        None => rpc_error(&req, unauthorized()),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use serde_json::{json, Value};
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get, send_post},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn users_resource() {
+        const PORT: u16 = 8640;
+        let api = format!("http://localhost:{}/api/v1", PORT);
+        let users_url = format!("{}/users", api);
+        let user_url = format!("{}/users/new_user", api);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let client = make_client();
+        assert_eq!(send_get(&client, &users_url).await.status(), StatusCode::UNAUTHORIZED);
+        login_client(&client, PORT, AcctType::Admin).await;
+
+        let payload = json!({"name": "new_user", "password": "new_password", "paths": ["*"], "acct_type": "User"});
+        assert_eq!(send_post(&client, &users_url, &payload).await.status(), StatusCode::OK);
+        assert_eq!(
+            send_post(&client, &users_url, &payload).await.status(),
+            StatusCode::CONFLICT
+        );
+
+        let resp = client
+            .patch(&user_url)
+            .json(&json!({"paths": ["/new/*"]}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = client
+            .patch(&user_url)
+            .json(&json!({"password": "short"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let reply: Value = resp.json().await.unwrap();
+        assert_eq!(reply["errors"][0]["field"], "password");
+
+        let disable_url = format!("{}/disable", user_url);
+        assert_eq!(
+            send_post(&client, &disable_url, &json!({})).await.status(),
+            StatusCode::OK
+        );
+
+        let list: Value = send_get(&client, &users_url).await.json().await.unwrap();
+        let user = list["response"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|u| u["name"] == "new_user")
+            .unwrap()
+            .clone();
+        assert_eq!(user["paths"], json!(["/new/*"]));
+        assert_eq!(user["disabled"], true);
+
+        assert_eq!(client.delete(&user_url).send().await.unwrap().status(), StatusCode::OK);
+        let list: Value = send_get(&client, &users_url).await.json().await.unwrap();
+        assert!(list["response"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|u| u["name"] != "new_user"));
+        assert_eq!(
+            send_get(&client, &format!("{}/nope", api)).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let doc: Value = send_get(&client, &format!("{}/openapi.json", api))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(doc["servers"][0]["url"], "/potato_auth/api/v1");
+        assert!(doc["paths"]["/users/{name}"]["patch"].is_object());
+    }
+}
@@ -0,0 +1,416 @@
+/*
+OpenAPI 3 description of the versioned API. It is built when requested so the
+server URL follows base_path.
+*/
+
+use actix_web::{HttpRequest, HttpResponse};
+use serde_json::{json, Map, Value};
+
+use crate::{pages::PageConfig, shared_data::Sharable};
+
+use super::PREFIX;
+
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    let base_path = &PageConfig::extract_from(&req).base_path;
+    return HttpResponse::Ok().json(document(base_path));
+}
+
+fn schema_ref(name: &str) -> Value {
+    return json!({ "$ref": format!("#/components/schemas/{}", name) });
+}
+
+/// Successful reply whose response field holds response
+fn reply(response: Value) -> Value {
+    return json!({
+        "description": "Success",
+        "content": { "application/json": { "schema": {
+            "type": "object",
+            "required": ["ok", "response"],
+            "properties": {
+                "ok": { "type": "boolean", "enum": [true] },
+                "response": response,
+            },
+        }}},
+    });
+}
+
+fn message_reply() -> Value {
+    return reply(json!({ "type": "string", "description": "What was done, in the client's language" }));
+}
+
+fn json_body(schema: Value) -> Value {
+    return json!({ "required": true, "content": { "application/json": { "schema": schema } } });
+}
+
+fn path_param(name: &str, description: &str) -> Value {
+    return json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": { "type": "string" },
+    });
+}
+
+/// An operation that can fail with any of the errors in the Error schema
+fn operation(summary: &str, alias: &str, params: Vec<Value>, body: Option<Value>, ok: Value) -> Value {
+    let mut responses = Map::new();
+    responses.insert("200".to_string(), ok);
+    for status in ["400", "401", "403", "404", "409", "500"] {
+        responses.insert(status.to_string(), json!({ "$ref": "#/components/responses/Error" }));
+    }
+
+    let mut op = json!({
+        "summary": summary,
+        "description": format!("Also available as the `rpc/{}` call.", alias),
+        "parameters": params,
+        "responses": responses,
+    });
+    if let Some(b) = body {
+        op["requestBody"] = b;
+    }
+    return op;
+}
+
+fn schemas() -> Value {
+    return json!({
+        "AcctType": { "type": "string", "enum": ["User", "Admin"] },
+        "User": {
+            "type": "object",
+            "required": ["name", "acct_type", "paths", "disabled"],
+            "properties": {
+                "name": { "type": "string" },
+                "acct_type": schema_ref("AcctType"),
+                "paths": { "type": "array", "items": { "type": "string" }, "description": "Glob patterns the user may access" },
+                "disabled": { "type": "boolean" },
+                "expires_at": { "type": "integer", "nullable": true, "description": "Unix time" },
+                "schedule": { "type": "string", "nullable": true },
+            },
+        },
+        "NewUser": {
+            "type": "object",
+            "required": ["name", "password", "paths", "acct_type"],
+            "properties": {
+                "name": { "type": "string" },
+                "password": { "type": "string" },
+                "paths": { "type": "array", "items": { "type": "string" } },
+                "acct_type": schema_ref("AcctType"),
+            },
+        },
+        "UserChanges": {
+            "type": "object",
+            "description": "Missing fields are left as they are",
+            "properties": {
+                "password": { "type": "string" },
+                "paths": { "type": "array", "items": { "type": "string" } },
+                "acct_type": schema_ref("AcctType"),
+                "must_change_password": { "type": "boolean", "description": "Defaults to true when password is set, unless admins set their own" },
+                "expires_at": { "type": "integer", "nullable": true, "description": "Unix time, null removes it" },
+                "schedule": { "type": "string", "nullable": true, "description": "Null removes it" },
+            },
+        },
+        "TransferFormat": { "type": "string", "enum": ["json", "csv", "htpasswd"] },
+        "ExportedUser": {
+            "type": "object",
+            "required": ["name", "hashed_password", "paths", "acct_type"],
+            "description": "Fields after acct_type may be missing from older exports",
+            "properties": {
+                "name": { "type": "string" },
+                "hashed_password": { "type": "string" },
+                "paths": { "type": "array", "items": { "type": "string" } },
+                "acct_type": schema_ref("AcctType"),
+                "disabled": { "type": "boolean", "default": false },
+                "expires_at": { "type": "integer", "nullable": true, "description": "Unix time" },
+                "schedule": { "type": "string", "nullable": true },
+                "must_change_password": { "type": "boolean", "default": false },
+                "password_changed_at": { "type": "integer", "nullable": true, "description": "Unix time" },
+                "password_history": {
+                    "type": "array",
+                    "items": { "type": "string" },
+                    "description": "Hashes of previous passwords that may not be reused",
+                },
+                "source": {
+                    "type": "string",
+                    "enum": ["oidc", "ldap"],
+                    "nullable": true,
+                    "description": "Provider that owns an account without a local password",
+                },
+            },
+        },
+        "Import": {
+            "type": "object",
+            "required": ["data"],
+            "properties": {
+                "data": { "type": "string", "description": "htpasswd file, or a JSON or CSV export" },
+                "format": { "allOf": [schema_ref("TransferFormat")], "default": "htpasswd" },
+                "paths": { "type": "array", "items": { "type": "string" }, "description": "htpasswd only" },
+                "acct_type": { "allOf": [schema_ref("AcctType")], "description": "htpasswd only" },
+                "dry_run": { "type": "boolean", "default": false },
+            },
+        },
+        "ImportReport": {
+            "type": "object",
+            "required": ["dry_run", "added", "skipped"],
+            "properties": {
+                "dry_run": { "type": "boolean" },
+                "added": { "type": "array", "items": { "type": "string" } },
+                "skipped": { "type": "array", "items": { "type": "string" }, "description": "Each with the reason" },
+            },
+        },
+        "PublicRule": {
+            "type": "object",
+            "required": ["path"],
+            "properties": {
+                "host": { "type": "string", "default": "*" },
+                "path": { "type": "string" },
+                "methods": { "type": "array", "items": { "type": "string" } },
+            },
+        },
+        "Session": {
+            "type": "object",
+            "required": ["id", "user", "expires_at", "restricted"],
+            "properties": {
+                "id": { "type": "string", "description": "Identifies the session without revealing its cookie" },
+                "user": { "type": "string" },
+                "expires_at": { "type": "integer", "description": "Unix time" },
+                "restricted": { "type": "boolean", "description": "Only allowed to change its password" },
+            },
+        },
+        "Message": {
+            "type": "object",
+            "required": ["code"],
+            "properties": {
+                "code": { "type": "string", "example": "password.too_short" },
+                "field": { "type": "string", "description": "Request field the message is about" },
+                "args": { "type": "object", "additionalProperties": { "type": "string" } },
+            },
+        },
+        "Error": {
+            "type": "object",
+            "required": ["ok", "code", "response", "errors"],
+            "properties": {
+                "ok": { "type": "boolean", "enum": [false] },
+                "code": {
+                    "type": "string",
+                    "enum": ["invalid", "unauthorized", "forbidden", "not_found", "conflict", "internal"],
+                },
+                "response": { "type": "string", "description": "The error in the client's language" },
+                "errors": { "type": "array", "items": schema_ref("Message") },
+            },
+        },
+    });
+}
+
+pub fn document(base_path: &str) -> Value {
+    let user_name = || path_param("name", "User name");
+
+    return json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "PotatoAuth",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Admin API. Requests must carry the session cookie of a logged in admin.",
+        },
+        "servers": [{ "url": format!("{}{}", base_path, PREFIX) }],
+        "paths": {
+            "/users": {
+                "get": operation("List users", "listusers", vec![], None,
+                    reply(json!({ "type": "array", "items": schema_ref("User") }))),
+                "post": operation("Add a user", "adduser", vec![], Some(json_body(schema_ref("NewUser"))),
+                    message_reply()),
+            },
+            "/users/{name}": {
+                "patch": operation("Change a user", "updateuser", vec![user_name()],
+                    Some(json_body(schema_ref("UserChanges"))), message_reply()),
+                "delete": operation("Remove a user", "removeuser", vec![user_name()], None, message_reply()),
+            },
+            "/users/{name}/disable": {
+                "post": operation("Disable a user and end their sessions", "disableuser", vec![user_name()], None,
+                    message_reply()),
+            },
+            "/users/{name}/enable": {
+                "post": operation("Enable a user", "enableuser", vec![user_name()], None, message_reply()),
+            },
+            "/export": {
+                "get": {
+                    "summary": "Export users with their password hashes",
+                    "description": "Also available as the `rpc/exportusers` call.",
+                    "parameters": [{
+                        "name": "format",
+                        "in": "query",
+                        "schema": { "allOf": [schema_ref("TransferFormat")], "default": "json" },
+                    }],
+                    "responses": {
+                        "200": reply(json!({
+                            "description": "Users for JSON, otherwise the exported text",
+                            "oneOf": [
+                                { "type": "array", "items": schema_ref("ExportedUser") },
+                                { "type": "string" },
+                            ],
+                        })),
+                        "400": { "$ref": "#/components/responses/Error" },
+                        "401": { "$ref": "#/components/responses/Error" },
+                    },
+                },
+            },
+            "/import": {
+                "post": operation("Import users", "importusers", vec![], Some(json_body(schema_ref("Import"))),
+                    reply(schema_ref("ImportReport"))),
+            },
+            "/public-rules": {
+                "get": operation("List public rules", "publicrules", vec![], None,
+                    reply(json!({ "type": "array", "items": schema_ref("PublicRule") }))),
+                "post": operation("Add a public rule", "addpublicrule", vec![],
+                    Some(json_body(schema_ref("PublicRule"))), message_reply()),
+            },
+            "/public-rules/{index}": {
+                "delete": operation("Remove a public rule", "removepublicrule",
+                    vec![path_param("index", "Position in the list of public rules")], None, message_reply()),
+            },
+            "/sessions": {
+                "get": operation("List sessions", "listsessions", vec![], None,
+                    reply(json!({ "type": "array", "items": schema_ref("Session") }))),
+            },
+            "/sessions/{id}": {
+                "delete": operation("End a session", "revokesession", vec![path_param("id", "Session id")], None,
+                    message_reply()),
+            },
+            "/server/restart": {
+                "post": operation("Restart the server", "restartserver", vec![], None,
+                    reply(json!({ "type": "string" }))),
+            },
+            "/server/quit": {
+                "post": operation("Stop the server", "restartserver", vec![], None,
+                    reply(json!({ "type": "string" }))),
+            },
+        },
+        "components": {
+            "schemas": schemas(),
+            "responses": {
+                "Error": {
+                    "description": "The status matches the error's code",
+                    "content": { "application/json": { "schema": schema_ref("Error") } },
+                },
+            },
+        },
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use serde::{
+        de::{self, DeserializeOwned, Visitor},
+        forward_to_deserialize_any, Deserializer,
+    };
+
+    use super::*;
+    use crate::{
+        config::PublicRule,
+        i18n::Message,
+        routes::rpc::{add_user, export_users, import_users, update_user},
+        sessions::SessionInfo,
+        transfer::ExportedUser,
+        userdb::{ImportReport, SafeSerializableUser},
+    };
+
+    /// Only gets as far as the field names of the struct it is given
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldNames<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            return Err(de::Error::custom("not a struct"));
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            return Err(de::Error::custom("done"));
+        }
+
+        forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit
+            unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    fn fields<T: DeserializeOwned>() -> Vec<&'static str> {
+        let mut fields: &'static [&'static str] = &[];
+        _ = T::deserialize(FieldNames(&mut fields));
+        return fields.to_vec();
+    }
+
+    #[test]
+    fn documents_every_route() {
+        let doc = document("");
+        let route = Regex::new(r#"\.route\("([^"]+)", web::(\w+)\(\)"#).unwrap();
+
+        let mut served = 0;
+        for r in route.captures_iter(include_str!("mod.rs")) {
+            let (path, method) = (&r[1], &r[2]);
+            if path == "/openapi.json" {
+                continue;
+            }
+            assert!(
+                doc["paths"][path][method].is_object(),
+                "{} {} is not documented",
+                method,
+                path
+            );
+            served += 1;
+        }
+
+        let documented: usize = doc["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|p| p.as_object().unwrap().len())
+            .sum();
+        assert_eq!(served, documented, "documented routes that are not served");
+    }
+
+    #[test]
+    fn documents_every_field() {
+        let schemas = schemas();
+        let check = |schema: &str, mut fields: Vec<&str>| {
+            let mut documented: Vec<&str> = match schemas[schema]["properties"].as_object() {
+                Some(p) => p.keys().map(|k| k.as_str()).collect(),
+                None => panic!("{} is not documented", schema),
+            };
+            documented.sort();
+            fields.sort();
+            assert_eq!(documented, fields, "{}", schema);
+        };
+
+        check("User", fields::<SafeSerializableUser>());
+        check("NewUser", fields::<add_user::Args>());
+        check("UserChanges", fields::<update_user::Changes>());
+        check("ExportedUser", fields::<ExportedUser>());
+        check("Import", fields::<import_users::Args>());
+        check("ImportReport", fields::<ImportReport>());
+        check("PublicRule", fields::<PublicRule>());
+        check("Session", fields::<SessionInfo>());
+
+        // Only ever serialized
+        let message = serde_json::to_value(Message::new("code").field("field").arg("arg", 1)).unwrap();
+        check(
+            "Message",
+            message.as_object().unwrap().keys().map(|k| k.as_str()).collect(),
+        );
+
+        let export = &document("")["paths"]["/export"]["get"]["parameters"];
+        let params: Vec<&str> = export
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|p| p["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(params, fields::<export_users::Args>());
+    }
+}
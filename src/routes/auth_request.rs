@@ -1,19 +1,284 @@
 use actix_web::{http::StatusCode, HttpRequest, HttpResponse};
+use percent_encoding::percent_decode_str;
 
-use crate::{sessions::SessionStore, shared_data::Sharable};
+use crate::{
+    access::{AccessControl, NetworkAccess},
+    sessions::SessionStore,
+    shared_data::Sharable,
+    tls::ClientCertificate,
+};
 
 use super::simple_response;
 
+/// The request nginx is asking about, taken from the X-Original-* headers
+/// set in potato_auth.nginx.conf. Falls back to the auth request itself if
+/// the headers are missing.
+struct OriginalRequest {
+    host: String,
+    method: String,
+    path: String,
+}
+
+impl OriginalRequest {
+    /// None if the original path can't be used safely
+    fn from(req: &HttpRequest) -> Option<Self> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.to_string())
+        };
+
+        let path = match req.headers().get("X-Original-URI") {
+            Some(uri) => match uri.to_str() {
+                Ok(uri) => normalize_path(uri.split('?').next().unwrap_or_default()),
+                Err(_) => None,
+            },
+            None => normalize_path(req.path()),
+        };
+        let path = match path {
+            Some(p) => p,
+            None => return None,
+        };
+
+        let method = header("X-Original-Method").unwrap_or(req.method().to_string());
+
+        let host = header("X-Original-Host").or(header("Host")).unwrap_or_default();
+        // Drop the port, but not the colons of an IPv6 address
+        let host = match host.rsplit_once(':') {
+            Some((h, port)) if !port.contains(']') => h.to_string(),
+            _ => host,
+        };
+
+        return Some(OriginalRequest { host, method, path });
+    }
+}
+
+/// Decodes the path and resolves `.` and `..` the way nginx does before it
+/// picks a location, so rules match what is actually served. None if the path
+/// isn't valid UTF-8 or climbs above the root.
+fn normalize_path(raw: &str) -> Option<String> {
+    let decoded = match percent_decode_str(raw).decode_utf8() {
+        Ok(p) => p,
+        Err(_) => return None,
+    };
+    if !decoded.starts_with('/') {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in decoded.split('/').skip(1) {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => match segments.pop() {
+                Some(_) => {}
+                None => return None,
+            },
+            s => segments.push(s),
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+    if trailing_slash && !segments.is_empty() {
+        path.push('/');
+    }
+    return Some(path);
+}
+
 pub async fn get(req: HttpRequest) -> HttpResponse {
+    // Only nginx has a certificate from the client CA, when one is set
+    if req.conn_data::<ClientCertificate>() == Some(&ClientCertificate::Missing) {
+        tracing::info!("Refused auth request without a client certificate from {:?}", req.peer_addr());
+        return simple_response(StatusCode::FORBIDDEN);
+    }
+
+    let original = match OriginalRequest::from(&req) {
+        Some(o) => o,
+        None => {
+            tracing::info!("Refused auth request for an invalid path from {:?}", req.peer_addr());
+            return simple_response(StatusCode::FORBIDDEN);
+        }
+    };
+
+    let access = AccessControl::extract_from(&req).read().unwrap();
+    let client = access.client_addr(&req);
+    match access.network_access(client, &original.path) {
+        NetworkAccess::Denied => {
+            tracing::info!("Denied access to {} from {:?}", original.path, client);
+            return simple_response(StatusCode::FORBIDDEN);
+        }
+        NetworkAccess::Trusted => return HttpResponse::Ok().finish(),
+        NetworkAccess::RequireLogin => {}
+    }
+
+    if access.is_public(&original.host, &original.method, &original.path) {
+        return HttpResponse::Ok().finish();
+    }
+
     let mut sessions_w = SessionStore::extract_from(&req).write().unwrap();
     let sess = match sessions_w.get_from_request(&req) {
         Some(s) => s,
         None => return simple_response(StatusCode::UNAUTHORIZED),
     };
 
-    if !sess.get_user().read().unwrap().path_allowed(req.path()) {
+    if sess.is_restricted() {
+        return simple_response(StatusCode::UNAUTHORIZED);
+    }
+
+    let user = sess.get_user();
+    let user = user.read().unwrap();
+    if !user.path_allowed(&original.path) {
         return simple_response(StatusCode::NOT_FOUND);
     }
 
+    if !access.allowed_now(&user, &original.path) {
+        tracing::info!("{} denied access to {} outside of schedule", user.get_name(), original.path);
+        return simple_response(StatusCode::FORBIDDEN);
+    }
+
     return HttpResponse::Ok().finish();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use http::StatusCode;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::{NetworkConfig, NetworkRule, PublicRule, Schedule, ScheduleRule, UserConfig},
+        routes::tests::{login_client, make_client, make_test_userdb, send_get},
+        userdb::AcctType,
+    };
+
+    #[test]
+    fn normalize_path() {
+        let normalize = super::normalize_path;
+        assert_eq!(normalize("/media/movies").unwrap(), "/media/movies");
+        assert_eq!(normalize("/media/movies/").unwrap(), "/media/movies/");
+        assert_eq!(normalize("/").unwrap(), "/");
+        assert_eq!(normalize("/media/../admin").unwrap(), "/admin");
+        assert_eq!(normalize("/.well-known/./../private/x").unwrap(), "/private/x");
+        assert_eq!(normalize("/media/%2e%2e/admin").unwrap(), "/admin");
+        assert_eq!(normalize("/media%2F..%2Fadmin").unwrap(), "/admin");
+        assert_eq!(normalize("//media///x/..").unwrap(), "/media/");
+        assert_eq!(normalize("/caf%C3%A9").unwrap(), "/café");
+        assert!(normalize("/../etc/passwd").is_none());
+        assert!(normalize("/media/../../admin").is_none());
+        assert!(normalize("/%ff").is_none());
+        assert!(normalize("media").is_none());
+    }
+
+    #[tokio::test]
+    async fn get_normalized_path() {
+        const PORT: u16 = 8635;
+        let url = format!("http://localhost:{}/authrequest", PORT);
+
+        let udb = make_test_userdb();
+        let users = std::fs::read_to_string(&udb).unwrap().replace(":*:User", ":/media/**:User");
+        std::fs::write(&udb, users).unwrap();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(udb);
+        cfg.port = Some(PORT);
+        cfg.public_rules = Some(vec![PublicRule {
+            path: "/.well-known/**".to_string(),
+            ..Default::default()
+        }]);
+        start_test_server(cfg);
+
+        let client = make_client();
+        let auth_request = |uri: &'static str| {
+            let request = client.get(&url).header("X-Original-URI", uri);
+            async move { request.send().await.expect("Can't send get request").status() }
+        };
+        assert_eq!(auth_request("/.well-known/security.txt").await, StatusCode::OK);
+        assert_eq!(auth_request("/.well-known/../private/x").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(auth_request("/.well-known/%2E%2E/private/x").await, StatusCode::UNAUTHORIZED);
+
+        login_client(&client, PORT, AcctType::User).await;
+        assert_eq!(auth_request("/media/movie.mkv").await, StatusCode::OK);
+        assert_eq!(auth_request("/media/../admin").await, StatusCode::NOT_FOUND);
+        assert_eq!(auth_request("/media/..%2Fadmin").await, StatusCode::NOT_FOUND);
+        assert_eq!(auth_request("/media/../../admin").await, StatusCode::FORBIDDEN);
+
+        // Without X-Original-URI the user's paths are matched against the auth
+        // request itself, as they always were before the header was read
+        assert_eq!(send_get(&client, &url).await.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn get_outside_schedule() {
+        const PORT: u16 = 8653;
+        let url = format!("http://localhost:{}/authrequest", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        // A schedule without windows never allows access
+        cfg.schedules = Some(HashMap::from([("never".to_string(), Schedule::default())]));
+        cfg.path_schedules = Some(vec![ScheduleRule {
+            path: "/games/**".to_string(),
+            schedule: "never".to_string(),
+        }]);
+        start_test_server(cfg);
+
+        let client = make_client();
+        // The rule is matched against the path nginx is asking about, not /authrequest
+        let auth_request = |uri: &'static str| {
+            let request = client.get(&url).header("X-Original-URI", uri);
+            async move { request.send().await.expect("Can't send get request").status() }
+        };
+        assert_eq!(auth_request("/games/tetris").await, StatusCode::UNAUTHORIZED);
+
+        login_client(&client, PORT, AcctType::User).await;
+        assert_eq!(auth_request("/games/tetris?level=2").await, StatusCode::FORBIDDEN);
+        assert_eq!(auth_request("/books/1").await, StatusCode::OK);
+        assert_eq!(send_get(&client, &url).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn get_network_rules() {
+        const PORT: u16 = 8652;
+        let url = format!("http://localhost:{}/authrequest", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.network = Some(NetworkConfig {
+            deny: vec!["203.0.113.0/24".to_string()],
+            trusted_networks: vec!["192.168.1.0/24".to_string()],
+            path_rules: vec![NetworkRule {
+                path: "/private/**".to_string(),
+                allow: vec!["192.168.1.0/24".to_string()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        start_test_server(cfg);
+
+        // Test requests come from localhost, which is a trusted proxy by default
+        let get_from = |addr: &'static str, uri: &'static str| {
+            let url = url.clone();
+            async move {
+                return make_client()
+                    .get(url)
+                    .header("X-Forwarded-For", addr)
+                    .header("X-Original-URI", uri)
+                    .send()
+                    .await
+                    .expect("Can't send get request")
+                    .status();
+            }
+        };
+
+        assert_eq!(get_from("192.168.1.20", "/media/a").await, StatusCode::OK);
+        assert_eq!(get_from("198.51.100.1", "/media/a").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(get_from("203.0.113.9", "/media/a").await, StatusCode::FORBIDDEN);
+
+        assert_eq!(get_from("192.168.1.20", "/private/a").await, StatusCode::OK);
+        assert_eq!(get_from("198.51.100.1", "/private/a").await, StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,152 @@
+use actix_web::{http::StatusCode, web::Bytes, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{i18n, sessions::SessionStore, shared_data::Sharable, userdb::UserDB};
+
+use super::{login, parse_post_body, serve_page, simple_response};
+
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    let logged_in = SessionStore::extract_from(&req)
+        .write()
+        .unwrap()
+        .get_from_request(&req)
+        .is_some();
+
+    if !logged_in {
+        return login::get(req).await;
+    }
+    return serve_page(&req, "change_password.html").await;
+}
+
+#[derive(Deserialize)]
+struct Args {
+    current_password: String,
+    new_password: String,
+}
+
+/// Changes the password of the session's user. Also accepts restricted
+/// sessions, which are replaced with a normal session on success.
+pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let sessions = SessionStore::extract_from(&req);
+    let user = match sessions.write().unwrap().get_from_request(&req) {
+        Some(s) => s.get_user(),
+        None => return simple_response(StatusCode::UNAUTHORIZED),
+    };
+
+    let args: Args = match parse_post_body(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return simple_response(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let name = user.read().unwrap().get_name().to_owned();
+    let mut user_db_w = UserDB::extract_from(&req).write().unwrap();
+
+    if user_db_w.verify_credentials(&name, &args.current_password).is_none() {
+        tracing::warn!("Password change failed for {}: incorrect current password", name);
+        return simple_response(StatusCode::UNAUTHORIZED);
+    }
+
+    let previous = user_db_w.get(&name).map(|u| u.read().unwrap().clone());
+    let target = match user_db_w.update_user(&name, Some(args.new_password), None, None) {
+        Ok(u) => u.clone(),
+        Err(e) => return HttpResponse::BadRequest().body(i18n::from_request(&req).error(&e)),
+    };
+
+    // update_user found the user, so there is a previous copy
+    match user_db_w.save_user_change(&target, previous.unwrap()) {
+        Ok(_) => {}
+        Err(e) => {
+            tracing::error!("Password for {} could not be saved: {}", name, e);
+            return simple_response(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+    drop(user_db_w);
+
+    tracing::info!("{} changed their password", name);
+    let mut sessions_w = sessions.write().unwrap();
+    sessions_w.remove_for_user(&user);
+    let sess = sessions_w.new_session(&user);
+    return HttpResponse::Ok().cookie(sess.cookie()).finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get, send_post},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn forced_change() {
+        const PORT: u16 = 8655;
+        let base = format!("http://localhost:{}", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        // Admin resets User_user's password
+        let admin = make_client();
+        login_client(&admin, PORT, AcctType::Admin).await;
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/updateuser", base),
+            &serde_json::json!({"name": "User_user", "password": "temporary pass"}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+
+        let client = make_client();
+        let resp = send_post(
+            &client,
+            &format!("{}/login", base),
+            &serde_json::json!({"username": "User_user", "password": "temporary pass"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.json::<serde_json::Value>().await.unwrap()["change_password"], true);
+
+        // Restricted session can't be used for anything but changing password
+        let resp = send_get(&client, &format!("{}/authrequest", base)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = send_get(&client, &format!("{}/changepassword", base)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let url = format!("{}/changepassword", base);
+        let resp = send_post(
+            &client,
+            &url,
+            &serde_json::json!({"current_password": "wrong pass", "new_password": "my own pass"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = send_post(
+            &client,
+            &url,
+            &serde_json::json!({"current_password": "temporary pass", "new_password": "short"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let resp = send_post(
+            &client,
+            &url,
+            &serde_json::json!({"current_password": "temporary pass", "new_password": "my own pass"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Normal session from here on
+        let resp = send_get(&client, &format!("{}/authrequest", base)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
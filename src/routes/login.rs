@@ -2,21 +2,38 @@ use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse, HttpResponseBu
 use serde::Deserialize;
 
 use crate::{
-    routes::{parse_post_body, serve_file, simple_response},
+    ldap::LdapAuthenticator,
+    oidc::OidcClient,
+    pages::PageConfig,
+    routes::{oidc, parse_post_body, serve_page, simple_response},
     sessions::SessionStore,
     shared_data::Sharable,
-    userdb::UserDB,
+    userdb::{ExternalSource, UserDB},
 };
 
 pub async fn get(req: HttpRequest) -> HttpResponse {
     let mut sessions_w = SessionStore::extract_from(&req).write().unwrap();
-    if sessions_w.get_from_request(&req).is_some() {
-        // if logged in, redirect
-        let resp = HttpResponse::Found().append_header(("Location", "/")).finish();
-        tracing::warn!("{}", resp.status());
-        return resp;
+    match sessions_w.get_from_request(&req) {
+        Some(s) if s.is_restricted() => {
+            let location = format!("{}/changepassword", PageConfig::extract_from(&req).base_path);
+            return HttpResponse::Found().append_header(("Location", location)).finish();
+        }
+        Some(_) => {
+            // if logged in, redirect
+            let resp = HttpResponse::Found().append_header(("Location", "/")).finish();
+            tracing::warn!("{}", resp.status());
+            return resp;
+        }
+        None => {}
+    }
+    drop(sessions_w);
+
+    // Logins go to the identity provider when one is configured. The local
+    // form stays reachable with /login?local so local admins can still log in.
+    if req.app_data::<web::Data<OidcClient>>().is_some() && req.query_string() != "local" {
+        return oidc::get(req).await;
     }
-    return serve_file("./static/login.html").await;
+    return serve_page(&req, "login.html").await;
 }
 
 #[derive(Deserialize)]
@@ -28,13 +45,17 @@ struct LoginForm {
 pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
     let sessions = SessionStore::extract_from(&req);
 
-    if sessions.write().unwrap().get_from_request(&req).is_some() {
+    if sessions
+        .write()
+        .unwrap()
+        .get_from_request(&req)
+        .filter(|s| !s.is_restricted())
+        .is_some()
+    {
         // don't start new session if already logged in
         return simple_response(StatusCode::OK);
     }
 
-    let user_db = UserDB::extract_from(&req).read().unwrap();
-
     let form: LoginForm = match parse_post_body(body) {
         Ok(f) => f,
         Err(e) => {
@@ -48,8 +69,45 @@ pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
         None => "???".to_string(),
     };
 
+    let user_db = UserDB::extract_from(&req);
+    let mut user = user_db
+        .read()
+        .unwrap()
+        .verify_credentials(&form.username, &form.password)
+        .cloned();
+    if let Some(u) = &user {
+        user_db.write().unwrap().upgrade_hash(u, &form.password);
+    }
+
+    // Local accounts take priority so they keep working if the directory
+    // is unavailable
+    if user.is_none() {
+        if let Some(ldap) = req.app_data::<web::Data<LdapAuthenticator>>() {
+            user = match ldap.authenticate(&form.username, &form.password).await {
+                Ok(Some(identity)) => {
+                    match user_db
+                        .write()
+                        .unwrap()
+                        .sync_external_user(&form.username, &identity.paths, identity.acct_type, ExternalSource::Ldap)
+                    {
+                        Ok(u) => Some(u),
+                        Err(e) => {
+                            tracing::warn!("Unable to use directory login for {}: {}", &form.username, e);
+                            None
+                        }
+                    }
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    tracing::error!("LDAP login unavailable: {}", e);
+                    None
+                }
+            };
+        }
+    }
+
     let resp: HttpResponse;
-    match user_db.verify_credentials(&form.username, &form.password) {
+    match user {
         Some(u) => {
             tracing::info!(
                 "Login attempt successful for {} from {}",
@@ -57,8 +115,17 @@ pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
                 origin_addr
             );
             let mut sessions_w = sessions.write().unwrap();
-            let sess = sessions_w.new_session(&u);
-            resp = HttpResponseBuilder::new(StatusCode::OK).cookie(sess.cookie()).finish();
+            if user_db.read().unwrap().password_change_required(&u) {
+                // The session can only be used to pick a new password
+                tracing::info!("{} must change their password", u.read().unwrap().get_name());
+                let sess = sessions_w.new_restricted_session(&u);
+                resp = HttpResponseBuilder::new(StatusCode::OK)
+                    .cookie(sess.cookie())
+                    .json(serde_json::json!({"change_password": true}));
+            } else {
+                let sess = sessions_w.new_session(&u);
+                resp = HttpResponseBuilder::new(StatusCode::OK).cookie(sess.cookie()).finish();
+            }
         }
         None => {
             tracing::warn!("Login attempt failed for {} from {}", &form.username, origin_addr);
@@ -74,7 +141,8 @@ mod tests {
 
     use crate::{
         app,
-        config::UserConfig,
+        config::{ThemeConfig, UserConfig},
+        ldap::tests::{mock_directory_config, start_mock_directory},
         routes::tests::{make_client, make_test_userdb, send_get, send_post},
     };
 
@@ -119,4 +187,72 @@ mod tests {
         assert_eq!(resp.status(), StatusCode::NOT_FOUND);
         /* ^^^ this is 404 because it redirects to '/', which doesn't exist */
     }
+
+    #[tokio::test]
+    async fn get_themed() {
+        const PORT: u16 = 8642;
+        let url = format!("http://localhost:{}/login", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.theme = Some(ThemeConfig {
+            title: "Family Cloud".to_string(),
+            accent_color: Some("rebeccapurple".to_string()),
+            message: Some("Ask Dad for an account".to_string()),
+            ..Default::default()
+        });
+
+        app::tests::start_test_server(cfg);
+
+        let resp = send_get(&make_client(), &url).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page = resp.text().await.unwrap();
+        assert!(page.contains("<title>Log In - Family Cloud</title>"));
+        assert!(page.contains("<span class=\"courier\">Family Cloud</span>"));
+        assert!(page.contains("background-color: rebeccapurple;"));
+        assert!(page.contains("Ask Dad for an account"));
+    }
+
+    #[tokio::test]
+    async fn post_login_ldap() {
+        const PORT: u16 = 8660;
+        const LDAP_PORT: u16 = 8659;
+        let url = format!("http://localhost:{}/login", PORT);
+        start_mock_directory(LDAP_PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.ldap = Some(mock_directory_config(LDAP_PORT));
+        app::tests::start_test_server(cfg);
+
+        let resp = send_post(
+            &make_client(),
+            &url,
+            &serde_json::json!({"username": "alice", "password": "not_a_password" }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let client = make_client();
+        let resp = send_post(
+            &client,
+            &url,
+            &serde_json::json!({"username": "alice", "password": "alice_pass" }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send_get(&client, &format!("http://localhost:{}/admin", PORT)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Local accounts still work alongside the directory
+        let resp = send_post(
+            &make_client(),
+            &url,
+            &serde_json::json!({"username": "Admin_user", "password": "password" }),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
 }
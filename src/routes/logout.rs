@@ -1,10 +1,10 @@
 use actix_web::{HttpRequest, HttpResponse};
 
-use super::{remove_session, serve_file};
+use super::{remove_session, serve_page};
 
 pub async fn get(req: HttpRequest) -> HttpResponse {
     remove_session(&req);
-    return serve_file("./static/logout.html").await;
+    return serve_page(&req, "logout.html").await;
 }
 
 #[cfg(test)]
@@ -1,22 +1,36 @@
 use std::{
-    path::Path,
+    borrow::Cow,
     sync::{Arc, RwLock},
 };
 
 use actix_web::{
-    http::StatusCode,
+    http::{
+        header::{HeaderValue, VARY},
+        StatusCode,
+    },
     web::{Buf, Bytes},
     HttpRequest, HttpResponse, HttpResponseBuilder,
 };
 
 use serde::de::DeserializeOwned;
 
-use crate::{sessions::SessionStore, shared_data::Sharable, userdb::User};
+use crate::{
+    assets::{self, Assets},
+    i18n::{self, Message},
+    pages::PageConfig,
+    sessions::SessionStore,
+    shared_data::Sharable,
+    userdb::User,
+};
 
 pub mod admin;
+pub mod api;
 pub mod auth_request;
+pub mod change_password;
 pub mod login;
 pub mod logout;
+pub mod oidc;
+pub mod recover;
 pub mod rpc;
 pub mod setup;
 pub mod static_dir;
@@ -34,21 +48,30 @@ where
 {
     match serde_json::from_reader(body.reader()) {
         Ok(a) => return Ok(a),
-        Err(e) => return Err(crate::Error::convert(e)),
+        Err(e) => return Err(crate::Error::invalid(Message::new("request.invalid_body").arg("error", e))),
     };
 }
 
+/// Gets the user of the request's session. Restricted sessions are ignored.
 fn req_user(req: &HttpRequest) -> Option<Arc<RwLock<User>>> {
     let mut sessions_w = SessionStore::extract_from(&req).write().unwrap();
-    return Some(sessions_w.get_from_request(&req)?.get_user().clone());
+    let sess = sessions_w.get_from_request(req)?;
+    if sess.is_restricted() {
+        return None;
+    }
+    return Some(sess.get_user().clone());
 }
 
-#[inline]
-pub async fn serve_file(filepath: impl AsRef<Path>) -> HttpResponse {
-    return match tokio::fs::read(filepath).await {
-        Ok(contents) => HttpResponse::Ok().body(contents),
-        Err(_) => simple_response(StatusCode::NOT_FOUND),
+/// Serves an HTML page with its placeholders filled in
+pub async fn serve_page(req: &HttpRequest, name: &str) -> HttpResponse {
+    let contents = match Assets::extract_from(req).get(name).await {
+        Some(c) => String::from_utf8_lossy(&c).into_owned(),
+        None => return simple_response(StatusCode::NOT_FOUND),
     };
+    let contents = PageConfig::extract_from(req).render(&contents, i18n::from_request(req));
+    let mut resp = assets::respond(req, name, Cow::Owned(contents.into_bytes()), assets::PAGE_CACHE);
+    resp.headers_mut().insert(VARY, HeaderValue::from_static("Accept-Language"));
+    return resp;
 }
 
 /// Clears session data from req
@@ -0,0 +1,431 @@
+use actix_web::{
+    cookie::{Cookie, CookieBuilder, SameSite},
+    http::StatusCode,
+    web::Query,
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+
+use crate::{
+    oidc::{OidcClient, PENDING_TTL},
+    pages::PageConfig,
+    routes::simple_response,
+    sessions::SessionStore,
+    shared_data::Sharable,
+    userdb::{AcctType, ExternalSource, UserDB},
+};
+
+/// Ties a login to the browser that started it, so nobody can finish their
+/// own login in someone else's browser
+const STATE_COOKIE: &str = "potato_auth_oidc_state";
+
+/// Lax so it is sent along with the provider's redirect back to the callback
+fn state_cookie<'a>(req: &HttpRequest, state: &'a str) -> Cookie<'a> {
+    return CookieBuilder::new(STATE_COOKIE, state)
+        .path(format!("{}/login", PageConfig::extract_from(req).base_path))
+        .max_age(PENDING_TTL)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish();
+}
+
+/// Redirects to the identity provider's login page
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    let client = OidcClient::extract_from(&req);
+    return match client.authorization_url().await {
+        Ok((url, state)) => HttpResponse::Found()
+            .append_header(("Location", url))
+            .cookie(state_cookie(&req, &state))
+            .finish(),
+        Err(e) => {
+            tracing::error!("{}", e);
+            simple_response(StatusCode::BAD_GATEWAY)
+        }
+    };
+}
+
+#[derive(Deserialize)]
+pub struct CallbackArgs {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+/// Receives the redirect back from the identity provider, then maps the
+/// identity to a user and starts a session
+pub async fn callback(req: HttpRequest, args: Query<CallbackArgs>) -> HttpResponse {
+    let args = args.into_inner();
+    let (code, state) = match (args.code, args.state) {
+        (Some(c), Some(s)) => (c, s),
+        _ => {
+            tracing::warn!(
+                "Identity provider login failed: {}",
+                args.error.unwrap_or("missing code or state".to_string())
+            );
+            return simple_response(StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    match req.cookie(STATE_COOKIE) {
+        Some(c) if c.value() == state => {}
+        _ => {
+            tracing::warn!("Identity provider login failed: state was not issued to this browser");
+            return simple_response(StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    let client = OidcClient::extract_from(&req);
+    let name = match client.complete(&code, &state).await {
+        Ok(n) => n,
+        Err(e) => {
+            tracing::warn!("Identity provider login failed: {}", e);
+            return simple_response(StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    let origin_addr = match req.peer_addr() {
+        Some(a) => a.to_string(),
+        None => "???".to_string(),
+    };
+
+    let user_db = UserDB::extract_from(&req);
+    let existing = user_db.read().unwrap().get(&name).cloned();
+    let user = match existing {
+        // The provider must never be able to log in as a local account or
+        // one that belongs to the directory
+        Some(u) if !u.read().unwrap().is_owned_by(ExternalSource::Oidc) => {
+            tracing::warn!(
+                "Identity provider login for account {} not owned by the provider from {} refused",
+                name,
+                origin_addr
+            );
+            return simple_response(StatusCode::UNAUTHORIZED);
+        }
+        Some(u) if !u.read().unwrap().is_active() => {
+            tracing::warn!(
+                "Identity provider login for disabled account {} from {}",
+                name,
+                origin_addr
+            );
+            return simple_response(StatusCode::UNAUTHORIZED);
+        }
+        Some(u) => u,
+        None => {
+            if !client.config().auto_provision {
+                tracing::warn!("Identity provider login for unknown user {} from {}", name, origin_addr);
+                return simple_response(StatusCode::UNAUTHORIZED);
+            }
+            match user_db.write().unwrap().add_external_user(
+                &name,
+                &client.config().default_paths,
+                AcctType::User,
+                ExternalSource::Oidc,
+            ) {
+                Ok(u) => {
+                    tracing::info!("Provisioned user {} from identity provider", name);
+                    u.clone()
+                }
+                Err(e) => {
+                    tracing::error!("Unable to provision user {}: {}", name, e);
+                    return simple_response(StatusCode::UNAUTHORIZED);
+                }
+            }
+        }
+    };
+
+    tracing::info!("Identity provider login successful for {} from {}", name, origin_addr);
+    let mut sessions_w = SessionStore::extract_from(&req).write().unwrap();
+    let sess = sessions_w.new_session(&user);
+    let mut used_state = state_cookie(&req, "");
+    used_state.make_removal();
+    return HttpResponse::Found()
+        .append_header(("Location", "/"))
+        .cookie(sess.cookie())
+        .cookie(used_state)
+        .finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use http::StatusCode;
+    use ring::digest;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::{OidcConfig, UserConfig},
+        routes::tests::make_test_userdb,
+        test_utils::make_tmp_file,
+    };
+
+    /// Minimal provider that always identifies the user as `oidc_user`, who
+    /// claims to be the local `Admin_user`. Its codes are `<nonce>.<code_challenge>`
+    /// of the login they were issued for. Without id_token it acts as a plain
+    /// OAuth2 provider.
+    fn start_mock_issuer(port: u16, id_token: bool) {
+        let issuer = format!("http://localhost:{}", port);
+        let srv = HttpServer::new(move || {
+            let issuer = issuer.clone();
+            let token_issuer = issuer.clone();
+            App::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(move || {
+                        let issuer = issuer.clone();
+                        async move {
+                            HttpResponse::Ok().json(serde_json::json!({
+                                "issuer": issuer,
+                                "authorization_endpoint": format!("{}/authorize", issuer),
+                                "token_endpoint": format!("{}/token", issuer),
+                                "userinfo_endpoint": format!("{}/userinfo", issuer),
+                            }))
+                        }
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(move |form: web::Form<HashMap<String, String>>| {
+                        let issuer = token_issuer.clone();
+                        async move {
+                            let (nonce, challenge) = form["code"].split_once('.').unwrap();
+                            let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+                            let hashed = digest::digest(&digest::SHA256, verifier.as_bytes());
+                            if URL_SAFE_NO_PAD.encode(hashed) != challenge {
+                                return HttpResponse::BadRequest().finish();
+                            }
+                            let mut token = serde_json::json!({"access_token": "token", "token_type": "Bearer"});
+                            if id_token {
+                                let claims = serde_json::json!({"iss": issuer, "aud": "client", "nonce": nonce});
+                                token["id_token"] =
+                                    format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims.to_string())).into();
+                            }
+                            HttpResponse::Ok().json(token)
+                        }
+                    }),
+                )
+                .route(
+                    "/userinfo",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .json(serde_json::json!({"sub": "oidc_user", "preferred_username": "Admin_user"}))
+                    }),
+                )
+        })
+        .bind(("localhost", port))
+        .unwrap()
+        .run();
+        tokio::spawn(srv);
+    }
+
+    fn make_no_redirect_client() -> reqwest::Client {
+        return reqwest::ClientBuilder::new()
+            .cookie_store(true)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+    }
+
+    fn oidc_config(port: u16, issuer_port: u16) -> OidcConfig {
+        return OidcConfig {
+            issuer: format!("http://localhost:{}", issuer_port),
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: format!("http://localhost:{}/login/oidc/callback", port),
+            auto_provision: true,
+            default_paths: vec!["/media/**".to_string()],
+            ..Default::default()
+        };
+    }
+
+    /// Follows /login to the provider and returns the state it was given with
+    /// the code the provider would send back
+    async fn start_login(client: &reqwest::Client, port: u16) -> (String, String) {
+        let resp = client
+            .get(format!("http://localhost:{}/login", port))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+        let location = reqwest::Url::parse(resp.headers()["Location"].to_str().unwrap()).unwrap();
+        assert_eq!(location.path(), "/authorize");
+        let param = |name: &str| {
+            location
+                .query_pairs()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_string())
+                .unwrap()
+        };
+        assert_eq!(param("code_challenge_method"), "S256");
+        return (
+            param("state"),
+            format!("{}.{}", param("nonce"), param("code_challenge")),
+        );
+    }
+
+    #[tokio::test]
+    async fn oidc_login() {
+        const PORT: u16 = 8663;
+        const ISSUER_PORT: u16 = 8662;
+        start_mock_issuer(ISSUER_PORT, true);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        // The state cookie is scoped to base_path, which no nginx strips here
+        cfg.base_path = Some(String::new());
+        cfg.oidc = Some(oidc_config(PORT, ISSUER_PORT));
+        start_test_server(cfg);
+
+        let client = make_no_redirect_client();
+        let (state, code) = start_login(&client, PORT).await;
+
+        let callback = format!("http://localhost:{}/login/oidc/callback", PORT);
+        let resp = client
+            .get(format!("{}?code={}&state=not_the_state", callback, code))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Someone else's browser can't be made to finish this login
+        let resp = make_no_redirect_client()
+            .get(format!("{}?code={}&state={}", callback, code, state))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Nor can a code issued for another login
+        let (other_state, _) = start_login(&client, PORT).await;
+        let resp = client
+            .get(format!("{}?code={}&state={}", callback, code, other_state))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let (state, code) = start_login(&client, PORT).await;
+        let resp = client
+            .get(format!("{}?code={}&state={}", callback, code, state))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        // State can only be used once
+        let resp = client
+            .get(format!("{}?code={}&state={}", callback, code, state))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = client
+            .get(format!("http://localhost:{}/authrequest", PORT))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND); // logged in, but path not allowed
+
+        // Local logins remain available
+        let resp = reqwest::get(format!("http://localhost:{}/login?local", PORT))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn oidc_local_account() {
+        const PORT: u16 = 8637;
+        const ISSUER_PORT: u16 = 8636;
+        start_mock_issuer(ISSUER_PORT, true);
+
+        // A claim the user controls names a local admin
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.base_path = Some(String::new());
+        cfg.oidc = Some(OidcConfig {
+            username_claim: "preferred_username".to_string(),
+            ..oidc_config(PORT, ISSUER_PORT)
+        });
+        start_test_server(cfg);
+
+        let client = make_no_redirect_client();
+        let (state, code) = start_login(&client, PORT).await;
+        let resp = client
+            .get(format!(
+                "http://localhost:{}/login/oidc/callback?code={}&state={}",
+                PORT, code, state
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn oidc_directory_account() {
+        const PORT: u16 = 8634;
+        const ISSUER_PORT: u16 = 8633;
+        start_mock_issuer(ISSUER_PORT, true);
+
+        // The claim names an admin created by LDAP
+        let udb = make_tmp_file();
+        std::fs::write(&udb, "Admin_user:!:*:Admin:source=ldap\n").unwrap();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(udb);
+        cfg.port = Some(PORT);
+        cfg.base_path = Some(String::new());
+        cfg.oidc = Some(OidcConfig {
+            username_claim: "preferred_username".to_string(),
+            ..oidc_config(PORT, ISSUER_PORT)
+        });
+        start_test_server(cfg);
+
+        let client = make_no_redirect_client();
+        let (state, code) = start_login(&client, PORT).await;
+        let resp = client
+            .get(format!(
+                "http://localhost:{}/login/oidc/callback?code={}&state={}",
+                PORT, code, state
+            ))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn oauth2_login() {
+        const PORT: u16 = 8632;
+        const ISSUER_PORT: u16 = 8631;
+        start_mock_issuer(ISSUER_PORT, false);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.base_path = Some(String::new());
+        cfg.oidc = Some(oidc_config(PORT, ISSUER_PORT));
+        start_test_server(cfg);
+
+        // The user is provisioned from userinfo alone, and logs in to the
+        // same account the second time
+        for _ in 0..2 {
+            let client = make_no_redirect_client();
+            let (state, code) = start_login(&client, PORT).await;
+            let resp = client
+                .get(format!(
+                    "http://localhost:{}/login/oidc/callback?code={}&state={}",
+                    PORT, code, state
+                ))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::FOUND);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::{sync::Mutex, time::Duration};
+
+use actix_web::{http::StatusCode, web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    app::{ExitCommand, ServerController},
+    i18n::{self, Message},
+    routes::simple_response,
+    shared_data::Sharable,
+    userdb::{AcctType, UserDB},
+    Error, Result,
+};
+
+use super::{parse_post_body, serve_page};
+
+/// One-time token printed to the log when the server starts with --recover.
+/// It is used up by the first successful recovery.
+pub struct RecoveryToken {
+    token: Mutex<Option<String>>,
+}
+
+impl RecoveryToken {
+    pub fn new() -> Self {
+        return RecoveryToken {
+            token: Mutex::new(Some(Uuid::new_v4().simple().to_string())),
+        };
+    }
+
+    pub fn get(&self) -> Option<String> {
+        return self.token.lock().unwrap().clone();
+    }
+
+    /// Checks token without revealing how much of it matched through timing
+    fn matches(&self, token: &str) -> bool {
+        return match self.token.lock().unwrap().as_deref() {
+            Some(t) if t.len() == token.len() => t.bytes().zip(token.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0,
+            _ => false,
+        };
+    }
+}
+
+impl Sharable for RecoveryToken {
+    type Shared = RecoveryToken;
+    fn to_sharable(self) -> web::Data<Self::Shared> {
+        return web::Data::new(self);
+    }
+}
+
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    return serve_page(&req, "recover.html").await;
+}
+
+#[derive(Deserialize)]
+struct Args {
+    token: String,
+    name: String,
+    password: String,
+}
+
+pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
+    let args = match parse_post_body::<Args>(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body sent to /recover: {}", e);
+            return simple_response(StatusCode::BAD_REQUEST);
+        }
+    };
+
+    let token = RecoveryToken::extract_from(&req);
+    let message = match recover_admin(&req, token, &args) {
+        Ok(m) => m,
+        Err(e) => {
+            tracing::warn!("Recovery failed: {}", e);
+            return HttpResponse::build(e.status()).body(i18n::from_request(&req).error(&e));
+        }
+    };
+    tracing::warn!("Recovery: {}", message);
+
+    let signaller = ServerController::extract_from(&req).clone();
+    tokio::task::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        _ = signaller.send_exit(ExitCommand::Restart, false).await;
+    });
+
+    return HttpResponse::Ok().body(i18n::from_request(&req).format(&message));
+}
+
+/// Resets the password of the admin called args.name, enabling it if needed,
+/// or adds it as a new admin. Other accounts are never changed.
+fn recover_admin(req: &HttpRequest, token: &RecoveryToken, args: &Args) -> Result<Message> {
+    // Held while the token is checked and used up so it only works once
+    let mut user_db = UserDB::extract_from(req).write().unwrap();
+    if !token.matches(&args.token) {
+        return Err(Error::Forbidden(Message::new("recover.invalid_token").field("token")));
+    }
+
+    let message = match user_db.get(&args.name) {
+        Some(u) if u.read().unwrap().get_type() != &AcctType::Admin => {
+            return Err(Error::Conflict(
+                Message::new("recover.not_admin").field("name").arg("name", &args.name),
+            ));
+        }
+        Some(u) => {
+            let previous = u.read().unwrap().clone();
+            let user = match user_db.update_user(&args.name, Some(args.password.to_owned()), None, None) {
+                Ok(u) => u.clone(),
+                Err(e) => return Err(e),
+            };
+            let mut user_w = user.write().unwrap();
+            user_w.set_disabled(false);
+            user_w.set_expires_at(None);
+            drop(user_w);
+            match user_db.save_user_change(&user, previous) {
+                Ok(_) => Message::new("recover.reset").arg("name", &args.name),
+                Err(e) => return Err(e),
+            }
+        }
+        None => match user_db.add_user(&args.name, &args.password, &vec!["*".to_string()], AcctType::Admin) {
+            Ok(_) => Message::new("recover.added").arg("name", &args.name),
+            Err(e) => return Err(e),
+        },
+    };
+
+    *token.token.lock().unwrap() = None;
+    return Ok(message);
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use http::StatusCode;
+
+    use super::*;
+    use crate::{
+        app,
+        config::UserConfig,
+        routes::tests::{make_client, make_test_userdb, send_get, send_post},
+    };
+
+    #[test]
+    fn recover_admin_account() {
+        let udb = make_test_userdb();
+        let user_db = UserDB::new(&udb).unwrap().to_sharable();
+        let req = TestRequest::default().app_data(user_db.clone()).to_http_request();
+        let token = RecoveryToken::new();
+        let args = |token: &str, name: &str| Args {
+            token: token.to_string(),
+            name: name.to_string(),
+            password: "mashed potatoes".to_string(),
+        };
+
+        assert!(matches!(
+            recover_admin(&req, &token, &args("nope", "Admin_user")),
+            Err(Error::Forbidden(_))
+        ));
+        let t = token.get().unwrap();
+        assert!(matches!(
+            recover_admin(&req, &token, &args(&t, "User_user")),
+            Err(Error::Conflict(_))
+        ));
+
+        user_db
+            .read()
+            .unwrap()
+            .get(&"Admin_user".to_string())
+            .unwrap()
+            .write()
+            .unwrap()
+            .set_disabled(true);
+        recover_admin(&req, &token, &args(&t, "Admin_user")).unwrap();
+
+        // Enabled again with the new password, and the other user untouched
+        let saved = UserDB::new(&udb).unwrap();
+        assert!(saved
+            .verify_credentials(&"Admin_user".to_string(), &"mashed potatoes".to_string())
+            .is_some());
+        assert!(saved
+            .verify_credentials(&"User_user".to_string(), &"password".to_string())
+            .is_some());
+
+        // Used up
+        assert!(token.get().is_none());
+        assert!(matches!(
+            recover_admin(&req, &token, &args(&t, "new_admin")),
+            Err(Error::Forbidden(_))
+        ));
+
+        let token = RecoveryToken::new();
+        recover_admin(&req, &token, &args(&token.get().unwrap(), "new_admin")).unwrap();
+        let saved = UserDB::new(&udb).unwrap();
+        assert_eq!(saved.count(), 3);
+        assert_eq!(
+            saved.get(&"new_admin".to_string()).unwrap().read().unwrap().get_type(),
+            &AcctType::Admin
+        );
+    }
+
+    #[tokio::test]
+    async fn recovery_mode() {
+        const PORT: u16 = 8638;
+        let url = |path: &str| format!("http://localhost:{}{}", PORT, path);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.recover = true;
+        app::tests::start_test_server(cfg);
+        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+        let client = make_client();
+        assert_eq!(send_get(&client, &url("/recover")).await.status(), StatusCode::OK);
+        assert_eq!(send_get(&client, &url("/login")).await.status(), StatusCode::NOT_FOUND);
+
+        let payload = serde_json::json!({"token": "guess", "name": "Admin_user", "password": "mashed potatoes"});
+        assert_eq!(
+            send_post(&client, &url("/recover"), &payload).await.status(),
+            StatusCode::FORBIDDEN
+        );
+    }
+}
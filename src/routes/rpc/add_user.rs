@@ -1,13 +1,14 @@
-use actix_web::{http::StatusCode, web::Bytes, HttpRequest, HttpResponse};
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
 use serde::Deserialize;
 
 use crate::{
-    routes::{parse_post_body, simple_response},
+    i18n::Message,
+    routes::parse_post_body,
     shared_data::Sharable,
     userdb::{AcctType, UserDB},
 };
 
-use super::rpc_response;
+use super::{rpc_error, rpc_message};
 
 #[derive(Deserialize)]
 pub struct Args {
@@ -22,7 +23,7 @@ pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
         Ok(a) => a,
         Err(e) => {
             tracing::error!("Bad post body: {}", e);
-            return simple_response(StatusCode::BAD_REQUEST);
+            return rpc_error(&req, e);
         }
     };
 
@@ -34,8 +35,8 @@ pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
         &args.paths,
         args.acct_type,
     ) {
-        Ok(_) => rpc_response(true, format!("{} account added", args.name)),
-        Err(e) => rpc_response(false, e.message),
+        Ok(_) => rpc_message(&req, Message::new("user.added").arg("name", &args.name)),
+        Err(e) => rpc_error(&req, e),
     };
     return resp;
 }
@@ -70,13 +71,41 @@ mod tests {
         login_client(&client, PORT, AcctType::Admin).await;
 
         let resp = send_post(&client, &url, &bad_payload).await;
-        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
         assert!(resp.text().await.unwrap().contains("false"));
 
+        // Errors are translated and carry a stable code
+        let resp = client
+            .post(&url)
+            .header("Accept-Language", "de-DE, en;q=0.5")
+            .json(&bad_payload)
+            .send()
+            .await
+            .expect("can't send post request");
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["code"], "invalid");
+        assert_eq!(body["response"], "Passwort zu kurz (mindestens 8 Zeichen)");
+        assert_eq!(
+            body["errors"],
+            serde_json::json!([{"code": "password.too_short", "field": "password", "args": {"min": "8"}}])
+        );
+
+        let resp = client.post(&url).body("{\"name\": 5}").send().await.expect("can't send post request");
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["errors"][0]["code"], "request.invalid_body");
+
         let resp = send_post(&client, &url, &payload).await;
         assert_eq!(resp.status(), StatusCode::OK);
         assert!(resp.text().await.unwrap().contains("true"));
 
+        let resp = send_post(&client, &url, &payload).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["code"], "conflict");
+        assert_eq!(body["errors"][0]["code"], "user.exists");
+        assert_eq!(body["errors"][0]["field"], "name");
+
         // Try logging in with new acct
         let client = make_client();
         assert_eq!(
@@ -0,0 +1,163 @@
+use std::sync::{Arc, RwLock};
+
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_message},
+    },
+    sessions::SessionStore,
+    shared_data::Sharable,
+    userdb::{User, UserDB},
+    Error,
+};
+
+#[derive(Deserialize)]
+struct Args {
+    name: String,
+}
+
+/// Handles both disableuser and enableuser
+pub async fn post(req: HttpRequest, body: Bytes, user: Arc<RwLock<User>>, disabled: bool) -> HttpResponse {
+    let args: Args = match parse_post_body(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return rpc_error(&req, e);
+        }
+    };
+
+    return set_disabled(&req, &args.name, user, disabled);
+}
+
+/// Disables or enables the account called name. user is the admin changing it.
+pub fn set_disabled(req: &HttpRequest, name: &String, user: Arc<RwLock<User>>, disabled: bool) -> HttpResponse {
+    if disabled && user.read().unwrap().get_name() == name {
+        return rpc_error(
+            req,
+            Error::Forbidden(Message::new("user.cannot_disable_self").field("name")),
+        );
+    }
+
+    let mut user_db = UserDB::extract_from(req).write().unwrap();
+    let target = match user_db.get(name) {
+        Some(u) => u.clone(),
+        None => {
+            return rpc_error(
+                req,
+                Error::NotFound(Message::new("user.not_found").field("name").arg("name", name)),
+            )
+        }
+    };
+
+    let previous = target.read().unwrap().clone();
+    target.write().unwrap().set_disabled(disabled);
+    match user_db.save_user_change(&target, previous) {
+        Ok(_) => {}
+        Err(e) => return rpc_error(req, Error::Internal(Message::new("user.not_saved").arg("error", e))),
+    }
+
+    if disabled {
+        SessionStore::extract_from(req)
+            .write()
+            .unwrap()
+            .remove_for_user(&target);
+    }
+
+    let state = if disabled { "disabled" } else { "enabled" };
+    tracing::info!("{} {} {}", user.read().unwrap().get_name(), state, name);
+    let code = if disabled { "user.disabled" } else { "user.enabled" };
+    return rpc_message(req, Message::new(code).arg("name", name));
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get, send_post},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn post_disable_user() {
+        const PORT: u16 = 8654;
+        let base = format!("http://localhost:{}", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let admin = make_client();
+        login_client(&admin, PORT, AcctType::Admin).await;
+        let user = make_client();
+        login_client(&user, PORT, AcctType::User).await;
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/disableuser", base),
+            &serde_json::json!({"name": "Admin_user"}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("false"));
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/disableuser", base),
+            &serde_json::json!({"name": "User_user"}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+
+        // Existing session ends and new logins fail
+        let resp = send_get(&user, &format!("{}/authrequest", base)).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = send_post(
+            &user,
+            &format!("{}/login", base),
+            &serde_json::json!({"username": "User_user", "password": "password"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/enableuser", base),
+            &serde_json::json!({"name": "User_user"}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+        login_client(&user, PORT, AcctType::User).await;
+
+        // Expiry in the past through updateuser
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/updateuser", base),
+            &serde_json::json!({"name": "User_user", "expires_at": 1}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+        let resp = send_post(
+            &user,
+            &format!("{}/login", base),
+            &serde_json::json!({"username": "User_user", "password": "password"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/updateuser", base),
+            &serde_json::json!({"name": "User_user", "expires_at": null}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+        login_client(&user, PORT, AcctType::User).await;
+    }
+}
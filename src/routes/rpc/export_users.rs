@@ -0,0 +1,90 @@
+use actix_web::{web::Query, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    i18n::Message,
+    routes::rpc::{rpc_error, rpc_response},
+    shared_data::Sharable,
+    transfer::{self, TransferFormat},
+    userdb::UserDB,
+    Error,
+};
+
+#[derive(Deserialize)]
+pub struct Args {
+    #[serde(default)]
+    format: TransferFormat,
+}
+
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    let args = match Query::<Args>::from_query(req.query_string()) {
+        Ok(a) => a.into_inner(),
+        Err(e) => {
+            tracing::error!("Bad query: {}", e);
+            return rpc_error(
+                &req,
+                Error::invalid(Message::new("request.invalid_query").arg("error", e)),
+            );
+        }
+    };
+
+    let users = UserDB::extract_from(&req).read().unwrap().export();
+
+    return match args.format {
+        // JSON exports are embedded as-is rather than as a string
+        TransferFormat::Json => rpc_response(true, users),
+        format => match transfer::serialize(&users, format) {
+            Ok(data) => rpc_response(true, data),
+            Err(e) => rpc_error(&req, e),
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn get_export_users() {
+        const PORT: u16 = 8657;
+        let url = format!("http://localhost:{}/rpc/exportusers", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let client = make_client();
+        login_client(&client, PORT, AcctType::Admin).await;
+
+        let body: serde_json::Value = send_get(&client, &url).await.json().await.unwrap();
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["response"][0]["name"], "Admin_user");
+        assert!(body["response"][0]["hashed_password"]
+            .as_str()
+            .unwrap()
+            .starts_with("$argon2"));
+
+        let body: serde_json::Value = send_get(&client, &format!("{}?format=csv", url))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert!(body["response"].as_str().unwrap().starts_with(concat!(
+            "name,hashed_password,paths,acct_type,disabled,expires_at,schedule,",
+            "must_change_password,password_changed_at,password_history,source\nAdmin_user,"
+        )));
+
+        let body: serde_json::Value = send_get(&client, &format!("{}?format=htpasswd", url))
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["ok"], false);
+    }
+}
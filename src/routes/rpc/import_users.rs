@@ -0,0 +1,138 @@
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_response},
+    },
+    shared_data::Sharable,
+    transfer::{self, TransferFormat},
+    userdb::{AcctType, UserDB},
+};
+
+#[derive(Deserialize)]
+pub struct Args {
+    /// Contents of an htpasswd file or a JSON/CSV export
+    data: String,
+    #[serde(default = "default_format")]
+    format: TransferFormat,
+    /// Only used for htpasswd imports, which have no paths of their own
+    #[serde(default)]
+    paths: Vec<String>,
+    /// Only used for htpasswd imports
+    acct_type: Option<AcctType>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+// htpasswd was the only import format before JSON and CSV were added
+fn default_format() -> TransferFormat {
+    return TransferFormat::Htpasswd;
+}
+
+pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let mut args: Args = match parse_post_body(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return rpc_error(&req, e);
+        }
+    };
+
+    args.paths = args.paths.iter().map(|p| p.trim().to_string()).collect();
+
+    let mut user_db_w = UserDB::extract_from(&req).write().unwrap();
+    let report = match args.format {
+        TransferFormat::Htpasswd => user_db_w.import_htpasswd(
+            &args.data,
+            &args.paths,
+            args.acct_type.unwrap_or(AcctType::User),
+            args.dry_run,
+        ),
+        format => transfer::deserialize(&args.data, format).and_then(|u| user_db_w.import_users(u, args.dry_run)),
+    };
+
+    return match report {
+        Ok(report) => rpc_response(true, report),
+        Err(e) => rpc_error(&req, e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_post},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn post_import_users() {
+        const PORT: u16 = 8658;
+        let url = format!("http://localhost:{}/rpc/importusers", PORT);
+
+        let udb = make_test_userdb();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(udb);
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let client = make_client();
+        login_client(&client, PORT, AcctType::Admin).await;
+
+        let payload = serde_json::json!({
+            "data": "apr1_user:$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1\nAdmin_user:{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=",
+            "paths": ["*"],
+            "acct_type": "User"
+        });
+        let resp = send_post(&client, &url, &payload).await;
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(body["ok"], true);
+        assert_eq!(body["response"]["added"], serde_json::json!(["apr1_user"]));
+        assert_eq!(body["response"]["skipped"].as_array().unwrap().len(), 1);
+
+        let resp = send_post(
+            &make_client(),
+            &format!("http://localhost:{}/login", PORT),
+            &serde_json::json!({"username": "apr1_user", "password": "password"}),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn post_import_users_json() {
+        const PORT: u16 = 8656;
+        let url = format!("http://localhost:{}/rpc/importusers", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let client = make_client();
+        login_client(&client, PORT, AcctType::Admin).await;
+
+        let users = serde_json::json!([
+            {"name": "Admin_user", "hashed_password": "!", "paths": [], "acct_type": "User"},
+            {"name": "json_user", "hashed_password": "!", "paths": ["/a"], "acct_type": "User"}
+        ]);
+        let mut payload = serde_json::json!({"format": "json", "data": users.to_string(), "dry_run": true});
+
+        let body: serde_json::Value = send_post(&client, &url, &payload).await.json().await.unwrap();
+        assert_eq!(body["response"]["dry_run"], true);
+        assert_eq!(body["response"]["added"], serde_json::json!(["json_user"]));
+        assert_eq!(body["response"]["skipped"].as_array().unwrap().len(), 1);
+
+        payload["dry_run"] = serde_json::json!(false);
+        let body: serde_json::Value = send_post(&client, &url, &payload).await.json().await.unwrap();
+        assert_eq!(body["response"]["added"], serde_json::json!(["json_user"]));
+
+        let body: serde_json::Value = send_post(&client, &url, &payload).await.json().await.unwrap();
+        assert_eq!(body["response"]["added"], serde_json::json!([]));
+    }
+}
@@ -1,58 +1,90 @@
+/*
+The original admin API. Each call is named in the path and takes its arguments
+as a JSON body. The calls are kept as aliases of the versioned API in
+routes::api, which shares their handlers.
+*/
+
+use std::sync::{Arc, RwLock};
+
 use actix_web::{
-    http::StatusCode,
-    web::{Bytes, Path},
+    web::{self, Bytes, Path},
     HttpRequest, HttpResponse,
 };
 use serde::Serialize;
 use serde_json::json;
 
-mod add_user;
-mod list_users;
-mod remove_user;
-mod restart_server;
-mod update_user;
-
-use crate::{routes::simple_response, sessions::SessionStore, shared_data::Sharable, userdb::AcctType};
+pub mod add_user;
+pub mod disable_user;
+pub mod export_users;
+pub mod import_users;
+pub mod list_users;
+pub mod public_rules;
+pub mod remove_user;
+pub mod restart_server;
+pub mod sessions;
+pub mod update_user;
+
+use crate::{
+    i18n::{self, Message},
+    userdb::{AcctType, User},
+    Error,
+};
 
-use super::req_user;
+use super::{api::LocalAdmin, req_user};
 
 pub async fn get(req: HttpRequest, path: Path<String>) -> HttpResponse {
-    if req_user(&req)
-        .filter(|u| u.read().unwrap().get_type() == &AcctType::Admin)
-        .is_none()
-    {
-        return simple_response(StatusCode::UNAUTHORIZED);
+    if admin_user(&req).is_none() {
+        return rpc_error(&req, unauthorized());
     }
 
     return match &*path.into_inner() {
         "listusers" => list_users::get(req).await,
-        _ => simple_response(StatusCode::NOT_FOUND),
+        "exportusers" => export_users::get(req).await,
+        "publicrules" => public_rules::get(req).await,
+        "listsessions" => sessions::get(req).await,
+        call => rpc_error(&req, unknown_call(call)),
     };
 }
 
 pub async fn post(req: HttpRequest, body: Bytes, path: Path<String>) -> HttpResponse {
-    let sessions = SessionStore::extract_from(&req);
-    let user = match sessions
-        .write()
-        .unwrap()
-        .get_from_request(&req)
-        .and_then(|session| Some(session.get_user()))
-        .filter(|u| u.read().unwrap().get_type() == &AcctType::Admin)
-    {
+    let user = match admin_user(&req) {
         Some(u) => u,
-        None => return simple_response(StatusCode::UNAUTHORIZED),
+        None => return rpc_error(&req, unauthorized()),
     };
 
     return match &*path.into_inner() {
         "adduser" => add_user::post(req, body).await,
         "removeuser" => remove_user::post(req, body, user).await,
         "updateuser" => update_user::post(req, body).await,
+        "disableuser" => disable_user::post(req, body, user, true).await,
+        "enableuser" => disable_user::post(req, body, user, false).await,
+        "importusers" => import_users::post(req, body).await,
+        "addpublicrule" => public_rules::add(req, body).await,
+        "removepublicrule" => public_rules::remove(req, body).await,
         "restartserver" => restart_server::post(req, body).await,
-        _ => simple_response(StatusCode::NOT_FOUND),
+        "revokesession" => sessions::post(req, body).await,
+        call => rpc_error(&req, unknown_call(call)),
     };
 }
 
-fn rpc_response<T>(ok: bool, response: T) -> HttpResponse
+/// The admin logged in to req's session, or the local admin for requests from
+/// admin.socket
+pub(super) fn admin_user(req: &HttpRequest) -> Option<Arc<RwLock<User>>> {
+    if let Some(local) = req.app_data::<web::Data<LocalAdmin>>() {
+        return Some(local.user.clone());
+    }
+    return req_user(req).filter(|u| u.read().unwrap().get_type() == &AcctType::Admin);
+}
+
+pub(super) fn unauthorized() -> Error {
+    return Error::Unauthorized(Message::new("request.unauthorized"));
+}
+
+pub(super) fn unknown_call(name: &str) -> Error {
+    return Error::NotFound(Message::new("request.unknown_call").arg("name", name));
+}
+
+pub(super) fn rpc_response<T>(ok: bool, response: T) -> HttpResponse
 where
     T: Serialize,
 {
@@ -65,6 +97,26 @@ where
     return HttpResponse::Ok().body(ser);
 }
 
+/// Successful response with message in the client's language
+pub(super) fn rpc_message(req: &HttpRequest, message: Message) -> HttpResponse {
+    return rpc_response(true, i18n::from_request(req).format(&message));
+}
+
+/// Failed response with the error's status and code. response is the error
+/// in the client's language, and errors has the code, field and arguments of
+/// each of its messages so clients can word them their own way.
+pub(super) fn rpc_error(req: &HttpRequest, error: Error) -> HttpResponse {
+    let ser = json!({
+        "ok": false,
+        "code": error.code(),
+        "response": i18n::from_request(req).error(&error),
+        "errors": error.messages(),
+    })
+    .to_string();
+
+    return HttpResponse::build(error.status()).body(ser);
+}
+
 #[cfg(test)]
 mod tests {
     use http::StatusCode;
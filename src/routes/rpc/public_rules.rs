@@ -0,0 +1,143 @@
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    access::AccessControl,
+    config::PublicRule,
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_message, rpc_response},
+    },
+    shared_data::Sharable,
+};
+
+/// Lists public rules. A rule's position in the list is used to remove it.
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    return rpc_response(true, AccessControl::extract_from(&req).read().unwrap().public_rules());
+}
+
+pub async fn add(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let rule: PublicRule = match parse_post_body(body) {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return rpc_error(&req, e);
+        }
+    };
+
+    let path = rule.path.to_owned();
+    return match AccessControl::extract_from(&req).write().unwrap().add_public_rule(rule) {
+        Ok(_) => rpc_message(&req, Message::new("rule.added").arg("path", path)),
+        Err(e) => rpc_error(&req, e),
+    };
+}
+
+#[derive(Deserialize)]
+struct RemoveArgs {
+    index: usize,
+}
+
+pub async fn remove(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let args: RemoveArgs = match parse_post_body(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return rpc_error(&req, e);
+        }
+    };
+
+    return remove_index(&req, args.index);
+}
+
+pub fn remove_index(req: &HttpRequest, index: usize) -> HttpResponse {
+    return match AccessControl::extract_from(req)
+        .write()
+        .unwrap()
+        .remove_public_rule(index)
+    {
+        Ok(r) => rpc_message(req, Message::new("rule.removed").arg("path", r.path)),
+        Err(e) => rpc_error(req, e),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get, send_post},
+        test_utils::make_tmp_file,
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn manage_public_rules() {
+        const PORT: u16 = 8651;
+        let base = format!("http://localhost:{}", PORT);
+
+        let cfg_path = make_tmp_file();
+        std::fs::remove_file(&cfg_path).unwrap();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        cfg.cfg_path = Some(cfg_path.to_owned());
+        start_test_server(cfg);
+
+        let auth_request = |uri: &'static str, method: &'static str| {
+            let url = format!("{}/authrequest", base);
+            async move {
+                return make_client()
+                    .get(url)
+                    .header("X-Original-URI", uri)
+                    .header("X-Original-Method", method)
+                    .send()
+                    .await
+                    .expect("Can't send get request")
+                    .status();
+            }
+        };
+        assert_eq!(auth_request("/status?full=1", "GET").await, StatusCode::UNAUTHORIZED);
+
+        let admin = make_client();
+        login_client(&admin, PORT, AcctType::Admin).await;
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/addpublicrule", base),
+            &serde_json::json!({"path": "/status", "methods": ["GET"]}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/addpublicrule", base),
+            &serde_json::json!({"path": "[invalid"}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("false"));
+
+        assert_eq!(auth_request("/status?full=1", "GET").await, StatusCode::OK);
+        assert_eq!(auth_request("/status", "POST").await, StatusCode::UNAUTHORIZED);
+
+        let resp = send_get(&admin, &format!("{}/rpc/publicrules", base)).await;
+        let body = resp.json::<serde_json::Value>().await.unwrap();
+        assert_eq!(body["response"][0]["path"], "/status");
+
+        // Rules are saved to the config file
+        let saved = UserConfig::new(&vec!["_".to_string(), "-c".to_string(), cfg_path]).unwrap();
+        assert_eq!(saved.public_rules.unwrap()[0].path, "/status");
+
+        let resp = send_post(
+            &admin,
+            &format!("{}/rpc/removepublicrule", base),
+            &serde_json::json!({"index": 0}),
+        )
+        .await;
+        assert!(resp.text().await.unwrap().contains("true"));
+        assert_eq!(auth_request("/status", "GET").await, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,13 +1,18 @@
 use std::sync::{Arc, RwLock};
 
-use actix_web::{http::StatusCode, web::Bytes, HttpRequest, HttpResponse};
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
 use serde::Deserialize;
 
 use crate::{
-    routes::{parse_post_body, rpc::rpc_response, simple_response},
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_message},
+    },
     sessions::SessionStore,
     shared_data::Sharable,
     userdb::{User, UserDB},
+    Error,
 };
 
 #[derive(Deserialize)]
@@ -16,28 +21,30 @@ struct Args {
 }
 
 pub async fn post(req: HttpRequest, body: Bytes, user: Arc<RwLock<User>>) -> HttpResponse {
-    let mut user_db = UserDB::extract_from(&req).write().unwrap();
-
     let args: Args = match parse_post_body(body) {
         Ok(a) => a,
         Err(e) => {
             tracing::error!("Bad post body: {}", e);
-            return simple_response(StatusCode::BAD_REQUEST);
+            return rpc_error(&req, e);
         }
     };
 
-    if user.read().unwrap().get_name() == &args.username {
-        return rpc_response(false, "Cannot remove your own account");
+    return remove(&req, &args.username, user);
+}
+
+/// Removes the account called name. user is the admin removing it.
+pub fn remove(req: &HttpRequest, name: &String, user: Arc<RwLock<User>>) -> HttpResponse {
+    if user.read().unwrap().get_name() == name {
+        return rpc_error(req, Error::Forbidden(Message::new("user.cannot_remove_self").field("username")));
     }
 
-    SessionStore::extract_from(&req)
-        .write()
-        .unwrap()
-        .remove_id(&args.username);
+    let mut user_db = UserDB::extract_from(req).write().unwrap();
+
+    SessionStore::extract_from(req).write().unwrap().remove_id(name);
 
-    return match user_db.remove(&args.username) {
-        Ok(_) => rpc_response(true, format!("User {} removed", &args.username)),
-        Err(e) => rpc_response(false, e.message),
+    return match user_db.remove(name) {
+        Ok(_) => rpc_message(req, Message::new("user.removed").arg("name", name)),
+        Err(e) => rpc_error(req, e),
     };
 }
 
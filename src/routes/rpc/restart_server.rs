@@ -1,11 +1,14 @@
 use std::time::Duration;
 
-use actix_web::{http::StatusCode, web::Bytes, HttpRequest, HttpResponse};
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
 use serde::Deserialize;
 
 use crate::{
     app::{ExitCommand, ServerController},
-    routes::{parse_post_body, rpc::rpc_response, simple_response},
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_response},
+    },
     shared_data::Sharable,
 };
 
@@ -19,14 +22,19 @@ pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
         Ok(f) => f,
         Err(e) => {
             tracing::error!("{}", e);
-            return simple_response(StatusCode::BAD_REQUEST);
+            return rpc_error(&req, e);
         }
     };
 
-    let signaller = ServerController::extract_from(&req).clone();
+    return send(&req, args.command);
+}
+
+/// Restarts or stops the server shortly after the response is sent
+pub fn send(req: &HttpRequest, command: ExitCommand) -> HttpResponse {
+    let signaller = ServerController::extract_from(req).clone();
     tokio::task::spawn(async move {
         tokio::time::sleep(Duration::from_millis(500)).await;
-        _ = signaller.send_exit(args.command, false).await;
+        _ = signaller.send_exit(command, false).await;
     });
 
     return rpc_response(true, "");
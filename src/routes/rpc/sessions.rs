@@ -0,0 +1,90 @@
+use actix_web::{web::Bytes, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+use crate::{
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{rpc_error, rpc_message, rpc_response},
+    },
+    sessions::SessionStore,
+    shared_data::Sharable,
+    Error,
+};
+
+/// Lists active sessions. Sessions are identified by SessionInfo::id, never
+/// by their cookie value.
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    return rpc_response(true, SessionStore::extract_from(&req).read().unwrap().list());
+}
+
+#[derive(Deserialize)]
+struct Args {
+    id: String,
+}
+
+pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let args: Args = match parse_post_body(body) {
+        Ok(a) => a,
+        Err(e) => {
+            tracing::error!("Bad post body: {}", e);
+            return rpc_error(&req, e);
+        }
+    };
+
+    return revoke(&req, &args.id);
+}
+
+/// Ends the session with a matching public id
+pub fn revoke(req: &HttpRequest, id: &str) -> HttpResponse {
+    if !SessionStore::extract_from(req).write().unwrap().remove_public_id(id) {
+        return rpc_error(
+            req,
+            Error::NotFound(Message::new("session.not_found").field("id").arg("id", id)),
+        );
+    }
+    return rpc_message(req, Message::new("session.revoked").arg("id", id));
+}
+
+#[cfg(test)]
+mod tests {
+    use http::StatusCode;
+    use serde_json::Value;
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{login_client, make_client, make_test_userdb, send_get, send_post},
+        userdb::AcctType,
+    };
+
+    #[tokio::test]
+    async fn revoke_session() {
+        const PORT: u16 = 8641;
+        let list_url = format!("http://localhost:{}/rpc/listsessions", PORT);
+        let revoke_url = format!("http://localhost:{}/rpc/revokesession", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let admin = make_client();
+        login_client(&admin, PORT, AcctType::Admin).await;
+        let user = make_client();
+        login_client(&user, PORT, AcctType::User).await;
+
+        let list: Value = send_get(&admin, &list_url).await.json().await.unwrap();
+        let sessions = list["response"].as_array().unwrap();
+        assert_eq!(sessions.len(), 2);
+        let id = sessions.iter().find(|s| s["user"] == "User_user").unwrap()["id"].clone();
+
+        let resp = send_post(&admin, &revoke_url, &serde_json::json!({ "id": id })).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = send_post(&admin, &revoke_url, &serde_json::json!({ "id": id })).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let list: Value = send_get(&admin, &list_url).await.json().await.unwrap();
+        assert_eq!(list["response"].as_array().unwrap().len(), 1);
+    }
+}
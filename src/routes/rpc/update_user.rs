@@ -1,20 +1,50 @@
 use actix_web::{web::Bytes, HttpRequest, HttpResponse};
-use http::StatusCode;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 
 use crate::{
-    routes::{parse_post_body, rpc::rpc_response, simple_response},
+    access::AccessControl,
+    i18n::Message,
+    routes::{
+        parse_post_body,
+        rpc::{admin_user, rpc_error, rpc_message},
+    },
     sessions::SessionStore,
     shared_data::Sharable,
     userdb::{AcctType, UserDB},
+    Error,
 };
 
 #[derive(Deserialize)]
 struct Args {
     pub name: String, // used for id only, cannot change
+    #[serde(flatten)]
+    pub changes: Changes,
+}
+
+/// Fields of a user to change. Missing fields are left as they are.
+#[derive(Deserialize)]
+pub struct Changes {
     pub password: Option<String>,
     pub paths: Option<Vec<String>>,
     pub acct_type: Option<AcctType>,
+    /// Require the user to choose their own password on next login. Defaults
+    /// to true when a new password is set, unless admins set their own.
+    pub must_change_password: Option<bool>,
+    /// Unix time at which the account expires. Null removes the expiration.
+    #[serde(default, deserialize_with = "present")]
+    pub expires_at: Option<Option<i64>>,
+    /// Name of an access schedule. Null removes the schedule.
+    #[serde(default, deserialize_with = "present")]
+    pub schedule: Option<Option<String>>,
+}
+
+/// Distinguishes a null field from a missing one
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    return Ok(Some(Option::deserialize(deserializer)?));
 }
 
 pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
@@ -22,39 +52,61 @@ pub async fn post(req: HttpRequest, body: Bytes) -> HttpResponse {
         Ok(a) => a,
         Err(e) => {
             tracing::error!("Bad post body: {}", e);
-            return simple_response(StatusCode::BAD_REQUEST);
+            return rpc_error(&req, e);
         }
     };
 
-    let mut user_db_w = UserDB::extract_from(&req).write().unwrap();
+    return update(&req, &args.name, args.changes);
+}
+
+pub fn update(req: &HttpRequest, name: &String, changes: Changes) -> HttpResponse {
+    match &changes.schedule {
+        Some(Some(s)) if !AccessControl::extract_from(req).read().unwrap().has_schedule(s) => {
+            return rpc_error(
+                req,
+                Error::invalid(Message::new("schedule.not_found").field("schedule").arg("name", s)),
+            );
+        }
+        _ => {}
+    }
+
+    let mut user_db_w = UserDB::extract_from(req).write().unwrap();
 
-    let user = match user_db_w.get(&args.name) {
-        Some(u) => u,
-        None => return rpc_response(false, format!("User {} does not exist in database", args.name)),
+    let previous = user_db_w.get(name).map(|u| u.read().unwrap().clone());
+    let user = match user_db_w.update_user(name, changes.password.clone(), changes.paths, changes.acct_type) {
+        Ok(u) => u.clone(),
+        Err(e) => return rpc_error(req, e),
     };
 
-    match user
-        .write()
-        .unwrap()
-        .update_info(args.password, args.paths, args.acct_type)
-    {
-        Ok(_) => {}
-        Err(e) => return rpc_response(false, e.message),
+    match changes.expires_at {
+        Some(exp) => user.write().unwrap().set_expires_at(exp),
+        None => {}
     }
 
-    SessionStore::extract_from(&req).write().unwrap().remove_for_user(&user);
+    match changes.schedule {
+        Some(s) => user.write().unwrap().set_schedule(s),
+        None => {}
+    }
 
-    match user_db_w.write_to_file() {
+    if changes.password.is_some() {
+        // Admins already chose their own new password
+        let is_self = admin_user(req).is_some_and(|a| a.read().unwrap().get_name() == name);
+        user.write()
+            .unwrap()
+            .set_must_change_password(changes.must_change_password.unwrap_or(!is_self));
+    }
+
+    // update_user found the user, so there is a previous copy
+    match user_db_w.save_user_change(&user, previous.unwrap()) {
         Ok(_) => {}
         Err(e) => {
-            return rpc_response(
-                false,
-                format!("User updated but could not be written to the database: {}", e),
-            )
+            return rpc_error(req, Error::Internal(Message::new("user.not_saved").arg("error", e)))
         }
     }
 
-    return rpc_response(true, format!("{} updated", args.name));
+    SessionStore::extract_from(req).write().unwrap().remove_for_user(&user);
+
+    return rpc_message(req, Message::new("user.updated").arg("name", name));
 }
 
 #[cfg(test)]
@@ -90,18 +142,32 @@ mod tests {
         let resp = send_post(&client, &url, &bad_payload).await;
         assert!(resp.text().await.unwrap().contains("false"));
 
+        // Resetting someone else's password makes them choose a new one
+        let reset = serde_json::json!({"name": "User_user", "password": "a_reset_password"});
+        let resp = send_post(&client, &url, &reset).await;
+        assert!(resp.text().await.unwrap().contains("true"));
+
+        // Ends the admin's own session too
         let resp = send_post(&client, &url, &payload).await;
         assert!(resp.text().await.unwrap().contains("true"));
 
-        // try logging in with new Admin_user password
-        let client = make_client();
-        let resp = client
-            .post(format!("http://localhost:{}/login", PORT))
-            .json(&serde_json::json!({"username": "Admin_user", "password": "a_new_password"}))
-            .send()
-            .await
-            .expect("Can't send login post");
-        assert_eq!(resp.status(), StatusCode::OK);
+        let login = |name: &'static str, password: &'static str| async move {
+            let client = make_client();
+            let resp = client
+                .post(format!("http://localhost:{}/login", PORT))
+                .json(&serde_json::json!({"username": name, "password": password}))
+                .send()
+                .await
+                .expect("Can't send login post");
+            assert_eq!(resp.status(), StatusCode::OK);
+            (client, resp.text().await.unwrap())
+        };
+        assert!(login("User_user", "a_reset_password").await.1.contains("change_password"));
+
+        // try logging in with new Admin_user password, which the admin set
+        // for themselves so it doesn't need changing
+        let (client, body) = login("Admin_user", "a_new_password").await;
+        assert!(!body.contains("change_password"));
 
         // list safe users and verify non-password changes
         let list_url = format!("http://localhost:{}/rpc/listusers", PORT);
@@ -9,15 +9,16 @@ use serde::Deserialize;
 
 use crate::{
     app::{ExitCommand, ServerController},
+    i18n,
     routes::simple_response,
     shared_data::Sharable,
     userdb::{AcctType, UserDB},
 };
 
-use super::{parse_post_body, serve_file};
+use super::{parse_post_body, serve_page};
 
-pub async fn get(_req: HttpRequest) -> HttpResponse {
-    return serve_file("./static/setup.html").await;
+pub async fn get(req: HttpRequest) -> HttpResponse {
+    return serve_page(&req, "setup.html").await;
 }
 
 #[derive(Deserialize)]
@@ -30,7 +31,7 @@ pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
     let args = match parse_post_body::<Args>(body) {
         Ok(a) => a,
         Err(e) => {
-            tracing::error!("Body post body sent to /setup: {}", e.message);
+            tracing::error!("Body post body sent to /setup: {}", e);
             return simple_response(StatusCode::BAD_REQUEST);
         }
     };
@@ -44,7 +45,7 @@ pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
         Ok(_) => simple_response(StatusCode::OK),
         Err(e) => {
             tracing::error!("{}", e);
-            return simple_response(StatusCode::INTERNAL_SERVER_ERROR);
+            return HttpResponse::build(e.status()).body(i18n::from_request(&req).error(&e));
         }
     };
 
@@ -59,11 +60,12 @@ pub async fn post(req: HttpRequest, body: web::Bytes) -> HttpResponse {
 
 #[cfg(test)]
 pub mod tests {
+    use super::*;
     use crate::app;
     use crate::config::UserConfig;
     use crate::routes::tests::{make_client, send_post};
     use crate::test_utils::make_tmp_file;
-    use http::StatusCode;
+    use actix_web::test::TestRequest;
 
     #[tokio::test]
     async fn post_setup() {
@@ -80,7 +82,17 @@ pub mod tests {
         let resp = send_post(
             &client,
             &url,
-            &serde_json::json!({"name": "username", "password": "password"}),
+            &serde_json::json!({"name": "username", "password": "short"}),
+        )
+        .await;
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(resp.text().await.unwrap(), "Password too short (minimum 8 characters)");
+
+        let resp = send_post(
+            &client,
+            &url,
+            &serde_json::json!({"name": "username", "password": "mashed potatoes"}),
         )
         .await;
 
@@ -88,4 +100,18 @@ pub mod tests {
 
         /* See restart_server.rs */
     }
+
+    #[tokio::test]
+    async fn setup_save_failure() {
+        let udb = make_tmp_file();
+        let user_db = UserDB::new(&udb).unwrap().to_sharable();
+        // Can't be opened for writing
+        std::fs::remove_file(&udb).unwrap();
+        std::fs::create_dir(&udb).unwrap();
+
+        let req = TestRequest::default().app_data(user_db).to_http_request();
+        let body = serde_json::json!({"name": "username", "password": "mashed potatoes"}).to_string();
+        let resp = post(req, web::Bytes::from(body)).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
 }
@@ -1,15 +1,63 @@
-use actix_web::{http::StatusCode, web::Path, HttpResponse};
+use actix_web::{http::StatusCode, web::Path, HttpRequest, HttpResponse};
 
-use super::{serve_file, simple_response};
+use crate::{
+    assets::{self, Assets},
+    shared_data::Sharable,
+};
 
-const FS_DIR: &str = "./static/";
+use super::simple_response;
 
-pub async fn get(path: Path<String>) -> HttpResponse {
+pub async fn get(req: HttpRequest, path: Path<String>) -> HttpResponse {
+    // Pages are only served through their routes, which fill them in
     let file = path.into_inner();
     if file.ends_with(".html") {
         return simple_response(StatusCode::NOT_FOUND);
     }
 
-    let local_file = format!("{}{}", FS_DIR, file);
-    return serve_file(local_file).await;
+    return match Assets::extract_from(&req).get(&file).await {
+        Some(contents) => assets::respond(&req, &file, contents, assets::STATIC_CACHE),
+        None => simple_response(StatusCode::NOT_FOUND),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, StatusCode};
+
+    use crate::{
+        app::tests::start_test_server,
+        config::UserConfig,
+        routes::tests::{make_client, make_test_userdb, send_get},
+    };
+
+    #[tokio::test]
+    async fn get_cached() {
+        const PORT: u16 = 8643;
+        let url = format!("http://localhost:{}/static/styles.css", PORT);
+
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_test_userdb());
+        cfg.port = Some(PORT);
+        start_test_server(cfg);
+
+        let client = make_client();
+        let resp = send_get(&client, &url).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CONTENT_TYPE], "text/css; charset=utf-8");
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "public, max-age=3600");
+        let etag = resp.headers()[header::ETAG].clone();
+
+        let resp = client
+            .get(&url)
+            .header(header::IF_NONE_MATCH, etag)
+            .send()
+            .await
+            .expect("Can't send get request");
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+
+        let login = format!("http://localhost:{}/static/login.html", PORT);
+        assert_eq!(send_get(&client, &login).await.status(), StatusCode::NOT_FOUND);
+        let missing = format!("http://localhost:{}/static/missing.js", PORT);
+        assert_eq!(send_get(&client, &missing).await.status(), StatusCode::NOT_FOUND);
+    }
 }
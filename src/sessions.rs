@@ -10,14 +10,21 @@ use actix_web::{
     },
     HttpRequest,
 };
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
 use uuid::Uuid;
 
-use crate::{routes::SESSION_NAME, shared_data::Sharable, userdb::User};
+use crate::{
+    routes::SESSION_NAME,
+    shared_data::Sharable,
+    userdb::{User, UserDB},
+};
 
 pub struct Session {
     user: Arc<RwLock<User>>,
     exp: OffsetDateTime,
     id: String,
+    restricted: bool,
 }
 
 impl Session {
@@ -26,6 +33,7 @@ impl Session {
             user: user.clone(),
             exp: OffsetDateTime::now_utc() + ttl,
             id: id.to_owned(),
+            restricted: false,
         };
     }
 
@@ -34,6 +42,21 @@ impl Session {
         return self.user.clone();
     }
 
+    /// A restricted session belongs to a user that must change their
+    /// password and may not be used for anything else
+    pub fn is_restricted(&self) -> bool {
+        return self.restricted;
+    }
+
+    /// Identifies the session to admins without revealing the cookie value
+    pub fn public_id(&self) -> String {
+        return Sha1::digest(self.id.as_bytes())
+            .iter()
+            .take(8)
+            .map(|b| format!("{:02x}", b))
+            .collect();
+    }
+
     /// Generate cookie from session id
     /// The cookie will contain only one value - the key used to access the
     /// session in the store. No data will be sent to the client other than
@@ -46,6 +69,17 @@ impl Session {
     }
 }
 
+/// What admins see of a session
+#[derive(Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// See Session::public_id
+    pub id: String,
+    pub user: String,
+    /// Unix time the session ends unless it is used before then
+    pub expires_at: i64,
+    pub restricted: bool,
+}
+
 pub struct SessionStore {
     ttl: Duration,
     sessions: HashMap<String, Session>,
@@ -72,6 +106,17 @@ impl SessionStore {
         return s;
     }
 
+    /// Creates a session that only allows the user to change their password
+    pub fn new_restricted_session(&mut self, user: &Arc<RwLock<User>>) -> &Session {
+        let key = Uuid::new_v4().to_string();
+
+        let mut sess = Session::new(user, &key, self.ttl);
+        sess.restricted = true;
+        self.sessions.insert(key.to_owned(), sess);
+
+        return self.sessions.get(&key).unwrap();
+    }
+
     pub fn remove_id(&mut self, id: &String) {
         self.sessions.remove(id);
     }
@@ -82,6 +127,48 @@ impl SessionStore {
             .retain(|_, sess| sess.user.read().unwrap().get_name() != &username);
     }
 
+    /// Removes the session with a matching public_id. Returns false if there
+    /// is none.
+    pub fn remove_public_id(&mut self, public_id: &str) -> bool {
+        let len = self.sessions.len();
+        self.sessions.retain(|_, sess| sess.public_id() != public_id);
+        return self.sessions.len() != len;
+    }
+
+    /// Sessions that have not expired, sorted by user
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let now = OffsetDateTime::now_utc();
+        let mut list: Vec<SessionInfo> = self
+            .sessions
+            .values()
+            .filter(|sess| sess.exp >= now)
+            .map(|sess| SessionInfo {
+                id: sess.public_id(),
+                user: sess.user.read().unwrap().get_name().to_owned(),
+                expires_at: sess.exp.unix_timestamp(),
+                restricted: sess.restricted,
+            })
+            .collect();
+        list.sort_by(|a, b| a.user.cmp(&b.user).then(a.expires_at.cmp(&b.expires_at)));
+        return list;
+    }
+
+    /// Points every session at the user with the same name in user_db, so
+    /// sessions survive the db being reloaded. Sessions of users that no
+    /// longer exist are removed.
+    pub fn swap_users(&mut self, user_db: &UserDB) {
+        self.sessions.retain(|_, sess| {
+            let name = sess.user.read().unwrap().get_name().to_owned();
+            return match user_db.get(&name) {
+                Some(user) => {
+                    sess.user = user.clone();
+                    true
+                }
+                None => false,
+            };
+        });
+    }
+
     /// Gets session using cookie header in req.
     pub fn get_from_request(&mut self, req: &HttpRequest) -> Option<&Session> {
         let id = req.cookie(SESSION_NAME)?.value().to_string();
@@ -100,7 +187,9 @@ impl SessionStore {
 
         let now = OffsetDateTime::now_utc();
 
-        if sess.exp < now {
+        // Disabling an account or reaching its expiration date ends any
+        // sessions it already has
+        if sess.exp < now || !sess.user.read().unwrap().is_active() {
             self.sessions.remove(id);
             return None;
         }
@@ -141,6 +230,10 @@ mod session_store_tests {
         let sess = session_store.new_session(&usr);
 
         assert!(sess.get_user().read().unwrap().eq(&usr.read().unwrap()));
+        assert!(!sess.is_restricted());
+
+        let sess = session_store.new_restricted_session(&usr);
+        assert!(sess.is_restricted());
     }
 
     #[test]
@@ -200,4 +293,50 @@ mod session_store_tests {
         tokio::time::sleep(std::time::Duration::from_millis(110)).await;
         assert!(session_store.get(&id).is_none());
     }
+
+    #[test]
+    fn disabled_user() {
+        let mut session_store = SessionStore::new(Duration::seconds(60));
+
+        let usr = User::new(
+            &"user".to_string(),
+            &"password".to_string(),
+            &vec!["*".to_string()],
+            AcctType::User,
+        )
+        .unwrap();
+        let usr = Arc::new(RwLock::new(usr));
+        let id = session_store.new_session(&usr).id.to_owned();
+        assert!(session_store.get(&id).is_some());
+
+        usr.write().unwrap().set_disabled(true);
+        assert!(session_store.get(&id).is_none());
+        assert_eq!(session_store.sessions.len(), 0);
+    }
+
+    #[test]
+    fn list_and_revoke() {
+        let mut session_store = SessionStore::new(Duration::seconds(60));
+
+        let usr = User::new(
+            &"user".to_string(),
+            &"password".to_string(),
+            &vec!["*".to_string()],
+            AcctType::User,
+        )
+        .unwrap();
+        let usr = Arc::new(RwLock::new(usr));
+        let id = session_store.new_session(&usr).id.to_owned();
+        session_store.new_restricted_session(&usr);
+
+        let list = session_store.list();
+        assert_eq!(list.len(), 2);
+        assert!(list.iter().all(|s| s.user == "user" && s.id.len() == 16 && s.id != id));
+
+        let public_id = session_store.get(&id).unwrap().public_id();
+        assert!(session_store.remove_public_id(&public_id));
+        assert!(!session_store.remove_public_id(&public_id));
+        assert!(session_store.get(&id).is_none());
+        assert_eq!(session_store.list().len(), 1);
+    }
 }
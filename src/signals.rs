@@ -0,0 +1,86 @@
+/*
+Process signals the server responds to. SIGTERM and SIGINT stop it gracefully
+and SIGHUP reloads the config and user db. Only ctrl-c is available on
+platforms without unix signals.
+*/
+
+#[derive(Debug, PartialEq)]
+pub enum Signal {
+    Stop,
+    Reload,
+}
+
+pub struct SignalListener {
+    #[cfg(unix)]
+    terminate: Option<tokio::signal::unix::Signal>,
+    #[cfg(unix)]
+    interrupt: Option<tokio::signal::unix::Signal>,
+    #[cfg(unix)]
+    hangup: Option<tokio::signal::unix::Signal>,
+}
+
+impl SignalListener {
+    /// Installs the signal handlers. A handler that can't be installed is
+    /// logged and its signal is never received.
+    #[cfg(unix)]
+    pub fn new() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let listen = |kind: SignalKind, name: &str| match signal(kind) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                tracing::error!("Unable to listen for {}: {}", name, e);
+                None
+            }
+        };
+        return SignalListener {
+            terminate: listen(SignalKind::terminate(), "SIGTERM"),
+            interrupt: listen(SignalKind::interrupt(), "SIGINT"),
+            hangup: listen(SignalKind::hangup(), "SIGHUP"),
+        };
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> Self {
+        return SignalListener {};
+    }
+
+    /// Waits for the next signal
+    #[cfg(unix)]
+    pub async fn recv(&mut self) -> Signal {
+        return tokio::select! {
+            _ = recv(&mut self.terminate) => {
+                tracing::info!("Received SIGTERM");
+                Signal::Stop
+            }
+            _ = recv(&mut self.interrupt) => {
+                tracing::info!("Received SIGINT");
+                Signal::Stop
+            }
+            _ = recv(&mut self.hangup) => {
+                tracing::info!("Received SIGHUP");
+                Signal::Reload
+            }
+        };
+    }
+
+    #[cfg(not(unix))]
+    pub async fn recv(&mut self) -> Signal {
+        match tokio::signal::ctrl_c().await {
+            Ok(_) => tracing::info!("Received ctrl-c"),
+            Err(e) => {
+                tracing::error!("Unable to listen for ctrl-c: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+        return Signal::Stop;
+    }
+}
+
+#[cfg(unix)]
+async fn recv(signal: &mut Option<tokio::signal::unix::Signal>) {
+    match signal {
+        Some(s) => _ = s.recv().await,
+        None => std::future::pending::<()>().await,
+    }
+}
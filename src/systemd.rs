@@ -0,0 +1,247 @@
+/*
+Support for running as a systemd service. Readiness and state changes are sent
+to NOTIFY_SOCKET, and watchdog keep-alives are sent when WATCHDOG_USEC is set.
+Everything here does nothing when those variables are not set or on platforms
+without systemd.
+*/
+
+use std::{
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+
+#[cfg(unix)]
+use std::{
+    os::unix::net::{SocketAddr, UnixDatagram},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Sends state changes to the service manager's notify socket
+#[cfg(unix)]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// Set after a failed send so repeated watchdog failures are logged once
+    failing: AtomicBool,
+}
+
+#[cfg(unix)]
+impl Notifier {
+    /// Connects to the socket at path. Paths starting with @ are abstract
+    /// socket names.
+    pub fn new(path: &str) -> std::io::Result<Self> {
+        let addr = match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)
+            }
+            #[cfg(not(target_os = "linux"))]
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Abstract sockets are only supported on linux",
+                ))
+            }
+            None => SocketAddr::from_pathname(path),
+        };
+        let addr = match addr {
+            Ok(a) => a,
+            Err(e) => return Err(e),
+        };
+
+        return match UnixDatagram::unbound() {
+            Ok(socket) => Ok(Notifier {
+                socket,
+                addr,
+                failing: AtomicBool::new(false),
+            }),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Sends newline separated assignments such as `READY=1`
+    pub fn notify(&self, state: &str) {
+        match self.socket.send_to_addr(state.as_bytes(), &self.addr) {
+            Ok(_) => self.failing.store(false, Ordering::Relaxed),
+            Err(e) => {
+                if !self.failing.swap(true, Ordering::Relaxed) {
+                    tracing::warn!("Unable to notify systemd: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn notifier() -> Option<&'static Notifier> {
+    static NOTIFIER: OnceLock<Option<Notifier>> = OnceLock::new();
+    return NOTIFIER
+        .get_or_init(|| {
+            let path = std::env::var("NOTIFY_SOCKET").ok()?;
+            return match Notifier::new(&path) {
+                Ok(n) => Some(n),
+                Err(e) => {
+                    tracing::error!("Unable to use NOTIFY_SOCKET {}: {}", path, e);
+                    None
+                }
+            };
+        })
+        .as_ref();
+}
+
+/// Sends state to systemd if it is supervising this process
+pub fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(n) = notifier() {
+        n.notify(state);
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+pub fn ready(status: &str) {
+    notify(&format!("READY=1\nSTATUS={}", status));
+}
+
+pub fn reloading() {
+    notify("RELOADING=1\nSTATUS=Reloading");
+}
+
+pub fn stopping() {
+    notify("STOPPING=1\nSTATUS=Stopping");
+}
+
+/// Time between keep-alives, half of the watchdog timeout. None when the
+/// watchdog is off or meant for another process.
+fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if let Some(p) = watchdog_pid {
+        if p.parse::<u32>().ok() != Some(pid) {
+            return None;
+        }
+    }
+    return match usec.and_then(|u| u.parse::<u64>().ok()) {
+        Some(u) if u > 0 => Some(Duration::from_micros(u / 2)),
+        _ => None,
+    };
+}
+
+/// Runs check on a blocking thread, so a check that never returns holds up
+/// the keep-alives without blocking the runtime. False if check panicked.
+async fn is_alive<F: Fn() + Send + Sync + 'static>(check: &Arc<F>) -> bool {
+    let check = check.clone();
+    return match tokio::task::spawn_blocking(move || check()).await {
+        Ok(_) => true,
+        Err(e) => {
+            tracing::error!("Watchdog check failed: {}", e);
+            false
+        }
+    };
+}
+
+/// Sends a watchdog keep-alive each time check returns. check should take
+/// the locks requests need, so a deadlock or a stuck runtime stops the
+/// keep-alives and systemd restarts the service. Abort the returned task
+/// when the state check looks at goes away.
+pub fn start_watchdog<F: Fn() + Send + Sync + 'static>(check: F) -> Option<JoinHandle<()>> {
+    let interval = watchdog_interval(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    );
+    let interval = match interval {
+        Some(i) => i,
+        None => return None,
+    };
+
+    tracing::info!("Sending systemd watchdog keep-alives every {:?}", interval);
+    let check = Arc::new(check);
+    return Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if is_alive(&check).await {
+                notify("WATCHDOG=1");
+            }
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchdog() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::await_holding_lock)] // the held lock is what is being tested
+    async fn watchdog_check() {
+        let lock = Arc::new(std::sync::RwLock::new(()));
+        let check_lock = lock.clone();
+        let check = Arc::new(move || drop(check_lock.read().unwrap()));
+        assert!(is_alive(&check).await);
+
+        // A lock that is never released stops the keep-alives
+        let held = lock.write().unwrap();
+        assert!(tokio::time::timeout(Duration::from_millis(200), is_alive(&check))
+            .await
+            .is_err());
+        drop(held);
+        assert!(is_alive(&check).await);
+
+        assert!(!is_alive(&Arc::new(|| panic!("poisoned"))).await);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fake_notify_socket() {
+        let path = crate::test_utils::make_tmp_file();
+        std::fs::remove_file(&path).unwrap();
+        let server = UnixDatagram::bind(&path).unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let notifier = Notifier::new(&path).unwrap();
+        let mut buf = [0u8; 256];
+        let mut recv = || {
+            let n = server.recv(&mut buf).unwrap();
+            return String::from_utf8_lossy(&buf[..n]).to_string();
+        };
+
+        notifier.notify("READY=1\nSTATUS=Listening");
+        assert_eq!(recv(), "READY=1\nSTATUS=Listening");
+        notifier.notify("WATCHDOG=1");
+        assert_eq!(recv(), "WATCHDOG=1");
+
+        assert!(Notifier::new("bad\0path").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn abstract_notify_socket() {
+        use std::os::linux::net::SocketAddrExt;
+
+        let name = format!("potato_auth_test_{}", std::process::id());
+        let server = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        Notifier::new(&format!("@{}", name)).unwrap().notify("STOPPING=1");
+        let mut buf = [0u8; 64];
+        let n = server.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STOPPING=1");
+    }
+}
@@ -0,0 +1,294 @@
+/*
+Conversion of full user records, including password hashes, to and from the
+formats used for backups and moving accounts between servers
+*/
+
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    i18n::Message,
+    userdb::{AcctType, ExternalSource},
+    Error, Result,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TransferFormat {
+    #[default]
+    Json,
+    Csv,
+    /// Apache htpasswd file. Only supported for imports.
+    Htpasswd,
+}
+
+/// A user with everything needed to recreate it in another user db. The
+/// fields after acct_type were added later, so they may be missing from
+/// older exports.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedUser {
+    pub name: String,
+    pub hashed_password: String,
+    pub paths: Vec<String>,
+    pub acct_type: AcctType,
+    #[serde(default)]
+    pub disabled: bool,
+    /// Unix time
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub schedule: Option<String>,
+    #[serde(default)]
+    pub must_change_password: bool,
+    /// Unix time
+    #[serde(default)]
+    pub password_changed_at: Option<i64>,
+    /// Hashes of previous passwords that may not be reused
+    #[serde(default)]
+    pub password_history: Vec<String>,
+    /// Provider that owns an account without a local password
+    #[serde(default)]
+    pub source: Option<ExternalSource>,
+}
+
+impl ExportedUser {
+    /// A record with only the fields every export has
+    pub fn new(name: String, hashed_password: String, paths: Vec<String>, acct_type: AcctType) -> Self {
+        return ExportedUser {
+            name,
+            hashed_password,
+            paths,
+            acct_type,
+            disabled: false,
+            expires_at: None,
+            schedule: None,
+            must_change_password: false,
+            password_changed_at: None,
+            password_history: Vec::new(),
+            source: None,
+        };
+    }
+}
+
+/// Columns after the first CSV_REQUIRED may be left out
+const CSV_HEADER: [&str; 11] = [
+    "name",
+    "hashed_password",
+    "paths",
+    "acct_type",
+    "disabled",
+    "expires_at",
+    "schedule",
+    "must_change_password",
+    "password_changed_at",
+    "password_history",
+    "source",
+];
+const CSV_REQUIRED: usize = 4;
+
+pub fn serialize(users: &Vec<ExportedUser>, format: TransferFormat) -> Result<String> {
+    return match format {
+        TransferFormat::Json => match serde_json::to_string_pretty(users) {
+            Ok(s) => Ok(s),
+            Err(e) => Err(Error::convert(e)),
+        },
+        TransferFormat::Csv => to_csv(users),
+        TransferFormat::Htpasswd => Err(Error::invalid(Message::new("transfer.htpasswd_export").field("format"))),
+    };
+}
+
+/// Parses JSON or CSV user records. htpasswd data is handled separately by
+/// UserDB::import_htpasswd since it carries no paths or account type.
+pub fn deserialize(data: &str, format: TransferFormat) -> Result<Vec<ExportedUser>> {
+    return match format {
+        TransferFormat::Json => match serde_json::from_str(data) {
+            Ok(u) => Ok(u),
+            Err(e) => Err(invalid_data(e)),
+        },
+        TransferFormat::Csv => from_csv(data),
+        TransferFormat::Htpasswd => Err(Error::invalid(
+            Message::new("transfer.htpasswd_records").field("format"),
+        )),
+    };
+}
+
+fn invalid_data(e: impl std::error::Error) -> Error {
+    return Error::invalid(Message::new("transfer.invalid_data").field("data").arg("error", e));
+}
+
+fn to_csv(users: &Vec<ExportedUser>) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    match writer.write_record(CSV_HEADER) {
+        Ok(_) => {}
+        Err(e) => return Err(Error::convert(e)),
+    }
+
+    let optional = |v: Option<String>| v.unwrap_or_default();
+    for u in users {
+        let record = [
+            u.name.to_owned(),
+            u.hashed_password.to_owned(),
+            u.paths.join(","),
+            format!("{:?}", u.acct_type),
+            u.disabled.to_string(),
+            optional(u.expires_at.map(|t| t.to_string())),
+            optional(u.schedule.to_owned()),
+            u.must_change_password.to_string(),
+            optional(u.password_changed_at.map(|t| t.to_string())),
+            u.password_history.join(";"),
+            optional(u.source.map(|s| s.to_string())),
+        ];
+        match writer.write_record(record) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::convert(e)),
+        }
+    }
+
+    return match writer.into_inner() {
+        Ok(bytes) => Ok(String::from_utf8_lossy(&bytes).to_string()),
+        Err(e) => Err(Error::new(e.to_string())),
+    };
+}
+
+fn from_csv(data: &str) -> Result<Vec<ExportedUser>> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+
+    match reader.headers() {
+        Ok(h)
+            if (CSV_REQUIRED..=CSV_HEADER.len()).contains(&h.len())
+                && h.iter().eq(CSV_HEADER[..h.len()].iter().copied()) => {}
+        Ok(h) => {
+            return Err(Error::invalid(
+                Message::new("transfer.csv_header")
+                    .field("data")
+                    .arg("expected", CSV_HEADER.join(","))
+                    .arg("found", h.iter().collect::<Vec<_>>().join(",")),
+            ))
+        }
+        Err(e) => return Err(invalid_data(e)),
+    }
+
+    let mut users = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => return Err(invalid_data(e)),
+        };
+
+        let acct_type = match AcctType::from_str(&record[3]) {
+            Ok(a) => a,
+            Err(_) => {
+                return Err(Error::invalid(
+                    Message::new("transfer.acct_type")
+                        .field("data")
+                        .arg("acct_type", &record[3])
+                        .arg("row", i + 1),
+                ))
+            }
+        };
+
+        let mut user = ExportedUser::new(
+            record[0].to_string(),
+            record[1].to_string(),
+            record[2]
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty())
+                .collect(),
+            acct_type,
+        );
+
+        // Missing columns and empty values leave the defaults
+        for (column, value) in CSV_HEADER.iter().zip(record.iter()).skip(CSV_REQUIRED) {
+            if value.is_empty() {
+                continue;
+            }
+            let valid = match *column {
+                "disabled" => value.parse().map(|v| user.disabled = v).is_ok(),
+                "expires_at" => value.parse().map(|v| user.expires_at = Some(v)).is_ok(),
+                "schedule" => {
+                    user.schedule = Some(value.to_string());
+                    true
+                }
+                "must_change_password" => value.parse().map(|v| user.must_change_password = v).is_ok(),
+                "password_changed_at" => value.parse().map(|v| user.password_changed_at = Some(v)).is_ok(),
+                "password_history" => {
+                    user.password_history = value.split(';').map(|h| h.to_string()).collect();
+                    true
+                }
+                "source" => value.parse().map(|v| user.source = Some(v)).is_ok(),
+                _ => false,
+            };
+            if !valid {
+                return Err(Error::invalid(
+                    Message::new("transfer.csv_value")
+                        .field("data")
+                        .arg("column", column)
+                        .arg("value", value)
+                        .arg("row", i + 1),
+                ));
+            }
+        }
+        users.push(user);
+    }
+    return Ok(users);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let hash = "$argon2i$v=19$m=16,t=2,p=1$Z3FyNTJoUlFZSkFPZE80TA$uOHPaLj1dPwVuStA8SlpXA";
+        let users = vec![
+            ExportedUser {
+                disabled: true,
+                expires_at: Some(1700000000),
+                schedule: Some("kids".to_string()),
+                must_change_password: true,
+                password_changed_at: Some(1600000000),
+                password_history: vec![hash.to_string(), "{SHA}W6ph5Mm5Pz8GgiULbPgzG37mj9g=".to_string()],
+                ..ExportedUser::new(
+                    "a".to_string(),
+                    hash.to_string(),
+                    vec!["/a/**".to_string(), "/b".to_string()],
+                    AcctType::Admin,
+                )
+            },
+            ExportedUser {
+                source: Some(ExternalSource::Ldap),
+                ..ExportedUser::new("b".to_string(), "!".to_string(), vec![], AcctType::User)
+            },
+        ];
+
+        for format in [TransferFormat::Json, TransferFormat::Csv] {
+            let data = serialize(&users, format).unwrap();
+            assert_eq!(deserialize(&data, format).unwrap(), users);
+        }
+
+        assert!(serialize(&users, TransferFormat::Htpasswd).is_err());
+        assert!(deserialize("name,password\na,b", TransferFormat::Csv).is_err());
+        assert!(deserialize(
+            "name,hashed_password,paths,acct_type,disabled\na,!,,User,maybe",
+            TransferFormat::Csv
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn older_exports() {
+        let expected = vec![ExportedUser::new(
+            "a".to_string(),
+            "!".to_string(),
+            vec!["/a/**".to_string()],
+            AcctType::User,
+        )];
+        let json = r#"[{"name": "a", "hashed_password": "!", "paths": ["/a/**"], "acct_type": "User"}]"#;
+        assert_eq!(deserialize(json, TransferFormat::Json).unwrap(), expected);
+        let csv = "name,hashed_password,paths,acct_type\na,!,/a/**,User\n";
+        assert_eq!(deserialize(csv, TransferFormat::Csv).unwrap(), expected);
+    }
+}
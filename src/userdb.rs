@@ -4,30 +4,128 @@ use std::{
     io::BufReader,
     io::{BufRead, Write},
     str::FromStr,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock,
+    },
+    time::SystemTime,
 };
 
-use actix_web::web;
+use actix_web::{cookie::time::OffsetDateTime, web};
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    file_utils::{file_exists, make_dirs_and_write},
+    config::{Argon2Config, Argon2Variant, PasswordPolicy, UserConfig},
+    file_utils::{file_exists, lock_file, make_dirs_and_write, FileLock},
+    htpasswd,
+    i18n::Message,
     shared_data::Sharable,
+    transfer::ExportedUser,
     Error, Result,
 };
 
-pub fn hash_password<T>(plain_password: T) -> String
+/// Stored in place of a password hash for users that authenticate through an
+/// external provider. Never matches any password.
+pub const NO_PASSWORD: &str = "!";
+
+pub fn hash_password<T>(plain_password: T, cfg: &Argon2Config) -> String
 where
     T: AsRef<[u8]>,
 {
     // hash_encoded returns a Result<String>, but as far as I can tell the only
-    // failure condition is an unallowed salt len
+    // failure condition is an unallowed salt len or out of range costs. The
+    // costs are checked when the config is loaded, so the defaults are only
+    // a last resort.
 
     let mut salt = [0u8; 16];
 
     rand::thread_rng().fill_bytes(&mut salt);
-    return argon2::hash_encoded(plain_password.as_ref(), &salt, &argon2::Config::default()).unwrap();
+
+    return match argon2::hash_encoded(plain_password.as_ref(), &salt, &cfg.params()) {
+        Ok(h) => h,
+        Err(e) => {
+            tracing::error!("Invalid argon2 config ({}); using defaults", e);
+            argon2::hash_encoded(plain_password.as_ref(), &salt, &argon2::Config::default()).unwrap()
+        }
+    };
+}
+
+/// Compares a plain password to any hash format that may be stored in the db
+fn hash_matches(hash: &str, plain_password: &str) -> bool {
+    if hash == NO_PASSWORD {
+        return false;
+    }
+    if htpasswd::is_supported(hash) {
+        return htpasswd::verify(hash, plain_password);
+    }
+    return argon2::verify_encoded(hash, plain_password.as_bytes()).unwrap_or(false);
+}
+
+/// Checks if hash is a complete encoded argon2 hash, without anything that
+/// would be read as another field of the user db
+fn is_argon2_hash(hash: &str) -> bool {
+    // $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>, the version is optional
+    let sections: Vec<&str> = hash.split('$').collect();
+    let (variant, params, salt, digest) = match sections.as_slice() {
+        ["", variant, version, params, salt, digest] => match version.strip_prefix("v=").map(|v| v.parse::<u32>()) {
+            Some(Ok(_)) => (*variant, *params, *salt, *digest),
+            _ => return false,
+        },
+        ["", variant, params, salt, digest] => (*variant, *params, *salt, *digest),
+        _ => return false,
+    };
+
+    if !["argon2d", "argon2i", "argon2id"].contains(&variant) {
+        return false;
+    }
+
+    let mut names = Vec::new();
+    for param in params.split(',') {
+        match param.split_once('=') {
+            Some((name, v)) if v.parse::<u32>().is_ok() => names.push(name),
+            _ => return false,
+        }
+    }
+    if names != ["m", "t", "p"] {
+        return false;
+    }
+
+    return [salt, digest].iter().all(|s| !s.is_empty() && STANDARD_NO_PAD.decode(s).is_ok());
+}
+
+/// Checks if an encoded argon2 hash was made with a different variant or lower
+/// costs than cfg
+fn is_weaker_hash(encoded: &str, cfg: &Argon2Config) -> bool {
+    // $argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>
+    let mut sections = encoded.split('$').skip(1);
+    let variant = sections.next().unwrap_or_default();
+    let expected_variant = match cfg.variant {
+        Argon2Variant::Argon2d => "argon2d",
+        Argon2Variant::Argon2i => "argon2i",
+        Argon2Variant::Argon2id => "argon2id",
+    };
+    if variant != expected_variant {
+        return true;
+    }
+
+    let params = match sections.find(|s| s.starts_with("m=")) {
+        Some(p) => p,
+        None => return true,
+    };
+
+    let (mut m, mut t, mut p) = (0, 0, 0);
+    for param in params.split(',') {
+        match param.split_once('=') {
+            Some(("m", v)) => m = v.parse().unwrap_or(0),
+            Some(("t", v)) => t = v.parse().unwrap_or(0),
+            Some(("p", v)) => p = v.parse().unwrap_or(0),
+            _ => {}
+        }
+    }
+
+    return m < cfg.mem_cost || t < cfg.time_cost || p < cfg.lanes;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -48,12 +146,57 @@ impl FromStr for AcctType {
     }
 }
 
+/// The provider that owns an account without a local password. Logins from
+/// one provider are never mapped onto accounts owned by another.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExternalSource {
+    Oidc,
+    Ldap,
+}
+
+impl FromStr for ExternalSource {
+    type Err = ();
+
+    fn from_str(input: &str) -> Result<ExternalSource, Self::Err> {
+        match input {
+            "oidc" => Ok(ExternalSource::Oidc),
+            "ldap" => Ok(ExternalSource::Ldap),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for ExternalSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            ExternalSource::Oidc => write!(f, "oidc"),
+            ExternalSource::Ldap => write!(f, "ldap"),
+        };
+    }
+}
+
+#[derive(Clone)]
 pub struct User {
     name: String,
     acct_type: AcctType,
     hashed_password: String,
     paths: Vec<String>,
     path_patterns: Vec<glob::Pattern>,
+    /// Previous password hashes, newest first
+    password_history: Vec<String>,
+    /// Unix time of the last password change, if known
+    password_changed_at: Option<i64>,
+    /// Set when an admin resets the password so the user picks their own
+    must_change_password: bool,
+    disabled: bool,
+    /// Unix time after which the account can no longer be used
+    expires_at: Option<i64>,
+    /// Name of the access schedule that limits when /authrequest lets this
+    /// user through. Logging in is not affected.
+    schedule: Option<String>,
+    /// Provider that owns the account if it has no local password
+    source: Option<ExternalSource>,
 }
 
 impl PartialEq for User {
@@ -67,6 +210,13 @@ impl PartialEq for User {
             && self.hashed_password == other.hashed_password
             && self.paths == other.paths
             && self.path_patterns == other.path_patterns
+            && self.password_history == other.password_history
+            && self.password_changed_at == other.password_changed_at
+            && self.must_change_password == other.must_change_password
+            && self.disabled == other.disabled
+            && self.expires_at == other.expires_at
+            && self.schedule == other.schedule
+            && self.source == other.source
     }
 }
 
@@ -88,25 +238,25 @@ impl User {
             paths: allowed_paths.to_owned(),
             path_patterns: patterns,
             acct_type,
+            password_history: Vec::new(),
+            password_changed_at: None,
+            must_change_password: false,
+            disabled: false,
+            expires_at: None,
+            schedule: None,
+            source: None,
         });
     }
 
+    /// Replaces any provided fields. The password must already be hashed;
+    /// use UserDB::update_user to change a password from plain text.
     pub fn update_info(
         &mut self,
-        plain_password: Option<String>,
+        hashed_password: Option<String>,
         paths: Option<Vec<String>>,
         acct_type: Option<AcctType>,
     ) -> Result<()> {
         // Validate everything first
-        let mut hashed_pass: Option<String> = None;
-        match plain_password {
-            Some(p) => match UserDB::validate_password(&p) {
-                Ok(_) => hashed_pass = Some(hash_password(p)),
-                Err(e) => return Err(e),
-            },
-            None => {}
-        }
-
         let mut parsed_paths: Option<Vec<glob::Pattern>> = None;
         match &paths {
             Some(p) => match User::parse_paths(&p) {
@@ -117,8 +267,12 @@ impl User {
         }
 
         // Assign new values
-        match hashed_pass {
-            Some(h) => self.hashed_password = h,
+        match hashed_password {
+            Some(h) => {
+                self.hashed_password = h;
+                self.password_changed_at = Some(OffsetDateTime::now_utc().unix_timestamp());
+                self.must_change_password = false;
+            }
             None => {}
         }
 
@@ -145,7 +299,9 @@ impl User {
             match glob::Pattern::new(s) {
                 Ok(p) => patterns.push(p),
                 Err(e) => {
-                    return Err(Error::new(format!("Invalid glob pattern {}: {}", s, e)));
+                    return Err(Error::invalid(
+                        Message::new("glob.invalid").field("paths").arg("pattern", s).arg("error", e),
+                    ));
                 }
             }
         }
@@ -169,14 +325,147 @@ impl User {
         return &self.acct_type;
     }
 
+    pub fn set_must_change_password(&mut self, must_change: bool) {
+        self.must_change_password = must_change;
+    }
+
+    pub fn set_disabled(&mut self, disabled: bool) {
+        self.disabled = disabled;
+    }
+
+    pub fn set_expires_at(&mut self, expires_at: Option<i64>) {
+        self.expires_at = expires_at;
+    }
+
+    pub fn get_schedule(&self) -> Option<&String> {
+        return self.schedule.as_ref();
+    }
+
+    pub fn set_schedule(&mut self, schedule: Option<String>) {
+        self.schedule = schedule;
+    }
+
+    /// Checks if the account has no local password and was created for an
+    /// external identity provider or directory
+    pub fn is_external(&self) -> bool {
+        return self.hashed_password == NO_PASSWORD;
+    }
+
+    /// Checks if the account has no local password and belongs to source.
+    /// External accounts created before sources were recorded belong to
+    /// no provider.
+    pub fn is_owned_by(&self, source: ExternalSource) -> bool {
+        return self.is_external() && self.source == Some(source);
+    }
+
+    /// Checks that the account is neither disabled nor expired
+    pub fn is_active(&self) -> bool {
+        if self.disabled {
+            return false;
+        }
+        return match self.expires_at {
+            Some(exp) => OffsetDateTime::now_utc().unix_timestamp() < exp,
+            None => true,
+        };
+    }
+
+    /// Names of the fields that differ from other
+    fn changed_fields(&self, other: &User) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+        if self.acct_type != other.acct_type {
+            changed.push("type");
+        }
+        if self.hashed_password != other.hashed_password {
+            changed.push("password");
+        }
+        if self.paths != other.paths {
+            changed.push("paths");
+        }
+        if self.password_history != other.password_history {
+            changed.push("password history");
+        }
+        if self.password_changed_at != other.password_changed_at || self.must_change_password != other.must_change_password {
+            changed.push("password expiry");
+        }
+        if self.disabled != other.disabled {
+            changed.push("disabled");
+        }
+        if self.expires_at != other.expires_at {
+            changed.push("expiration");
+        }
+        if self.schedule != other.schedule {
+            changed.push("schedule");
+        }
+        if self.source != other.source {
+            changed.push("source");
+        }
+        return changed;
+    }
+
     fn to_line(&self) -> String {
-        return format!(
+        let mut line = format!(
             "{}:{}:{}:{:#?}",
             self.name,
             self.hashed_password,
             self.paths.join(","),
             self.acct_type
         );
+
+        // Optional fields are only written when set so older files stay
+        // readable by older versions
+        if !self.password_history.is_empty() {
+            line.push_str(&format!(":history={}", self.password_history.join(";")));
+        }
+        match self.password_changed_at {
+            Some(t) => line.push_str(&format!(":password_changed_at={}", t)),
+            None => {}
+        }
+        if self.must_change_password {
+            line.push_str(":must_change_password=true");
+        }
+        if self.disabled {
+            line.push_str(":disabled=true");
+        }
+        match self.expires_at {
+            Some(t) => line.push_str(&format!(":expires_at={}", t)),
+            None => {}
+        }
+        match &self.schedule {
+            Some(s) => line.push_str(&format!(":schedule={}", s)),
+            None => {}
+        }
+        match self.source {
+            Some(s) => line.push_str(&format!(":source={}", s)),
+            None => {}
+        }
+        return line;
+    }
+
+    /// Reads an optional `key=value` field that follows the account type.
+    /// Returns false if the field is not recognized.
+    fn parse_field(&mut self, field: &str) -> bool {
+        match field.split_once('=') {
+            Some(("history", v)) => {
+                self.password_history = v.split(';').filter(|h| !h.is_empty()).map(|h| h.to_string()).collect();
+            }
+            Some(("password_changed_at", v)) => match v.parse::<i64>() {
+                Ok(t) => self.password_changed_at = Some(t),
+                Err(_) => return false,
+            },
+            Some(("must_change_password", v)) => self.must_change_password = v == "true",
+            Some(("disabled", v)) => self.disabled = v == "true",
+            Some(("expires_at", v)) => match v.parse::<i64>() {
+                Ok(t) => self.expires_at = Some(t),
+                Err(_) => return false,
+            },
+            Some(("schedule", v)) => self.schedule = Some(v.to_string()),
+            Some(("source", v)) => match v.parse::<ExternalSource>() {
+                Ok(s) => self.source = Some(s),
+                Err(_) => return false,
+            },
+            _ => return false,
+        }
+        return true;
     }
 }
 
@@ -186,6 +475,12 @@ pub struct SafeSerializableUser {
     pub name: String,
     pub acct_type: AcctType,
     pub paths: Vec<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub schedule: Option<String>,
 }
 
 impl From<&Arc<RwLock<User>>> for SafeSerializableUser {
@@ -195,13 +490,43 @@ impl From<&Arc<RwLock<User>>> for SafeSerializableUser {
             name: u.name.to_owned(),
             acct_type: u.acct_type,
             paths: u.paths.to_owned(),
+            disabled: u.disabled,
+            expires_at: u.expires_at,
+            schedule: u.schedule.to_owned(),
         };
     }
 }
 
+/// Outcome of importing users from another source
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// True if nothing was written and added lists the users that would
+    /// have been imported
+    pub dry_run: bool,
+    pub added: Vec<String>,
+    /// Entries that were not imported, each with the reason
+    pub skipped: Vec<String>,
+}
+
 pub struct UserDB {
     users: HashMap<String, Arc<RwLock<User>>>, // K: Name, V: User
     filepath: String,
+    argon2: Argon2Config,
+    policy: PasswordPolicy,
+    /// Set when the last write to filepath failed
+    unsaved: AtomicBool,
+    /// Held for the life of a UserDB made by from_config_locked
+    lock: Option<FileLock>,
+    /// File state when this db last read or wrote filepath
+    file_state: Option<FileState>,
+}
+
+/// Modification time and length of a file, to tell if someone else wrote it
+type FileState = (SystemTime, u64);
+
+fn file_state(filepath: &str) -> Option<FileState> {
+    let meta = std::fs::metadata(filepath).ok()?;
+    return Some((meta.modified().ok()?, meta.len()));
 }
 
 impl UserDB {
@@ -209,19 +534,86 @@ impl UserDB {
         let mut um = UserDB {
             users: HashMap::new(),
             filepath: filepath.clone(),
+            argon2: Argon2Config::default(),
+            policy: PasswordPolicy::default(),
+            unsaved: AtomicBool::new(false),
+            lock: None,
+            file_state: None,
+        };
+
+        return match um.parse_file(filepath, false) {
+            Ok(_) => Ok(um),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Loads the user db named in cfg using cfg's password settings
+    pub fn from_config(cfg: &UserConfig) -> Result<Self> {
+        let mut um = match UserDB::new(cfg.user_db.as_ref().unwrap()) {
+            Ok(u) => u,
+            Err(e) => return Err(e),
+        };
+        um.set_argon2_config(cfg.argon2.clone().unwrap_or_default());
+        um.set_password_policy(cfg.password_policy.clone().unwrap_or_default());
+        return Ok(um);
+    }
+
+    /// Like from_config, but keeps the user db locked until dropped so other
+    /// processes, including a running server, can't write to it in between
+    pub fn from_config_locked(cfg: &UserConfig) -> Result<Self> {
+        let lock = match lock_file(cfg.user_db.as_ref().unwrap()) {
+            Ok(l) => l,
+            Err(e) => return Err(e),
+        };
+        return match UserDB::from_config(cfg) {
+            Ok(mut um) => {
+                um.lock = Some(lock);
+                Ok(um)
+            }
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Like from_config, but a missing file or any invalid entry is an
+    /// error instead of being created or skipped. Used when reloading a
+    /// running server so a bad edit can't lock users out.
+    pub fn from_config_strict(cfg: &UserConfig) -> Result<Self> {
+        let filepath = cfg.user_db.as_ref().unwrap();
+        let mut um = UserDB {
+            users: HashMap::new(),
+            filepath: filepath.clone(),
+            argon2: cfg.argon2.clone().unwrap_or_default(),
+            policy: cfg.password_policy.clone().unwrap_or_default(),
+            unsaved: AtomicBool::new(false),
+            lock: None,
+            file_state: None,
         };
 
-        return match um.parse_file(filepath) {
+        return match um.parse_file(filepath, true) {
             Ok(_) => Ok(um),
             Err(e) => Err(e),
         };
     }
 
-    fn parse_file(&mut self, filepath: &String) -> Result<()> {
+    pub fn set_argon2_config(&mut self, cfg: Argon2Config) {
+        self.argon2 = cfg;
+    }
+
+    pub fn set_password_policy(&mut self, policy: PasswordPolicy) {
+        self.policy = policy;
+    }
+
+    fn parse_file(&mut self, filepath: &String, strict: bool) -> Result<()> {
         tracing::info!("Loading {}", filepath);
+        if strict && !file_exists(filepath) {
+            return crate::err!("{} does not exist", filepath);
+        }
         if !file_exists(filepath) {
             match File::create(filepath) {
-                Ok(_) => return Ok(()),
+                Ok(_) => {
+                    self.file_state = file_state(filepath);
+                    return Ok(());
+                }
                 Err(e) => {
                     tracing::error!("Could not create file: {}", e);
                     return Err(Error::convert(e));
@@ -229,6 +621,8 @@ impl UserDB {
             }
         };
 
+        // Taken before reading, so a write that lands in between is noticed
+        self.file_state = file_state(filepath);
         let file = match File::open(filepath) {
             Ok(f) => f,
             Err(e) => {
@@ -247,13 +641,23 @@ impl UserDB {
                     return Err(Error::convert(e));
                 }
             };
+            if strict && line.trim().is_empty() {
+                continue;
+            }
+
             let parts: Vec<String> = line.split(':').map(|s| s.to_string()).collect();
-            if parts.len() != 4 {
+            if parts.len() < 4 {
+                if strict {
+                    return crate::err!("Invalid entry on line {} of {}", i, filepath);
+                }
                 tracing::warn!("Invalid entry on line {} of {}", i, filepath);
                 continue;
             }
 
             if self.users.contains_key(&parts[0]) {
+                if strict {
+                    return crate::err!("Duplicate user `{}` on line {}", parts[0], i);
+                }
                 tracing::warn!(
                     "Duplicate user `{}` on line {}; this line will be ignored.",
                     parts[0],
@@ -263,6 +667,9 @@ impl UserDB {
 
             let acct_type = match AcctType::from_str(&parts[3]) {
                 Ok(r) => r,
+                Err(_) if strict => {
+                    return crate::err!("Invalid acct type `{}` on line {}", parts[3], i);
+                }
                 Err(_) => {
                     tracing::warn!(
                         "Invalid acct type `{}` on line {}; this line will be ignored",
@@ -273,7 +680,7 @@ impl UserDB {
                 }
             };
 
-            let usr = match User::new(
+            let mut usr = match User::new(
                 &parts[0],
                 &parts[1],
                 &parts[2].split(',').map(|s| s.to_string()).collect(),
@@ -283,6 +690,12 @@ impl UserDB {
                 Err(e) => return Err(e),
             };
 
+            for field in &parts[4..] {
+                if !usr.parse_field(field) {
+                    tracing::warn!("Unknown field `{}` on line {}; it will be ignored", field, i);
+                }
+            }
+
             self.users.insert(parts[0].to_owned(), Arc::new(RwLock::new(usr)));
         }
         return Ok(());
@@ -292,6 +705,30 @@ impl UserDB {
         return self.users.len();
     }
 
+    /// Describes how other differs from this db, with one line for each
+    /// user that was added, removed or changed
+    pub fn diff(&self, other: &UserDB) -> Vec<String> {
+        let mut names: Vec<&String> = self.users.keys().chain(other.users.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        let mut lines = Vec::new();
+        for name in names {
+            match (self.users.get(name), other.users.get(name)) {
+                (Some(_), None) => lines.push(format!("removed user `{}`", name)),
+                (None, Some(_)) => lines.push(format!("added user `{}`", name)),
+                (Some(old), Some(new)) => {
+                    let changed = old.read().unwrap().changed_fields(&new.read().unwrap());
+                    if !changed.is_empty() {
+                        lines.push(format!("changed {} of user `{}`", changed.join(", "), name));
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        return lines;
+    }
+
     /// Adds a new unique user. Will return an error if that user name
     /// already exists in the db
     pub fn add_user(
@@ -306,15 +743,21 @@ impl UserDB {
             Err(e) => return Err(e),
         }
 
-        match Self::validate_password(plain_password) {
+        match self.validate_password(name, plain_password, &[]) {
             Ok(_) => {}
             Err(e) => return Err(e),
         }
 
-        let user = match User::new(name, &hash_password(plain_password), allowed_paths, acct_type) {
+        let mut user = match User::new(
+            name,
+            &hash_password(plain_password, &self.argon2),
+            allowed_paths,
+            acct_type,
+        ) {
             Ok(u) => u,
             Err(e) => return Err(e),
         };
+        user.password_changed_at = Some(OffsetDateTime::now_utc().unix_timestamp());
 
         match self.append_to_file(&user) {
             Ok(_) => {}
@@ -326,74 +769,441 @@ impl UserDB {
         return Ok(());
     }
 
+    /// Adds a new unique user that has no local password and can only log in
+    /// through the external provider source
+    pub fn add_external_user(
+        &mut self,
+        name: &String,
+        allowed_paths: &Vec<String>,
+        acct_type: AcctType,
+        source: ExternalSource,
+    ) -> Result<&Arc<RwLock<User>>> {
+        match self.validate_username(name, true) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut user = match User::new(name, &NO_PASSWORD.to_string(), allowed_paths, acct_type) {
+            Ok(u) => u,
+            Err(e) => return Err(e),
+        };
+        user.source = Some(source);
+
+        match self.append_to_file(&user) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        self.users.insert(name.to_owned(), Arc::new(RwLock::new(user)));
+
+        return Ok(self.users.get(name).unwrap());
+    }
+
+    /// Creates or updates a user managed by an external directory so that
+    /// its paths and account type match the directory. Local accounts with a
+    /// password and accounts owned by other providers are never modified.
+    pub fn sync_external_user(
+        &mut self,
+        name: &String,
+        allowed_paths: &Vec<String>,
+        acct_type: AcctType,
+        source: ExternalSource,
+    ) -> Result<Arc<RwLock<User>>> {
+        let user = match self.users.get(name) {
+            Some(u) => u.clone(),
+            None => return self.add_external_user(name, allowed_paths, acct_type, source).cloned(),
+        };
+
+        let mut user_w = user.write().unwrap();
+        if !user_w.is_external() {
+            return crate::err!("`{}` is a local account", name);
+        }
+
+        if !user_w.is_owned_by(source) {
+            return crate::err!("`{}` is not managed by {}", name, source);
+        }
+
+        if !user_w.is_active() {
+            return crate::err!("`{}` is disabled or expired", name);
+        }
+
+        if &user_w.paths == allowed_paths && user_w.acct_type == acct_type {
+            drop(user_w);
+            return Ok(user);
+        }
+
+        match user_w.update_info(None, Some(allowed_paths.to_owned()), Some(acct_type)) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+        drop(user_w);
+
+        return match self.write_to_file() {
+            Ok(_) => Ok(user),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Adds users from the contents of an Apache htpasswd file. Each user
+    /// keeps their existing hash until their next successful login. Entries
+    /// that cannot be imported are listed in the report rather than failing
+    /// the whole import.
+    pub fn import_htpasswd(
+        &mut self,
+        contents: &str,
+        allowed_paths: &Vec<String>,
+        acct_type: AcctType,
+        dry_run: bool,
+    ) -> Result<ImportReport> {
+        match User::parse_paths(allowed_paths) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut records = Vec::new();
+        let mut invalid_lines = Vec::new();
+        for entry in htpasswd::parse(contents) {
+            match entry {
+                Ok((name, hash)) => records.push(ExportedUser::new(name, hash, allowed_paths.to_owned(), acct_type)),
+                Err(line) => invalid_lines.push(format!("Line {}: invalid entry", line)),
+            }
+        }
+
+        return match self.import_users(records, dry_run) {
+            Ok(mut report) => {
+                report.skipped.extend(invalid_lines);
+                Ok(report)
+            }
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Adds users exported from another user db. Users whose names already
+    /// exist are never overwritten and are listed in the report as skipped.
+    /// With dry_run the report is built without changing the db.
+    pub fn import_users(&mut self, users: Vec<ExportedUser>, dry_run: bool) -> Result<ImportReport> {
+        let mut report = ImportReport {
+            dry_run,
+            ..Default::default()
+        };
+
+        let mut accepted: Vec<User> = Vec::new();
+        for u in users {
+            if report.added.contains(&u.name) {
+                report.skipped.push(format!("{}: duplicate entry", u.name));
+                continue;
+            }
+
+            match self.validate_username(&u.name, true) {
+                Ok(_) => {}
+                Err(e) => {
+                    report.skipped.push(format!("{}: {}", u.name, e));
+                    continue;
+                }
+            }
+
+            if !(is_argon2_hash(&u.hashed_password)
+                || u.hashed_password == NO_PASSWORD
+                || htpasswd::is_supported(&u.hashed_password))
+            {
+                report.skipped.push(format!("{}: unsupported hash format", u.name));
+                continue;
+            }
+
+            if !u.password_history.iter().all(|h| is_argon2_hash(h) || htpasswd::is_supported(h)) {
+                report.skipped.push(format!("{}: unsupported hash format in password history", u.name));
+                continue;
+            }
+
+            // Would split the entry into other fields or lines once saved
+            match u.paths.iter().find(|p| p.contains([':', ',', '\n', '\r'])) {
+                Some(p) => {
+                    report.skipped.push(format!("{}: invalid character in path `{}`", u.name, p.escape_debug()));
+                    continue;
+                }
+                None => {}
+            }
+            match &u.schedule {
+                Some(s) if s.is_empty() || s.contains([':', '\n', '\r']) => {
+                    report.skipped.push(format!("{}: invalid schedule name `{}`", u.name, s.escape_debug()));
+                    continue;
+                }
+                _ => {}
+            }
+
+            match User::new(&u.name, &u.hashed_password, &u.paths, u.acct_type) {
+                Ok(mut user) => {
+                    user.disabled = u.disabled;
+                    user.expires_at = u.expires_at;
+                    user.schedule = u.schedule;
+                    user.must_change_password = u.must_change_password;
+                    user.password_changed_at = u.password_changed_at;
+                    user.password_history = u.password_history;
+                    user.source = u.source;
+                    report.added.push(u.name);
+                    accepted.push(user);
+                }
+                Err(e) => report.skipped.push(format!("{}: {}", u.name, e)),
+            }
+        }
+
+        if dry_run || accepted.is_empty() {
+            return Ok(report);
+        }
+
+        for user in accepted {
+            self.users.insert(user.name.to_owned(), Arc::new(RwLock::new(user)));
+        }
+
+        return match self.write_to_file() {
+            Ok(_) => Ok(report),
+            Err(e) => Err(e),
+        };
+    }
+
+    /// Full user records including password hashes, sorted by name
+    pub fn export(&self) -> Vec<ExportedUser> {
+        let mut users: Vec<ExportedUser> = self
+            .users
+            .values()
+            .map(|u| {
+                let u = u.read().unwrap();
+                ExportedUser {
+                    name: u.name.to_owned(),
+                    hashed_password: u.hashed_password.to_owned(),
+                    paths: u.paths.to_owned(),
+                    acct_type: u.acct_type,
+                    disabled: u.disabled,
+                    expires_at: u.expires_at,
+                    schedule: u.schedule.to_owned(),
+                    must_change_password: u.must_change_password,
+                    password_changed_at: u.password_changed_at,
+                    password_history: u.password_history.to_owned(),
+                    source: u.source,
+                }
+            })
+            .collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        return users;
+    }
+
     /// Checks if username is suitable for use (eg length, invalid chars).
     /// check_existing also checks for collisions against existing names
     pub fn validate_username(&self, name: &String, check_existing: bool) -> Result<()> {
         if check_existing && self.users.contains_key(name) {
-            return crate::err!("User `{}` already exists", name);
+            return Err(Error::Conflict(Message::new("user.exists").field("name").arg("name", name)));
         };
 
         if name.contains(":") {
-            return crate::err!("Username cannot contain `:`");
+            return Err(Error::invalid(Message::new("user.name_colon").field("name")));
+        };
+
+        if name.contains(['\n', '\r']) {
+            return Err(Error::invalid(Message::new("user.name_line_break").field("name")));
         };
 
         if name.len() == 0 {
-            return crate::err!("User name may not be empty");
+            return Err(Error::invalid(Message::new("user.name_empty").field("name")));
         }
 
         if name.len() > 72 {
-            return crate::err!("User name too long (maximum 72 characters)");
+            return Err(Error::invalid(Message::new("user.name_too_long").field("name").arg("max", 72)));
         }
 
         return Ok(());
     }
 
-    pub fn validate_password(plain_password: &String) -> Result<()> {
-        if plain_password.len() < 8 {
-            return crate::err!("Password too short (minimum 8 characters)");
-        }
+    /// Checks a new password against the password policy. previous_hashes
+    /// are hashes of passwords that may not be reused. All failed rules are
+    /// included in the error message.
+    pub fn validate_password(&self, name: &str, plain_password: &str, previous_hashes: &[String]) -> Result<()> {
+        let mut errors = self.policy.violations(name, plain_password);
 
-        // Sounds like a good enough limit
-        if plain_password.len() > 72 {
-            return crate::err!("Password too long (maximum 72 characters)");
+        if previous_hashes.iter().any(|h| hash_matches(h, plain_password)) {
+            errors.push(Message::new("password.reused").arg("count", self.policy.history_size));
         }
 
+        if !errors.is_empty() {
+            return Err(Error::Invalid(errors.into_iter().map(|m| m.field("password")).collect()));
+        }
         return Ok(());
     }
 
-    fn append_to_file(&self, user: &User) -> Result<()> {
+    /// Locks the file for a single write unless it is already held
+    fn lock_for_write(&self) -> Result<Option<FileLock>> {
+        if self.lock.is_some() {
+            return Ok(None);
+        }
+        return lock_file(&self.filepath).map(Some);
+    }
+
+    /// Refuses to write over changes another process made since this db
+    /// last read or wrote the file. The file must be locked. A missing file
+    /// has nothing to lose.
+    fn check_unchanged(&self) -> Result<()> {
+        return match file_state(&self.filepath) {
+            Some(s) if Some(s) != self.file_state => {
+                tracing::warn!("{} was changed by another process since it was loaded", self.filepath);
+                Err(Error::Conflict(Message::new("user.db_changed")))
+            }
+            _ => Ok(()),
+        };
+    }
+
+    fn append_to_file(&mut self, user: &User) -> Result<()> {
+        let _lock = match self.lock_for_write() {
+            Ok(l) => l,
+            Err(e) => return Err(e),
+        };
+        // Appending keeps what others wrote, but this db only knows the whole
+        // file, and may rewrite it later, if nobody else wrote to it
+        let unchanged = self.check_unchanged().is_ok();
+        // write_to_file and hand edits may leave the last line unterminated
+        let separator = match std::fs::read(&self.filepath) {
+            Ok(c) if c.last().is_some_and(|b| *b != b'\n') => "\n",
+            _ => "",
+        };
+
         let mut file = match OpenOptions::new().write(true).append(true).open(self.filepath.as_str()) {
             Ok(f) => f,
             Err(e) => return Err(Error::convert(e)),
         };
 
-        return match writeln!(file, "{}", user.to_line()) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(Error::convert(e)),
-        };
+        match writeln!(file, "{}{}", separator, user.to_line()) {
+            Ok(_) => {}
+            Err(e) => return Err(Error::convert(e)),
+        }
+        if unchanged {
+            self.file_state = file_state(&self.filepath);
+        }
+        return Ok(());
     }
 
     /// Gets a user from the database with a matching name and password
-    /// Should be used to verify a user login
+    /// Should be used to verify a user login, followed by upgrade_hash
     pub fn verify_credentials(&self, name: &String, plain_pass: &String) -> Option<&Arc<RwLock<User>>> {
         let user = match self.users.get(name) {
             Some(u) => u,
-            None => return None,
+            None => return None,
+        };
+
+        if !user.read().unwrap().is_active() {
+            tracing::info!("Rejecting login for disabled or expired account {}", name);
+            return None;
+        }
+
+        let hashed_password = user.read().unwrap().hashed_password.to_owned();
+        if hashed_password == NO_PASSWORD {
+            return None;
+        }
+
+        if htpasswd::is_supported(&hashed_password) {
+            return match htpasswd::verify(&hashed_password, plain_pass) {
+                true => Some(user),
+                false => None,
+            };
+        }
+
+        let is_match = match argon2::verify_encoded(&hashed_password, plain_pass.as_bytes()) {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!("Could not compare password and hash: {}", e);
+                return None;
+            }
+        };
+
+        if !is_match {
+            return None;
+        }
+        return Some(user);
+    }
+
+    /// Replaces an imported htpasswd hash, or an argon2 hash weaker than the
+    /// current config, with a new hash of plain_pass and saves the db.
+    /// plain_pass must already have been checked with verify_credentials.
+    pub fn upgrade_hash(&mut self, user: &Arc<RwLock<User>>, plain_pass: &String) {
+        let mut user_w = user.write().unwrap();
+        let hashed_password = &user_w.hashed_password;
+        if !htpasswd::is_supported(hashed_password) && !is_weaker_hash(hashed_password, &self.argon2) {
+            return;
+        }
+        user_w.hashed_password = hash_password(plain_pass, &self.argon2);
+        let name = user_w.name.to_owned();
+        drop(user_w);
+
+        match self.write_to_file() {
+            Ok(_) => tracing::info!("Upgraded password hash for {}", name),
+            Err(e) => tracing::error!("Unable to save upgraded password hash for {}: {}", name, e),
+        }
+    }
+
+    /// Checks if a user must choose a new password before using their
+    /// session, either because an admin reset it or because it is older than
+    /// the policy's max_age_days. Users without a recorded change time and
+    /// users without a local password never expire.
+    pub fn password_change_required(&self, user: &Arc<RwLock<User>>) -> bool {
+        let user = user.read().unwrap();
+        if user.hashed_password == NO_PASSWORD {
+            return false;
+        }
+        if user.must_change_password {
+            return true;
+        }
+
+        return match (self.policy.max_age_days, user.password_changed_at) {
+            (Some(days), Some(changed)) => {
+                OffsetDateTime::now_utc().unix_timestamp() - changed > days as i64 * 24 * 60 * 60
+            }
+            _ => false,
+        };
+    }
+
+    /// Changes any provided fields of an existing user. A new password is
+    /// validated and hashed before being stored. Does not write to disk.
+    pub fn update_user(
+        &self,
+        name: &String,
+        plain_password: Option<String>,
+        paths: Option<Vec<String>>,
+        acct_type: Option<AcctType>,
+    ) -> Result<&Arc<RwLock<User>>> {
+        let user = match self.users.get(name) {
+            Some(u) => u,
+            None => return Err(Error::NotFound(Message::new("user.not_found").field("name").arg("name", name))),
         };
 
-        let is_match = match argon2::verify_encoded(&user.read().unwrap().hashed_password, plain_pass.as_bytes()) {
-            Ok(result) => result,
-            Err(e) => {
-                tracing::error!("Could not compare password and hash: {}", e);
-                return None;
-            }
-        };
+        let mut user_w = user.write().unwrap();
 
-        if is_match {
-            return Some(user);
-        } else {
-            return None;
-        };
+        let mut previous_hashes: Vec<String> = Vec::new();
+        if self.policy.history_size > 0 {
+            previous_hashes.push(user_w.hashed_password.to_owned());
+            previous_hashes.extend(user_w.password_history.iter().cloned());
+            previous_hashes.retain(|h| h != NO_PASSWORD);
+        }
+
+        let mut hashed_password: Option<String> = None;
+        match plain_password {
+            Some(p) => match self.validate_password(name, &p, &previous_hashes) {
+                Ok(_) => hashed_password = Some(hash_password(p, &self.argon2)),
+                Err(e) => return Err(e),
+            },
+            None => {}
+        }
+
+        let changed_password = hashed_password.is_some();
+        match user_w.update_info(hashed_password, paths, acct_type) {
+            Ok(_) => {}
+            Err(e) => return Err(e),
+        }
+
+        if changed_password {
+            // The current password is always checked, so only older ones
+            // need to be kept
+            previous_hashes.truncate(self.policy.history_size.saturating_sub(1));
+            user_w.password_history = previous_hashes;
+        }
+
+        return Ok(user);
     }
 
     pub fn get(&self, name: &String) -> Option<&Arc<RwLock<User>>> {
@@ -407,14 +1217,59 @@ impl UserDB {
         return usrs;
     }
 
+    /// Replaces the file with the users in this db. Fails if another process
+    /// wrote to the file since this db read it, such as the command line
+    /// tools while the server is running, so their changes aren't lost; the
+    /// server reloads the file shortly after.
     pub fn write_to_file(&mut self) -> Result<()> {
+        let _lock = match self.lock_for_write() {
+            Ok(l) => l,
+            Err(e) => return Err(e),
+        };
+        match self.check_unchanged() {
+            Ok(_) => {}
+            Err(e) => {
+                self.unsaved.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
         let mut lines: Vec<String> = Vec::with_capacity(self.users.len());
 
         for user in self.users.values() {
             lines.push(user.read().unwrap().to_line());
         }
 
-        return make_dirs_and_write(self.filepath.as_str(), lines.join("\n"));
+        let res = make_dirs_and_write(self.filepath.as_str(), lines.join("\n"));
+        self.unsaved.store(res.is_err(), Ordering::Relaxed);
+        if res.is_ok() {
+            self.file_state = file_state(&self.filepath);
+        }
+        return res;
+    }
+
+    /// Writes the db to file after user was changed from previous. If that
+    /// fails, user is put back as it was, so a change the caller reports as
+    /// not saved doesn't stay in use until the next reload.
+    pub fn save_user_change(&mut self, user: &Arc<RwLock<User>>, previous: User) -> Result<()> {
+        let was_unsaved = self.unsaved.load(Ordering::Relaxed);
+        return match self.write_to_file() {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                *user.write().unwrap() = previous;
+                self.unsaved.store(was_unsaved, Ordering::Relaxed);
+                Err(e)
+            }
+        };
+    }
+
+    /// Writes the db to file if the last attempt failed, so changes that
+    /// only exist in memory are not lost when the server stops
+    pub fn save_unsaved(&mut self) -> Result<()> {
+        if !self.unsaved.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        tracing::info!("Saving unsaved changes to {}", self.filepath);
+        return self.write_to_file();
     }
 
     /// Removes user from in-memory database and writes database contents to file
@@ -496,6 +1351,242 @@ mod user_db_tests {
             .is_none());
     }
 
+    #[test]
+    fn external_user() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        assert!(user_db
+            .add_external_user(&NAME.to_string(), &vec!["*".to_string()], AcctType::User, ExternalSource::Oidc)
+            .is_ok());
+        assert!(user_db
+            .add_external_user(&NAME.to_string(), &vec!["*".to_string()], AcctType::User, ExternalSource::Ldap)
+            .is_err());
+
+        assert!(user_db
+            .verify_credentials(&NAME.to_string(), &NO_PASSWORD.to_string())
+            .is_none());
+
+        let user_db = UserDB::new(&tmp).unwrap();
+        let user = user_db.get(&NAME.to_string()).unwrap().read().unwrap();
+        assert!(user.is_owned_by(ExternalSource::Oidc));
+        assert!(!user.is_owned_by(ExternalSource::Ldap));
+    }
+
+    #[test]
+    fn sync_external_user() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        _ = user_db.add_user(
+            &NAME.to_string(),
+            &PASS.to_string(),
+            &vec!["*".to_string()],
+            AcctType::Admin,
+        );
+        assert!(user_db
+            .sync_external_user(&NAME.to_string(), &vec![], AcctType::User, ExternalSource::Ldap)
+            .is_err());
+
+        // Accounts created by the identity provider are not taken over
+        let provisioned = "provisioned_user".to_string();
+        _ = user_db.add_external_user(&provisioned, &vec![], AcctType::User, ExternalSource::Oidc);
+        assert!(user_db
+            .sync_external_user(&provisioned, &vec![], AcctType::Admin, ExternalSource::Ldap)
+            .is_err());
+        assert_eq!(user_db.get(&provisioned).unwrap().read().unwrap().acct_type, AcctType::User);
+
+        let name = "directory_user".to_string();
+        assert!(user_db
+            .sync_external_user(&name, &vec!["/a".to_string()], AcctType::User, ExternalSource::Ldap)
+            .is_ok());
+        assert!(user_db
+            .sync_external_user(&name, &vec!["/b".to_string()], AcctType::Admin, ExternalSource::Ldap)
+            .is_ok());
+
+        let user_db = UserDB::new(&tmp).unwrap();
+        let user = user_db.get(&name).unwrap().read().unwrap();
+        assert_eq!(user.paths, vec!["/b".to_string()]);
+        assert_eq!(user.acct_type, AcctType::Admin);
+    }
+
+    #[test]
+    fn import_htpasswd() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        _ = user_db.add_user(
+            &NAME.to_string(),
+            &PASS.to_string(),
+            &vec!["*".to_string()],
+            AcctType::Admin,
+        );
+
+        let contents = format!(
+            "apr1_user:$apr1$abcdefgh$FBwExRW4dCc8aL.OvjpIE1\nsha_user:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\ncrypt_user:rOHb0pyx4yR6c\n{}:{{SHA}}W6ph5Mm5Pz8GgiULbPgzG37mj9g=\nbad_line",
+            NAME
+        );
+        assert!(user_db
+            .import_htpasswd(&contents, &vec!["[".to_string()], AcctType::User, false)
+            .is_err());
+
+        let report = user_db
+            .import_htpasswd(&contents, &vec!["/app/**".to_string()], AcctType::User, false)
+            .unwrap();
+        assert_eq!(report.added, vec!["apr1_user".to_string(), "sha_user".to_string()]);
+        assert_eq!(report.skipped.len(), 3);
+
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        assert_eq!(user_db.count(), 3);
+        assert!(user_db
+            .verify_credentials(&"apr1_user".to_string(), &"not_a_pass".to_string())
+            .is_none());
+        let user = user_db
+            .verify_credentials(&"apr1_user".to_string(), &"password".to_string())
+            .cloned()
+            .unwrap();
+        user_db.upgrade_hash(&user, &"password".to_string());
+
+        // Hash is upgraded and saved after logging in
+        let user_db = UserDB::new(&tmp).unwrap();
+        let hash = user_db.get(&"apr1_user".to_string()).unwrap().read().unwrap().hashed_password.to_owned();
+        assert!(hash.starts_with("$argon2"));
+        assert!(user_db
+            .verify_credentials(&"apr1_user".to_string(), &"password".to_string())
+            .is_some());
+    }
+
+    #[test]
+    fn export_import() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        _ = user_db.add_user(
+            &NAME.to_string(),
+            &PASS.to_string(),
+            &vec!["*".to_string()],
+            AcctType::Admin,
+        );
+        _ = user_db.add_user(
+            &"another_user".to_string(),
+            &PASS.to_string(),
+            &vec!["/a/**".to_string()],
+            AcctType::User,
+        );
+        {
+            let mut another = user_db.get(&"another_user".to_string()).unwrap().write().unwrap();
+            another.disabled = true;
+            another.expires_at = Some(1700000000);
+            another.schedule = Some("kids".to_string());
+            another.must_change_password = true;
+            another.password_changed_at = Some(1600000000);
+            another.password_history = vec![hash_password("old pass", &Argon2Config::default())];
+        }
+        let exported = user_db.export();
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0].name, NAME.to_string());
+
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        _ = user_db.add_user(
+            &NAME.to_string(),
+            &"a_different_pass".to_string(),
+            &vec!["*".to_string()],
+            AcctType::Admin,
+        );
+
+        let report = user_db.import_users(exported.clone(), true).unwrap();
+        assert!(report.dry_run);
+        assert_eq!(report.added, vec!["another_user".to_string()]);
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(user_db.count(), 1);
+
+        let report = user_db.import_users(exported.clone(), false).unwrap();
+        assert_eq!(report.added, vec!["another_user".to_string()]);
+
+        let user_db = UserDB::new(&tmp).unwrap();
+        assert_eq!(user_db.count(), 2);
+        // Everything about the user survives the trip, through the file too
+        assert_eq!(user_db.export()[1], exported[1]);
+        assert!(hash_matches(&exported[1].hashed_password, PASS));
+        // Still disabled
+        assert!(user_db
+            .verify_credentials(&"another_user".to_string(), &PASS.to_string())
+            .is_none());
+        assert!(user_db
+            .verify_credentials(&NAME.to_string(), &"a_different_pass".to_string())
+            .is_some());
+    }
+
+    #[test]
+    fn import_rejects_injection() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        let hash = hash_password(PASS, &Argon2Config::default());
+        let entry = |name: &str, hashed_password: &str, path: &str| {
+            ExportedUser::new(name.to_string(), hashed_password.to_string(), vec![path.to_string()], AcctType::User)
+        };
+
+        let report = user_db
+            .import_users(
+                vec![
+                    entry("hash_user", &format!("{}\nevil:{}:*:Admin", hash, hash), "/a/**"),
+                    entry("colon_user", &format!("{}:*:Admin", hash), "/a/**"),
+                    entry("name\nevil", &hash, "/a/**"),
+                    entry("path_user", &hash, "/a/**:disabled=false"),
+                    entry("list_user", &hash, "/a/**,*"),
+                    entry("line_user", &hash, "/a/**\revil"),
+                    entry("fake_user", "$argon2id$evil", "/a/**"),
+                    entry("good_user", &hash, "/a/**"),
+                ],
+                false,
+            )
+            .unwrap();
+        assert_eq!(report.added, vec!["good_user".to_string()]);
+        assert_eq!(report.skipped.len(), 7);
+
+        let user_db = UserDB::new(&tmp).unwrap();
+        assert_eq!(user_db.count(), 1);
+        assert!(user_db.get(&"evil".to_string()).is_none());
+        assert!(user_db.verify_credentials(&"good_user".to_string(), &PASS.to_string()).is_some());
+    }
+
+    #[test]
+    fn upgrade_weak_hash() {
+        let tmp = crate::routes::tests::make_test_userdb();
+        let name = "User_user".to_string();
+        let pass = "password".to_string();
+
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        let strong = Argon2Config {
+            mem_cost: 32,
+            time_cost: 3,
+            ..Default::default()
+        };
+        user_db.set_argon2_config(strong.to_owned());
+        let original = user_db.get(&name).unwrap().read().unwrap().hashed_password.to_owned();
+        assert!(is_weaker_hash(&original, &strong));
+
+        let user = user_db.verify_credentials(&name, &pass).cloned().unwrap();
+        // Checking credentials alone never changes the db
+        assert_eq!(user.read().unwrap().hashed_password, original);
+        user_db.upgrade_hash(&user, &pass);
+
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        let upgraded = user_db.get(&name).unwrap().read().unwrap().hashed_password.to_owned();
+        assert!(upgraded.starts_with("$argon2id$v=19$m=32,t=3,p=1$"));
+        assert!(!is_weaker_hash(&upgraded, &strong));
+        assert!(is_weaker_hash(
+            &upgraded,
+            &Argon2Config {
+                lanes: 2,
+                ..strong.to_owned()
+            }
+        ));
+        assert!(user_db.verify_credentials(&name, &pass).is_some());
+
+        // Already as strong as the config, so left alone
+        user_db.set_argon2_config(strong);
+        user_db.upgrade_hash(&user_db.get(&name).cloned().unwrap(), &pass);
+        assert_eq!(user_db.get(&name).unwrap().read().unwrap().hashed_password, upgraded);
+    }
+
     #[test]
     fn invalid_name_pass() {
         let tmp = make_tmp_file();
@@ -530,6 +1621,14 @@ mod user_db_tests {
                 AcctType::Admin
             )
             .is_err());
+        assert!(user_db
+            .add_user(
+                &"test\nuser".to_string(),
+                &PASS.to_string(),
+                &vec!["*".to_string()],
+                AcctType::Admin
+            )
+            .is_err());
         assert!(user_db
             .add_user(
                 &"bad_pass".to_string(),
@@ -631,6 +1730,38 @@ mod user_db_tests {
         assert_eq!(user_db.count(), 0);
     }
 
+    #[test]
+    fn strict_and_diff() {
+        let tmp = make_tmp_file();
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(tmp.to_owned());
+
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        user_db.add_user(&"Bob".to_string(), &"mashed potatoes".to_string(), &vec!["*".to_string()], AcctType::User).unwrap();
+        user_db.add_user(&"Carl".to_string(), &"mashed potatoes".to_string(), &vec!["*".to_string()], AcctType::User).unwrap();
+        let line = user_db.get(&"Bob".to_string()).unwrap().read().unwrap().to_line();
+
+        for bad in ["username:password", "username:password:*:Null", &format!("{}\n{}", line, line)] {
+            _ = fs::write(&tmp, bad);
+            assert!(UserDB::from_config_strict(&cfg).is_err());
+        }
+        _ = fs::remove_file(&tmp);
+        assert!(UserDB::from_config_strict(&cfg).is_err());
+        assert!(!file_exists(&tmp));
+
+        _ = fs::write(&tmp, format!("{}\n\n{}", line.replace(":User", ":Admin"), "Dan::*:User"));
+        let reloaded = UserDB::from_config_strict(&cfg).unwrap();
+        assert_eq!(
+            user_db.diff(&reloaded),
+            vec![
+                "changed type of user `Bob`".to_string(),
+                "removed user `Carl`".to_string(),
+                "added user `Dan`".to_string()
+            ]
+        );
+        assert!(reloaded.diff(&reloaded).is_empty());
+    }
+
     #[test]
     fn list_safe() {
         let tmp = make_tmp_file();
@@ -668,6 +1799,102 @@ mod user_db_tests {
         let user_db = UserDB::new(&tmp).unwrap();
         assert_eq!(user_db.count(), 0);
     }
+
+    #[test]
+    fn save_unsaved() {
+        let tmp = make_tmp_file();
+        _ = fs::write(&tmp, "username:password:*:Admin\nother:password:*:User");
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        assert!(user_db.save_unsaved().is_ok());
+
+        // Nothing can be written while a directory is in the way
+        fs::remove_file(&tmp).unwrap();
+        fs::create_dir(&tmp).unwrap();
+        assert!(user_db.remove(&"username".to_string()).is_err());
+        assert!(user_db.save_unsaved().is_err());
+
+        fs::remove_dir(&tmp).unwrap();
+        assert!(user_db.save_unsaved().is_ok());
+        assert_eq!(UserDB::new(&tmp).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn changed_before_update() {
+        let tmp = make_tmp_file();
+        _ = fs::write(&tmp, "username:password:*:Admin\nother:password:*:User");
+        let mut user_db = UserDB::new(&tmp).unwrap();
+
+        // Another process writes to the file after it was loaded
+        _ = fs::write(&tmp, "username:password:*:Admin\nother:password:/other/**:User\n");
+
+        let name = "other".to_string();
+        let previous = user_db.get(&name).unwrap().read().unwrap().clone();
+        let user = user_db
+            .update_user(&name, None, Some(vec!["/media/**".to_string()]), Some(AcctType::Admin))
+            .unwrap()
+            .clone();
+        user.write().unwrap().set_disabled(true);
+        assert!(matches!(user_db.save_user_change(&user, previous), Err(Error::Conflict(_))));
+
+        // The change is neither kept in memory nor retried at shutdown
+        let user = user.read().unwrap();
+        assert_eq!(user.paths, vec!["*".to_string()]);
+        assert_eq!(user.acct_type, AcctType::User);
+        assert!(!user.disabled);
+        assert!(user.path_allowed("/books"));
+        assert!(user_db.save_unsaved().is_ok());
+        assert!(fs::read_to_string(&tmp).unwrap().contains("other:password:/other/**:User"));
+    }
+
+    #[test]
+    fn append_after_unterminated_line() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        assert!(user_db
+            .add_user(&"first".to_string(), &"password".to_string(), &vec![], AcctType::User)
+            .is_ok());
+        // write_to_file doesn't end the last line, and neither do some editors
+        assert!(user_db.write_to_file().is_ok());
+        assert!(!fs::read_to_string(&tmp).unwrap().ends_with('\n'));
+
+        assert!(user_db
+            .add_user(&"second".to_string(), &"password".to_string(), &vec![], AcctType::User)
+            .is_ok());
+        let reloaded = UserDB::new(&tmp).unwrap();
+        assert!(reloaded.get(&"first".to_string()).is_some());
+        assert!(reloaded.get(&"second".to_string()).is_some());
+    }
+
+    #[test]
+    fn stale_write() {
+        let tmp = make_tmp_file();
+        _ = fs::write(&tmp, "username:password:*:Admin\nother:password:*:User");
+        let mut server_db = UserDB::new(&tmp).unwrap();
+        let mut cli_db = UserDB::new(&tmp).unwrap();
+
+        // Appending can't lose anything
+        assert!(cli_db
+            .add_user(&"new_user".to_string(), &"password".to_string(), &vec![], AcctType::User)
+            .is_ok());
+        assert!(server_db
+            .add_user(&"server_user".to_string(), &"password".to_string(), &vec![], AcctType::User)
+            .is_ok());
+        assert_eq!(UserDB::new(&tmp).unwrap().count(), 4);
+
+        // But rewriting the file from a stale copy would
+        assert!(matches!(server_db.remove(&"other".to_string()), Err(Error::Conflict(_))));
+        assert!(matches!(cli_db.remove(&"other".to_string()), Err(Error::Conflict(_))));
+        assert_eq!(UserDB::new(&tmp).unwrap().count(), 4);
+
+        // A fresh copy can, and keeps writing after its own changes
+        let mut reloaded = UserDB::new(&tmp).unwrap();
+        assert!(reloaded.remove(&"other".to_string()).is_ok());
+        assert!(reloaded
+            .add_user(&"another".to_string(), &"password".to_string(), &vec![], AcctType::User)
+            .is_ok());
+        assert!(reloaded.remove(&"new_user".to_string()).is_ok());
+        assert_eq!(UserDB::new(&tmp).unwrap().count(), 3);
+    }
 }
 
 #[cfg(test)]
@@ -715,15 +1942,16 @@ mod user_tests {
         );
         assert!(added.is_ok());
 
-        let mut user_w = user_db.get(&NAME.to_string()).unwrap().write().unwrap();
         let replacement_paths = vec![String::from("foo"), String::from("bar")];
-        let res = user_w.update_info(
+        let res = user_db.update_user(
+            &NAME.to_string(),
             Some("a_new_password".to_string()),
             Some(replacement_paths.to_owned()),
             Some(AcctType::User),
         );
         assert!(res.is_ok());
 
+        let user_w = user_db.get(&NAME.to_string()).unwrap().read().unwrap();
         assert_eq!(user_w.name, NAME.to_string());
         assert_eq!(user_w.paths, replacement_paths);
         assert_eq!(user_w.acct_type, AcctType::User);
@@ -749,6 +1977,154 @@ mod user_tests {
         assert_eq!(user_db.count(), 1);
         let usr = user_db.get(&"name".to_string()).unwrap().read().unwrap();
         assert_eq!(usr.to_line(), line);
+
+        let line = "name:pw:*:User:history=old1;old2".to_string();
+        _ = fs::write(&tmp, &line);
+        let user_db = UserDB::new(&tmp).unwrap();
+        let usr = user_db.get(&"name".to_string()).unwrap().read().unwrap();
+        assert_eq!(usr.password_history, vec!["old1".to_string(), "old2".to_string()]);
+        assert_eq!(usr.to_line(), line);
+
+        let line = "name:!:*:Admin:source=ldap".to_string();
+        _ = fs::write(&tmp, &line);
+        let user_db = UserDB::new(&tmp).unwrap();
+        let usr = user_db.get(&"name".to_string()).unwrap().read().unwrap();
+        assert!(usr.is_owned_by(ExternalSource::Ldap));
+        assert_eq!(usr.to_line(), line);
+    }
+
+    #[test]
+    fn disabled_and_expired() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        _ = user_db.add_user(&NAME.to_string(), &PASS.to_string(), &vec![], AcctType::User);
+        let user = user_db.get(&NAME.to_string()).unwrap().clone();
+        assert!(user_db.verify_credentials(&NAME.to_string(), &PASS.to_string()).is_some());
+
+        user.write().unwrap().set_disabled(true);
+        assert!(user_db.verify_credentials(&NAME.to_string(), &PASS.to_string()).is_none());
+
+        user.write().unwrap().set_disabled(false);
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        user.write().unwrap().set_expires_at(Some(now + 60));
+        assert!(user_db.verify_credentials(&NAME.to_string(), &PASS.to_string()).is_some());
+        user.write().unwrap().set_expires_at(Some(now - 60));
+        assert!(user_db.verify_credentials(&NAME.to_string(), &PASS.to_string()).is_none());
+
+        user.write().unwrap().set_disabled(true);
+        _ = user_db.write_to_file();
+        let user_db = UserDB::new(&tmp).unwrap();
+        let reloaded = user_db.get(&NAME.to_string()).unwrap().read().unwrap();
+        assert!(reloaded.disabled);
+        assert_eq!(reloaded.expires_at, Some(now - 60));
+
+        // External users are checked too
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        let ext = user_db
+            .add_external_user(&"ext".to_string(), &vec![], AcctType::User, ExternalSource::Ldap)
+            .unwrap()
+            .clone();
+        ext.write().unwrap().set_disabled(true);
+        assert!(user_db
+            .sync_external_user(&"ext".to_string(), &vec![], AcctType::User, ExternalSource::Ldap)
+            .is_err());
+    }
+
+    #[test]
+    fn password_change_required() {
+        let tmp = make_tmp_file();
+        _ = fs::write(
+            &tmp,
+            "reset:pw:*:User:must_change_password=true\nold:pw:*:User:password_changed_at=0\nunknown:pw:*:User",
+        );
+        let mut user_db = UserDB::new(&tmp).unwrap();
+
+        let reset = user_db.get(&"reset".to_string()).unwrap().clone();
+        let old = user_db.get(&"old".to_string()).unwrap().clone();
+        let unknown = user_db.get(&"unknown".to_string()).unwrap().clone();
+        assert!(user_db.password_change_required(&reset));
+        assert!(!user_db.password_change_required(&old));
+        assert!(!user_db.password_change_required(&unknown));
+
+        user_db.set_password_policy(PasswordPolicy {
+            max_age_days: Some(90),
+            ..Default::default()
+        });
+        assert!(user_db.password_change_required(&old));
+        assert!(!user_db.password_change_required(&unknown));
+
+        // Choosing a new password clears both
+        for name in ["reset", "old"] {
+            assert!(user_db
+                .update_user(&name.to_string(), Some("brand new pass".to_string()), None, None)
+                .is_ok());
+        }
+        assert!(!user_db.password_change_required(&reset));
+        assert!(!user_db.password_change_required(&old));
+
+        _ = user_db.write_to_file();
+        assert!(!fs::read_to_string(&tmp).unwrap().contains("must_change_password"));
+    }
+
+    #[test]
+    fn password_policy() {
+        let tmp = make_tmp_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        user_db.set_password_policy(PasswordPolicy {
+            require_digit: true,
+            reject_username: true,
+            history_size: 2,
+            ..Default::default()
+        });
+
+        let err = user_db
+            .add_user(&NAME.to_string(), &"testuser".to_string(), &vec![], AcctType::User)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Password must contain a number; Password may not contain the user name"
+        );
+
+        let passwords = ["first pass 1", "second pass 2", "third pass 3"];
+        assert!(user_db
+            .add_user(&NAME.to_string(), &passwords[0].to_string(), &vec![], AcctType::User)
+            .is_ok());
+
+        // Current password may not be reused
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[0].to_string()), None, None).is_err());
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[1].to_string()), None, None).is_ok());
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[0].to_string()), None, None).is_err());
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[2].to_string()), None, None).is_ok());
+
+        // Only the last 2 passwords are remembered
+        _ = user_db.write_to_file();
+        let mut user_db = UserDB::new(&tmp).unwrap();
+        user_db.set_password_policy(PasswordPolicy {
+            history_size: 2,
+            ..Default::default()
+        });
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[1].to_string()), None, None).is_err());
+        assert!(user_db.update_user(&NAME.to_string(), Some(passwords[0].to_string()), None, None).is_ok());
+    }
+
+    #[test]
+    fn locked_db() {
+        let mut cfg = UserConfig::default();
+        cfg.user_db = Some(make_tmp_file());
+        let lock_path = format!("{}.lock", cfg.user_db.as_ref().unwrap());
+
+        let mut user_db = UserDB::from_config_locked(&cfg).unwrap();
+        let other = std::fs::File::open(&lock_path).unwrap();
+        assert!(other.try_lock().is_err());
+
+        // Writing doesn't wait on the lock it already holds
+        assert!(user_db
+            .add_user(&NAME.to_string(), &PASS.to_string(), &vec![], AcctType::User)
+            .is_ok());
+        assert!(other.try_lock().is_err());
+
+        drop(user_db);
+        assert!(other.try_lock().is_ok());
     }
 
     #[test]
@@ -0,0 +1,11 @@
+// Translates code with the page's MESSAGES catalog, filling in {name}
+// placeholders from args. Unknown codes are returned as they are.
+function t(code, args) {
+    let text = MESSAGES[code] === undefined ? code : MESSAGES[code];
+    if (args !== undefined) {
+        for (const [name, value] of Object.entries(args)) {
+            text = text.replaceAll(`{${name}}`, value);
+        }
+    }
+    return text;
+}